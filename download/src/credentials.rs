@@ -0,0 +1,70 @@
+//! Credentials for hosts that need them, fetched when first needed
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::*;
+
+/// Somewhere to find the credentials for a host, such as a netrc file or
+/// a program that hands out short-lived tokens.
+pub trait CredentialProvider: Send + Sync {
+    /// The value of the `Authorization` header for requests to the host,
+    /// if it needs one. Errors must not include the credentials.
+    fn authorization(&self) -> Result<Option<String>>;
+}
+
+/// The credentials for a host, fetched from a `CredentialProvider` the
+/// first time they're needed and again if the host rejects them. Clones
+/// share them, so concurrent downloads fetch them only once.
+#[derive(Clone)]
+pub struct Credentials {
+    provider: Arc<dyn CredentialProvider>,
+    // `None` until fetched, as the host may need no credentials
+    fetched: Arc<Mutex<Option<Option<String>>>>,
+}
+
+impl Credentials {
+    pub fn new(provider: impl CredentialProvider + 'static) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            fetched: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // The `Authorization` header value, fetching it if it hasn't been yet.
+    pub(crate) fn authorization(&self) -> Result<Option<String>> {
+        let mut fetched = self.fetched.lock().unwrap();
+        if let Some(authorization) = &*fetched {
+            return Ok(authorization.clone());
+        }
+        let authorization = self.provider.authorization()?;
+        *fetched = Some(authorization.clone());
+        Ok(authorization)
+    }
+
+    // Fetches the credentials again after the host rejected `rejected`,
+    // unless another download already has since.
+    pub(crate) fn refresh(&self, rejected: Option<&str>) -> Result<Option<String>> {
+        let mut fetched = self.fetched.lock().unwrap();
+        if let Some(authorization) = &*fetched {
+            if authorization.as_deref() != rejected {
+                return Ok(authorization.clone());
+            }
+        }
+        let authorization = self.provider.authorization()?;
+        *fetched = Some(authorization.clone());
+        Ok(authorization)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Credentials(..)")
+    }
+}
+
+impl PartialEq for Credentials {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fetched, &other.fetched)
+    }
+}
@@ -0,0 +1,451 @@
+//! Downloading files as configured once, reusing connections
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::fs::{self, remove_file, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use url::Url;
+
+use crate::errors::*;
+use crate::timeouts::StallDetector;
+use crate::{curl, reqwest_be};
+use crate::{
+    Backend, CachedVersion, Event, HostConfig, HostConfigs, Options, ProxyConfig, Throttle,
+    Timeouts,
+};
+
+// As many as reqwest follows by default
+const MAX_REDIRECTS: usize = 10;
+
+/// Downloads files as it's been set up to, keeping the connections it
+/// opens to reuse for later downloads.
+///
+/// ```no_run
+/// # fn main() -> download::Result<()> {
+/// use std::path::Path;
+///
+/// use download::{Backend, Downloader, TlsBackend};
+///
+/// let downloader = Downloader::new(Backend::Reqwest(TlsBackend::Rustls))
+///     .header("User-Agent", "my-tool/1.0")
+///     .concurrency(4);
+/// let url = "https://static.rust-lang.org/rustup/release-stable.toml".parse().unwrap();
+/// downloader.download_to_path(&url, Path::new("release-stable.toml"), false, None)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Downloader {
+    backend: Backend,
+    hosts: HostConfigs,
+    headers: Vec<(String, String)>,
+    throttle: Option<Throttle>,
+    timeouts: Timeouts,
+    proxy: Option<ProxyConfig>,
+    cached: Option<CachedVersion>,
+    concurrency: usize,
+    handles: curl::Handles,
+    clients: reqwest_be::Clients,
+}
+
+/// A file for `Downloader::download_all` to fetch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fetch {
+    pub url: Url,
+    pub path: PathBuf,
+    /// Whether to carry on from a partial download already at `path`
+    pub resume_from_partial: bool,
+}
+
+impl Downloader {
+    /// A downloader that makes requests with `backend`, and otherwise as
+    /// the defaults are, fetching one file at a time.
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            hosts: HostConfigs::new(),
+            headers: Vec::new(),
+            throttle: None,
+            timeouts: Timeouts::default(),
+            proxy: None,
+            cached: None,
+            concurrency: 1,
+            handles: curl::Handles::pool(),
+            clients: reqwest_be::Clients::own(),
+        }
+    }
+
+    // A downloader set up as `options` say for downloading `url`, which
+    // shares connections with other downloads rather than keeping its own.
+    pub(crate) fn for_options(backend: Backend, url: &Url, options: &Options<'_>) -> Self {
+        let mut hosts = HostConfigs::new();
+        if let (Some(host), Some(config)) = (url.host_str(), options.host) {
+            hosts.insert(host, config.clone());
+        }
+        Self {
+            hosts,
+            throttle: options.throttle.cloned(),
+            timeouts: options.timeouts,
+            proxy: options.proxy.cloned(),
+            cached: options.cached.cloned(),
+            handles: curl::Handles::thread_local(),
+            clients: reqwest_be::Clients::shared(),
+            ..Self::new(backend)
+        }
+    }
+
+    /// Makes requests to hosts that need more than the defaults as `hosts`
+    /// say.
+    pub fn hosts(mut self, hosts: HostConfigs) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    /// Adds a header to every request, unless the host's `HostConfig` has
+    /// one of the same name.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Keeps to `throttle`'s cap, between every download it's shared by.
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Goes through the proxies `proxy` says, rather than those the
+    /// environment says.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Fetches up to `concurrency` files at once in `download_all`.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Downloads `url`, starting `resume_from` bytes in, handing the data
+    /// to `callback` as it arrives rather than saving it anywhere.
+    pub fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        callback: &dyn Fn(Event<'_>) -> Result<()>,
+    ) -> Result<()> {
+        self.download_from(url, resume_from, None, callback)
+    }
+
+    /// Asks the server for the size of the file at `url` without
+    /// downloading it. Servers need not say, in which case this returns
+    /// `None`.
+    pub fn content_length(&self, url: &Url) -> Result<Option<u64>> {
+        self.following_redirects(url, &|url, options| match self.backend {
+            Backend::Curl => curl::content_length(url, options, &self.handles),
+            Backend::Reqwest(tls) => reqwest_be::content_length(url, options, tls, &self.clients),
+        })
+    }
+
+    /// Downloads `url` to `path`, carrying on from a partial download
+    /// already there if `resume_from_partial`, as long as the file hasn't
+    /// changed since. `callback`, if given, is told of the data already
+    /// there as well as that received.
+    pub fn download_to_path(
+        &self,
+        url: &Url,
+        path: &Path,
+        resume_from_partial: bool,
+        callback: Option<&dyn Fn(Event<'_>) -> Result<()>>,
+    ) -> Result<()> {
+        // What the partial download is of, kept next to it in case it's
+        // interrupted
+        let mut validator_path = path.as_os_str().to_owned();
+        validator_path.push(".validator");
+        let validator_path = Path::new(&validator_path);
+
+        let restarted = Cell::new(false);
+        || -> Result<()> {
+            let (file, resume_from) = if resume_from_partial {
+                let possible_partial = OpenOptions::new().read(true).open(&path);
+
+                let downloaded_so_far = if let Ok(mut partial) = possible_partial {
+                    if let Some(cb) = callback {
+                        cb(Event::ResumingPartialDownload)?;
+
+                        let mut buf = vec![0; 32768];
+                        let mut downloaded_so_far = 0;
+                        loop {
+                            let n = partial.read(&mut buf)?;
+                            downloaded_so_far += n as u64;
+                            if n == 0 {
+                                break;
+                            }
+                            cb(Event::DownloadDataReceived(&buf[..n]))?;
+                        }
+
+                        downloaded_so_far
+                    } else {
+                        let file_info = partial.metadata()?;
+                        file_info.len()
+                    }
+                } else {
+                    0
+                };
+
+                let mut possible_partial = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .open(&path)
+                    .chain_err(|| "error opening file for download")?;
+
+                possible_partial.seek(SeekFrom::End(0))?;
+
+                (possible_partial, downloaded_so_far)
+            } else {
+                (
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .open(&path)
+                        .chain_err(|| "error creating file for download")?,
+                    0,
+                )
+            };
+
+            // Without a partial download to resume, an old validator is of
+            // no use. Without a validator, the server is trusted to be
+            // sending the rest of the same file.
+            let if_range = if resume_from > 0 {
+                fs::read_to_string(validator_path).ok()
+            } else {
+                let _ = remove_file(validator_path);
+                None
+            };
+
+            let file = RefCell::new(file);
+            let save = |event: Event<'_>| {
+                match event {
+                    Event::DownloadDataReceived(data) => {
+                        file.borrow_mut()
+                            .write_all(data)
+                            .chain_err(|| "unable to write download to disk")?;
+                    }
+                    Event::DownloadValidatorReceived(validator) => {
+                        fs::write(validator_path, validator)
+                            .chain_err(|| "unable to write download validator to disk")?;
+                    }
+                    Event::DownloadRestarted => {
+                        let mut file = file.borrow_mut();
+                        file.set_len(0)
+                            .chain_err(|| "unable to restart download on disk")?;
+                        file.seek(SeekFrom::Start(0))?;
+                    }
+                    _ => {}
+                }
+                match callback {
+                    Some(cb) => cb(event),
+                    None => Ok(()),
+                }
+            };
+
+            match self.download_from(url, resume_from, if_range.as_deref(), &save) {
+                // The partial download is already as long as the file, or
+                // longer, so isn't of this file
+                Err(ref e) if resume_from > 0 && matches!(e.kind(), ErrorKind::HttpStatus(416)) => {
+                    restarted.set(true);
+                    save(Event::DownloadRestarted)?;
+                    self.download_from(url, 0, None, &save)?;
+                }
+                result => result?,
+            }
+
+            file.borrow_mut()
+                .sync_data()
+                .chain_err(|| "unable to sync download to disk")?;
+            let _ = remove_file(validator_path);
+
+            Ok(())
+        }()
+        .or_else(|e| {
+            // A download that broke off, or stalled, is kept to resume
+            // later. One that was cancelled, or had to start over and
+            // failed anyway, isn't worth keeping.
+            if restarted.get() || matches!(e.kind(), ErrorKind::Cancelled) {
+                if path.exists() {
+                    remove_file(path).chain_err(|| "cleaning up cached downloads")?;
+                }
+                let _ = remove_file(validator_path);
+            }
+            Err(e)
+        })
+    }
+
+    /// Fetches each of `fetches` as `download_to_path` does, up to
+    /// `concurrency` at once, telling `callback` what happens with each
+    /// along with its index in `fetches`. Returns how each went, in the
+    /// same order.
+    ///
+    /// Once `cancel` is set, fetches not yet started aren't made, and
+    /// those underway fail with `ErrorKind::Cancelled` as soon as they
+    /// next receive anything.
+    pub fn download_all(
+        &self,
+        fetches: &[Fetch],
+        cancel: &AtomicBool,
+        callback: &(dyn Fn(usize, Event<'_>) -> Result<()> + Sync),
+    ) -> Vec<Result<()>> {
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<Result<()>>>> =
+            fetches.iter().map(|_| Mutex::new(None)).collect();
+        let fetch_next = || {
+            let mut index = next.fetch_add(1, Ordering::SeqCst);
+            while let Some(fetch) = fetches.get(index) {
+                let result = if cancel.load(Ordering::SeqCst) {
+                    Err(ErrorKind::Cancelled.into())
+                } else {
+                    self.download_to_path(
+                        &fetch.url,
+                        &fetch.path,
+                        fetch.resume_from_partial,
+                        Some(&|event| {
+                            if cancel.load(Ordering::SeqCst) {
+                                return Err(ErrorKind::Cancelled.into());
+                            }
+                            callback(index, event)
+                        }),
+                    )
+                };
+                *results[index].lock().unwrap() = Some(result);
+                index = next.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+
+        let workers = self.concurrency.min(fetches.len());
+        crossbeam_utils::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|_| fetch_next());
+            }
+        })
+        // A worker only panics if a callback did
+        .unwrap_or_else(|e| std::panic::resume_unwind(e));
+
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
+    }
+
+    // Downloads as `download` does, resuming only if the file is still the
+    // version `if_range` identifies, if given.
+    fn download_from(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+        callback: &dyn Fn(Event<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let stall = StallDetector::new(self.timeouts.stall);
+        // Holding up the backend between chunks of data holds up the
+        // connection, and so the server
+        let throttled_callback = |event: Event<'_>| {
+            callback(event)?;
+            if let Event::DownloadDataReceived(data) = event {
+                stall.received(data.len())?;
+                if let Some(throttle) = &self.throttle {
+                    let wait = throttle.received(data.len());
+                    if wait > Duration::from_secs(0) {
+                        callback(Event::DownloadThrottled(throttle.rate()))?;
+                        thread::sleep(wait);
+                        stall.paused(wait);
+                    }
+                }
+            }
+            Ok(())
+        };
+        let callback: &dyn Fn(Event<'_>) -> Result<()> = &throttled_callback;
+        self.following_redirects(url, &|url, options| match self.backend {
+            Backend::Curl => {
+                curl::download(url, resume_from, if_range, options, callback, &self.handles)
+            }
+            Backend::Reqwest(tls) => reqwest_be::download(
+                url,
+                resume_from,
+                if_range,
+                options,
+                callback,
+                tls,
+                &self.clients,
+            ),
+        })
+    }
+
+    // Makes `request` to `url`, and to wherever the server redirects it,
+    // each with the options for its own host, so that the headers and
+    // credentials for one host aren't sent to another.
+    fn following_redirects<T>(
+        &self,
+        url: &Url,
+        request: &dyn Fn(&Url, &Options<'_>) -> Result<T>,
+    ) -> Result<T> {
+        let mut url = Cow::Borrowed(url);
+        for _ in 0..=MAX_REDIRECTS {
+            let host = self.host_config(&url);
+            let options = self.options(host.as_deref());
+            let result = crate::with_credentials(&options, &|options| request(&url, options));
+            let location = match &result {
+                Err(e) => match e.kind() {
+                    ErrorKind::Redirected(location) => location.clone(),
+                    _ => return result,
+                },
+                Ok(_) => return result,
+            };
+            url = Cow::Owned(location);
+        }
+        Err(ErrorKind::TooManyRedirects.into())
+    }
+
+    // How to make requests to `url`'s host: as its `HostConfig` says, with
+    // the headers for every host added.
+    fn host_config(&self, url: &Url) -> Option<Cow<'_, HostConfig>> {
+        let host = self.hosts.for_url(url);
+        if self.headers.is_empty() {
+            return host.map(Cow::Borrowed);
+        }
+        let mut config = host.cloned().unwrap_or_default();
+        let headers = self
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                !config
+                    .headers
+                    .iter()
+                    .any(|(host_name, _)| host_name.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        config.headers.splice(0..0, headers);
+        Some(Cow::Owned(config))
+    }
+
+    fn options<'a>(&'a self, host: Option<&'a HostConfig>) -> Options<'a> {
+        Options {
+            host,
+            throttle: self.throttle.as_ref(),
+            timeouts: self.timeouts,
+            proxy: self.proxy.as_ref(),
+            cached: self.cached.as_ref(),
+        }
+    }
+}
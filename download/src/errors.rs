@@ -14,9 +14,57 @@ error_chain! {
             description("http request returned an unsuccessful status code")
             display("http request returned an unsuccessful status code: {}", e)
         }
+        RetryAfter(delay: std::time::Duration) {
+            description("server asked for the request to be retried later")
+            display("server asked for the request to be retried after {} seconds", delay.as_secs())
+        }
         FileNotFound {
             description("file not found")
         }
+        Redirected(url: url::Url) {
+            description("server redirected the request")
+            display("server redirected the request to '{}'", url)
+        }
+        TooManyRedirects {
+            description("server redirected the request too many times")
+        }
+        UnexpectedContentRange(range: String, resume_from: u64) {
+            description("server sent the wrong part of the file")
+            display("server sent the range '{}' of the file, not the rest of it from byte {}", range, resume_from)
+        }
+        TimedOut {
+            description("timed out waiting on the server")
+        }
+        ConnectionFailed {
+            description("connection to the server failed or was dropped")
+        }
+        Cancelled {
+            description("download cancelled")
+        }
+        NotModified {
+            description("file not modified since the cached version")
+        }
+        Stalled(rate: u64, stall: crate::Stall) {
+            description("download stalled")
+            display(
+                "download stalled: received {} bytes/s over {} seconds, less than the minimum of {} bytes/s",
+                rate,
+                stall.window.as_secs(),
+                stall.min_rate
+            )
+        }
+        ReadingTlsFile(path: std::path::PathBuf) {
+            description("could not read certificate or key")
+            display("could not read certificate or key '{}'", path.display())
+        }
+        NoCertificates(path: std::path::PathBuf) {
+            description("CA bundle has no certificates")
+            display("CA bundle '{}' has no certificates", path.display())
+        }
+        ClientCertificateFormat(path: std::path::PathBuf, tls: &'static str, format: &'static str) {
+            description("client certificate is in a format the TLS backend can't use")
+            display("client certificate '{}' must be {} for {}", path.display(), format, tls)
+        }
         BackendUnavailable(be: &'static str) {
             description("download backend unavailable")
             display("download backend '{}' unavailable", be)
@@ -0,0 +1,170 @@
+//! Settings for requests to particular hosts
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::credentials::Credentials;
+use crate::errors::*;
+
+/// How to make requests to a host, beyond what's done for every host:
+/// which certificate authorities to trust, which certificate to identify
+/// ourselves with, and what headers and credentials to add.
+#[derive(Clone, Default, PartialEq)]
+pub struct HostConfig {
+    /// A PEM file of the certificate authorities to trust for the host,
+    /// in place of the system's
+    pub ca_bundle: Option<PathBuf>,
+    /// A certificate to identify ourselves to the host with, for mutual
+    /// TLS. Either a PEM file, or a PKCS#12 archive without a password.
+    /// curl takes either, reqwest with rustls only PEM, and reqwest with
+    /// the default TLS only PKCS#12.
+    pub client_cert: Option<PathBuf>,
+    /// The PEM file of `client_cert`'s private key, if `client_cert`
+    /// doesn't have it
+    pub client_key: Option<PathBuf>,
+    /// Headers to add to every request to the host, such as
+    /// `Authorization`
+    pub headers: Vec<(String, String)>,
+    /// Where to find the `Authorization` header for requests to the host,
+    /// in place of any in `headers`
+    pub credentials: Option<Credentials>,
+}
+
+impl HostConfig {
+    /// Whether requests to the host need TLS set up differently.
+    pub fn has_tls_settings(&self) -> bool {
+        self.ca_bundle.is_some() || self.client_cert.is_some()
+    }
+
+    // This config with the `Authorization` header set to `authorization`,
+    // if there is one.
+    pub(crate) fn authorized(&self, authorization: Option<&str>) -> Self {
+        let mut config = self.clone();
+        if let Some(authorization) = authorization {
+            config
+                .headers
+                .retain(|(name, _)| !name.eq_ignore_ascii_case("authorization"));
+            config
+                .headers
+                .push(("Authorization".to_owned(), authorization.to_owned()));
+        }
+        config
+    }
+}
+
+// Header values are left out, as they may well be credentials
+impl fmt::Debug for HostConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: Vec<_> = self.headers.iter().map(|(name, _)| name).collect();
+        f.debug_struct("HostConfig")
+            .field("ca_bundle", &self.ca_bundle)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("headers", &headers)
+            .field("credentials", &self.credentials)
+            .finish()
+    }
+}
+
+/// `HostConfig`s by the name of the host they're for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostConfigs {
+    hosts: BTreeMap<String, HostConfig>,
+}
+
+impl HostConfigs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how to make requests to `host`, such as `static.rust-lang.org`.
+    pub fn insert(&mut self, host: &str, config: HostConfig) {
+        self.hosts.insert(host.to_ascii_lowercase(), config);
+    }
+
+    /// How to make requests to `host`, to be set up in place.
+    pub fn entry(&mut self, host: &str) -> &mut HostConfig {
+        self.hosts.entry(host.to_ascii_lowercase()).or_default()
+    }
+
+    /// How to make requests for `url`, if its host has been configured.
+    pub fn for_url(&self, url: &Url) -> Option<&HostConfig> {
+        url.host_str()
+            .and_then(|host| self.hosts.get(&host.to_ascii_lowercase()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+// Reads a certificate or key named in a `HostConfig`.
+pub(crate) fn read_tls_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).chain_err(|| ErrorKind::ReadingTlsFile(path.to_owned()))
+}
+
+// Whether a certificate file is PEM, rather than the DER of a PKCS#12
+// archive.
+pub(crate) fn is_pem(data: &[u8]) -> bool {
+    data.windows(b"-----BEGIN ".len())
+        .any(|w| w == b"-----BEGIN ")
+}
+
+// Each certificate in a PEM file, such as a CA bundle, which may have
+// comments between them.
+#[cfg_attr(not(feature = "reqwest-backend"), allow(dead_code))]
+pub(crate) fn pem_certificates(data: &[u8]) -> Vec<&[u8]> {
+    const BEGIN: &[u8] = b"-----BEGIN CERTIFICATE-----";
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+
+    let find = |data: &[u8], needle: &[u8]| data.windows(needle.len()).position(|w| w == needle);
+    let mut certificates = Vec::new();
+    let mut rest = data;
+    while let Some(begin) = find(rest, BEGIN) {
+        let end = match find(&rest[begin..], END) {
+            Some(end) => begin + end + END.len(),
+            None => break,
+        };
+        certificates.push(&rest[begin..end]);
+        rest = &rest[end..];
+    }
+    certificates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_configs_match_case_insensitively() {
+        let config = HostConfig {
+            headers: vec![("Authorization".to_owned(), "Bearer t".to_owned())],
+            ..HostConfig::default()
+        };
+        let mut hosts = HostConfigs::new();
+        hosts.insert("Mirror.Example.com", config.clone());
+
+        let url = Url::parse("https://mirror.example.COM:8443/dist/x").unwrap();
+        assert_eq!(hosts.for_url(&url), Some(&config));
+        let url = Url::parse("https://static.rust-lang.org/dist/x").unwrap();
+        assert_eq!(hosts.for_url(&url), None);
+    }
+
+    #[test]
+    fn bundles_are_split_into_certificates() {
+        let bundle = b"# Private CA\n\
+            -----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\
+            # Another\n\
+            -----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+        let certificates = pem_certificates(bundle);
+        assert_eq!(certificates.len(), 2);
+        assert!(certificates[1].starts_with(b"-----BEGIN CERTIFICATE-----\nBBBB"));
+        assert!(certificates[1].ends_with(b"-----END CERTIFICATE-----"));
+        assert!(is_pem(bundle));
+        assert!(!is_pem(&[0x30, 0x82, 0x0a, 0x01]));
+    }
+}
@@ -7,37 +7,221 @@ use url::Url;
 mod errors;
 pub use crate::errors::*;
 
+mod downloader;
+pub use crate::downloader::{Downloader, Fetch};
+
+mod credentials;
+pub use crate::credentials::{CredentialProvider, Credentials};
+
+mod host;
+pub use crate::host::{HostConfig, HostConfigs};
+
+mod proxy;
+pub use crate::proxy::{proxy_for_url, ProxyConfig};
+
+mod throttle;
+pub use crate::throttle::Throttle;
+
+mod timeouts;
+pub use crate::timeouts::{Stall, Timeouts};
+
 #[derive(Debug, Copy, Clone)]
 pub enum Backend {
     Curl,
     Reqwest(TlsBackend),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TlsBackend {
     Rustls,
     Default,
 }
 
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum Event<'a> {
     ResumingPartialDownload,
     /// Received the Content-Length of the to-be downloaded data.
     DownloadContentLengthReceived(u64),
     /// Received some data.
     DownloadDataReceived(&'a [u8]),
+    /// Holding back from receiving more data to keep to a `Throttle`'s
+    /// cap, in bytes per second.
+    DownloadThrottled(u64),
+    /// Received the `ETag` or `Last-Modified` of the file, to send as
+    /// `If-Range` when resuming the download.
+    DownloadValidatorReceived(&'a str),
+    /// The server sent the whole file rather than the rest of it, as it's
+    /// changed since the download started or the server doesn't do ranges,
+    /// so the data received before is to be thrown away.
+    DownloadRestarted,
+    /// Received the `ETag` and `Last-Modified` of the file, either of
+    /// which may be missing, to make a `CachedVersion` of.
+    DownloadVersionReceived(Option<&'a str>, Option<&'a str>),
+}
+
+/// What identifies a version of a file kept from an earlier download, so
+/// that the file is only downloaded again if it's changed since.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CachedVersion {
+    /// Sent as `If-None-Match`
+    pub etag: Option<String>,
+    /// Sent as `If-Modified-Since`
+    pub last_modified: Option<String>,
+}
+
+/// How to make a download, beyond which backend makes it.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Options<'a> {
+    /// How to make requests to the host, if it's been configured
+    pub host: Option<&'a HostConfig>,
+    /// A cap on how fast to receive data, if there is one
+    pub throttle: Option<&'a Throttle>,
+    /// How long to wait on the server
+    pub timeouts: Timeouts,
+    /// Which proxy to go through, in place of the one the environment
+    /// says, if given
+    pub proxy: Option<&'a ProxyConfig>,
+    /// The version of the file already had, if any. The file is only
+    /// downloaded if it's changed since, and otherwise the download fails
+    /// with `ErrorKind::NotModified`.
+    pub cached: Option<&'a CachedVersion>,
+}
+
+// The error for an unsuccessful response, noting how long the server asked
+// us to wait before trying again, if it did.
+fn http_status(code: u32, retry_after: Option<&str>) -> Error {
+    match retry_after.and_then(parse_retry_after) {
+        Some(delay) => Error::with_chain(
+            Error::from(ErrorKind::RetryAfter(delay)),
+            ErrorKind::HttpStatus(code),
+        ),
+        None => ErrorKind::HttpStatus(code).into(),
+    }
+}
+
+// A `Retry-After` header's value. Only the number of seconds to wait is
+// understood; a date to wait until is ignored.
+fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .map(std::time::Duration::from_secs)
+}
+
+// The value to send as `If-Range` to resume downloading the version of a
+// file that came with these headers: a strong `ETag`, or failing that its
+// `Last-Modified`. A weak `ETag` can't be used to resume.
+fn validator(etag: Option<&str>, last_modified: Option<&str>) -> Option<String> {
+    match etag.map(str::trim) {
+        Some(etag) if !etag.starts_with("W/") && !etag.is_empty() => Some(etag.to_owned()),
+        _ => last_modified
+            .map(str::trim)
+            .filter(|date| !date.is_empty())
+            .map(ToOwned::to_owned),
+    }
+}
+
+// The headers asking for the file only if it's changed since the `cached`
+// version.
+fn cache_headers(cached: Option<&CachedVersion>) -> Vec<(&'static str, &str)> {
+    let mut headers = Vec::new();
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            headers.push(("If-None-Match", etag.as_str()));
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            headers.push(("If-Modified-Since", last_modified.as_str()));
+        }
+    }
+    headers
+}
+
+// Hands on what identifies the version of the file being received, to
+// resume the download with and to cache the file by.
+fn version_received(
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    callback: &dyn Fn(Event<'_>) -> Result<()>,
+) -> Result<()> {
+    if let Some(validator) = validator(etag, last_modified) {
+        callback(Event::DownloadValidatorReceived(&validator))?;
+    }
+    if etag.is_some() || last_modified.is_some() {
+        callback(Event::DownloadVersionReceived(etag, last_modified))?;
+    }
+    Ok(())
+}
+
+// Checks that a partial response's `Content-Range` starts where the
+// download is resumed from, so that it carries on from the right place.
+fn check_content_range(content_range: Option<&str>, resume_from: u64) -> Result<()> {
+    let start = content_range
+        .map(str::trim)
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.trim().parse::<u64>().ok());
+    if start == Some(resume_from) {
+        Ok(())
+    } else {
+        let range = content_range.unwrap_or("").trim().to_owned();
+        Err(ErrorKind::UnexpectedContentRange(range, resume_from).into())
+    }
 }
 
-fn download_with_backend(
+// Makes a request as `options` say, with the host's credentials if it has
+// any. As they may have expired since they were fetched, if the server
+// rejects them they're fetched again and the request is made once more.
+fn with_credentials<T>(
+    options: &Options<'_>,
+    request: &dyn Fn(&Options<'_>) -> Result<T>,
+) -> Result<T> {
+    let (host, credentials) = match options.host {
+        Some(config) => match &config.credentials {
+            Some(credentials) => (config, credentials),
+            None => return request(options),
+        },
+        None => return request(options),
+    };
+    let authorized = |authorization: Option<&str>| {
+        let host = host.authorized(authorization);
+        request(&Options {
+            host: Some(&host),
+            ..*options
+        })
+    };
+    let authorization = credentials.authorization()?;
+    match authorized(authorization.as_deref()) {
+        Err(ref e) if matches!(e.kind(), ErrorKind::HttpStatus(401)) => {
+            let authorization = credentials.refresh(authorization.as_deref())?;
+            authorized(authorization.as_deref())
+        }
+        result => result,
+    }
+}
+
+/// Download `url`, starting `resume_from` bytes in, handing the data to
+/// `callback` as it arrives rather than saving it anywhere, as `options`
+/// say.
+pub fn download_with_backend(
     backend: Backend,
     url: &Url,
     resume_from: u64,
+    options: &Options<'_>,
     callback: &dyn Fn(Event<'_>) -> Result<()>,
 ) -> Result<()> {
-    match backend {
-        Backend::Curl => curl::download(url, resume_from, callback),
-        Backend::Reqwest(tls) => reqwest_be::download(url, resume_from, callback, tls),
-    }
+    Downloader::for_options(backend, url, options).download(url, resume_from, callback)
+}
+
+/// Ask the server for the size of the file at `url` without downloading
+/// it. Servers need not say, in which case this returns `None`.
+pub fn content_length_with_backend(
+    backend: Backend,
+    url: &Url,
+    options: &Options<'_>,
+) -> Result<Option<u64>> {
+    Downloader::for_options(backend, url, options).content_length(url)
 }
 
 pub fn download_to_path_with_backend(
@@ -45,175 +229,220 @@ pub fn download_to_path_with_backend(
     url: &Url,
     path: &Path,
     resume_from_partial: bool,
+    host: Option<&HostConfig>,
+    throttle: Option<&Throttle>,
     callback: Option<&dyn Fn(Event<'_>) -> Result<()>>,
 ) -> Result<()> {
-    use std::cell::RefCell;
-    use std::fs::remove_file;
-    use std::fs::OpenOptions;
-    use std::io::{Read, Seek, SeekFrom, Write};
-
-    || -> Result<()> {
-        let (file, resume_from) = if resume_from_partial {
-            let possible_partial = OpenOptions::new().read(true).open(&path);
-
-            let downloaded_so_far = if let Ok(mut partial) = possible_partial {
-                if let Some(cb) = callback {
-                    cb(Event::ResumingPartialDownload)?;
-
-                    let mut buf = vec![0; 32768];
-                    let mut downloaded_so_far = 0;
-                    loop {
-                        let n = partial.read(&mut buf)?;
-                        downloaded_so_far += n as u64;
-                        if n == 0 {
-                            break;
-                        }
-                        cb(Event::DownloadDataReceived(&buf[..n]))?;
-                    }
-
-                    downloaded_so_far
-                } else {
-                    let file_info = partial.metadata()?;
-                    file_info.len()
-                }
-            } else {
-                0
-            };
-
-            let mut possible_partial = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&path)
-                .chain_err(|| "error opening file for download")?;
-
-            possible_partial.seek(SeekFrom::End(0))?;
-
-            (possible_partial, downloaded_so_far)
-        } else {
-            (
-                OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(&path)
-                    .chain_err(|| "error creating file for download")?,
-                0,
-            )
-        };
-
-        let file = RefCell::new(file);
-
-        download_with_backend(backend, url, resume_from, &|event| {
-            if let Event::DownloadDataReceived(data) = event {
-                file.borrow_mut()
-                    .write_all(data)
-                    .chain_err(|| "unable to write download to disk")?;
-            }
-            match callback {
-                Some(cb) => cb(event),
-                None => Ok(()),
-            }
-        })?;
-
-        file.borrow_mut()
-            .sync_data()
-            .chain_err(|| "unable to sync download to disk")?;
+    let options = Options {
+        host,
+        throttle,
+        ..Options::default()
+    };
+    download_to_path_with_options(backend, url, path, resume_from_partial, &options, callback)
+}
 
-        Ok(())
-    }()
-    .map_err(|e| {
-        // TODO: We currently clear up the cached download on any error, should we restrict it to a subset?
-        remove_file(path)
-            .chain_err(|| "cleaning up cached downloads")
-            .unwrap();
-        e
-    })
+/// Like `download_to_path_with_backend`, but taking the timeouts and the
+/// rest of `options` as well as the host and throttle.
+pub fn download_to_path_with_options(
+    backend: Backend,
+    url: &Url,
+    path: &Path,
+    resume_from_partial: bool,
+    options: &Options<'_>,
+    callback: Option<&dyn Fn(Event<'_>) -> Result<()>>,
+) -> Result<()> {
+    Downloader::for_options(backend, url, options).download_to_path(
+        url,
+        path,
+        resume_from_partial,
+        callback,
+    )
 }
 
 /// Download via libcurl; encrypt with the native (or OpenSSl) TLS
 /// stack via libcurl
 #[cfg(feature = "curl-backend")]
 pub mod curl {
-    use super::Event;
+    use super::{CachedVersion, Event, Options};
     use crate::errors::*;
-    use curl::easy::Easy;
-    use std::cell::RefCell;
+    use crate::host::{self, HostConfig};
+    use crate::proxy::{self, ProxyConfig};
+    use crate::timeouts::Timeouts;
+    use curl::easy::{Easy, List};
+    use std::cell::{Cell, RefCell};
     use std::str;
-    use std::time::Duration;
+    use std::sync::Mutex;
     use url::Url;
 
-    pub fn download(
+    /// libcurl handles kept to be reused, along with the connections they
+    /// have open.
+    pub(crate) enum Handles {
+        /// A handle for each thread, shared by every download on it
+        ThreadLocal,
+        /// Handles of their own, as many as have been in use at once
+        Pool(Mutex<Vec<Easy>>),
+    }
+
+    impl Handles {
+        pub(crate) fn thread_local() -> Self {
+            Handles::ThreadLocal
+        }
+
+        pub(crate) fn pool() -> Self {
+            Handles::Pool(Mutex::new(Vec::new()))
+        }
+
+        // Runs `f` with a handle no other download is using.
+        fn with<T>(&self, f: impl FnOnce(&mut Easy) -> Result<T>) -> Result<T> {
+            match self {
+                Handles::ThreadLocal => {
+                    thread_local!(static EASY: RefCell<Easy> = RefCell::new(Easy::new()));
+                    EASY.with(|handle| f(&mut handle.borrow_mut()))
+                }
+                Handles::Pool(pool) => {
+                    let handle = pool.lock().unwrap().pop();
+                    let mut handle = handle.unwrap_or_else(Easy::new);
+                    let result = f(&mut handle);
+                    pool.lock().unwrap().push(handle);
+                    result
+                }
+            }
+        }
+    }
+
+    pub(crate) fn download(
         url: &Url,
         resume_from: u64,
+        if_range: Option<&str>,
+        options: &Options<'_>,
         callback: &dyn Fn(Event<'_>) -> Result<()>,
+        handles: &Handles,
     ) -> Result<()> {
-        // Fetch either a cached libcurl handle (which will preserve open
-        // connections) or create a new one if it isn't listed.
-        //
-        // Once we've acquired it, reset the lifetime from 'static to our local
-        // scope.
-        thread_local!(static EASY: RefCell<Easy> = RefCell::new(Easy::new()));
-        EASY.with(|handle| {
-            let mut handle = handle.borrow_mut();
+        // Use a handle that may already have connections open, which are
+        // reused if they're to the same server
+        handles.with(|handle| {
+            // Start again from libcurl's defaults, keeping open connections,
+            // so that nothing set for a host downloaded from before carries
+            // over.
+            handle.reset();
 
+            // Redirects are left to the `Downloader` to follow, so that
+            // one host isn't sent what's meant for another
             handle
                 .url(&url.to_string())
                 .chain_err(|| "failed to set url")?;
-            handle
-                .follow_location(true)
-                .chain_err(|| "failed to set follow redirects")?;
 
-            if resume_from > 0 {
+            // A range rather than `resume_from`, which fails if the server
+            // sends the whole file, as it will if it's changed
+            let if_range = if resume_from > 0 {
                 handle
-                    .resume_from(resume_from)
+                    .range(&format!("{}-", resume_from))
                     .chain_err(|| "setting the range header for download resumption")?;
+                if_range
             } else {
-                // an error here indicates that the range header isn't supported by underlying curl,
-                // so there's nothing to "clear" - safe to ignore this error.
-                let _ = handle.resume_from(0);
-            }
+                None
+            };
 
-            // Take at most 30s to connect
-            handle
-                .connect_timeout(Duration::new(30, 0))
-                .chain_err(|| "failed to set connect timeout")?;
+            set_timeouts(handle, &options.timeouts)?;
+            set_proxy(handle, url, options.proxy)?;
+            configure(handle, options.host, if_range, options.cached)?;
 
+            let retry_after = RefCell::new(None);
+            let status = Cell::new(0u32);
+            let restarted = Cell::new(false);
+            let etag = RefCell::new(None);
+            let last_modified = RefCell::new(None);
+            let content_range = RefCell::new(None);
             {
                 let cberr = RefCell::new(None);
                 let mut transfer = handle.transfer();
 
                 // Data callback for libcurl which is called with data that's
                 // downloaded. We just feed it into our hasher and also write it out
-                // to disk.
+                // to disk. The body of an unsuccessful response isn't the data
+                // asked for, and is left out, so that the request can be made
+                // again.
                 transfer
-                    .write_function(|data| match callback(Event::DownloadDataReceived(data)) {
-                        Ok(()) => Ok(data.len()),
-                        Err(e) => {
-                            *cberr.borrow_mut() = Some(e);
-                            Ok(0)
+                    .write_function(|data| {
+                        if status.get() >= 300 {
+                            return Ok(data.len());
+                        }
+                        match callback(Event::DownloadDataReceived(data)) {
+                            Ok(()) => Ok(data.len()),
+                            Err(e) => {
+                                *cberr.borrow_mut() = Some(e);
+                                Ok(0)
+                            }
                         }
                     })
                     .chain_err(|| "failed to set write")?;
 
                 // Listen for headers and parse out a `Content-Length` (case-insensitive) if it
-                // comes so we know how much we're downloading.
+                // comes so we know how much we're downloading, a `Retry-After` in case
+                // the request fails, and what's needed to resume the download.
                 transfer
                     .header_function(|header| {
-                        if let Ok(data) = str::from_utf8(header) {
-                            let prefix = "content-length: ";
-                            if data.to_ascii_lowercase().starts_with(prefix) {
-                                if let Ok(s) = data[prefix.len()..].trim().parse::<u64>() {
-                                    let msg = Event::DownloadContentLengthReceived(s + resume_from);
-                                    match callback(msg) {
-                                        Ok(()) => (),
-                                        Err(e) => {
-                                            *cberr.borrow_mut() = Some(e);
-                                            return false;
-                                        }
-                                    }
+                        let data = match str::from_utf8(header) {
+                            Ok(data) => data,
+                            Err(_) => return true,
+                        };
+                        let header = data.to_ascii_lowercase();
+                        let value = |name: &str| data[name.len()..].trim().to_owned();
+                        let result = if data.starts_with("HTTP/") {
+                            // Each response, such as an interim one, starts afresh
+                            *retry_after.borrow_mut() = None;
+                            *etag.borrow_mut() = None;
+                            *last_modified.borrow_mut() = None;
+                            *content_range.borrow_mut() = None;
+                            if let Some(code) = data.split_whitespace().nth(1) {
+                                status.set(code.parse().unwrap_or(0));
+                            }
+                            if resume_from > 0 && status.get() == 200 {
+                                restarted.set(true);
+                                callback(Event::DownloadRestarted)
+                            } else {
+                                Ok(())
+                            }
+                        } else if header.starts_with("retry-after:") {
+                            *retry_after.borrow_mut() = Some(value("retry-after:"));
+                            Ok(())
+                        } else if header.starts_with("etag:") {
+                            *etag.borrow_mut() = Some(value("etag:"));
+                            Ok(())
+                        } else if header.starts_with("last-modified:") {
+                            *last_modified.borrow_mut() = Some(value("last-modified:"));
+                            Ok(())
+                        } else if header.starts_with("content-range:") {
+                            *content_range.borrow_mut() = Some(value("content-range:"));
+                            Ok(())
+                        } else if header.starts_with("content-length:") && status.get() < 300 {
+                            match value("content-length:").parse::<u64>() {
+                                Ok(len) => {
+                                    let offset = if restarted.get() { 0 } else { resume_from };
+                                    callback(Event::DownloadContentLengthReceived(len + offset))
                                 }
+                                Err(_) => Ok(()),
+                            }
+                        } else if data.trim().is_empty() && (200..300).contains(&status.get()) {
+                            // The end of the headers of the response with the file
+                            headers_received(
+                                resume_from,
+                                restarted.get(),
+                                etag.borrow().as_deref(),
+                                last_modified.borrow().as_deref(),
+                                content_range.borrow().as_deref(),
+                                callback,
+                            )
+                        } else {
+                            Ok(())
+                        };
+                        match result {
+                            Ok(()) => true,
+                            Err(e) => {
+                                *cberr.borrow_mut() = Some(e);
+                                false
                             }
                         }
-                        true
                     })
                     .chain_err(|| "failed to set header")?;
 
@@ -224,14 +453,8 @@ pub mod curl {
                     // callbacks, return it.
                     match cberr.borrow_mut().take() {
                         Some(cberr) => Err(cberr),
-                        None => {
-                            // Otherwise, return the error from curl
-                            if e.is_file_couldnt_read_file() {
-                                Err(e).chain_err(|| ErrorKind::FileNotFound)
-                            } else {
-                                Err(e).chain_err(|| "error during download")
-                            }
-                        }
+                        // Otherwise, return the error from curl
+                        None => Err(perform_error(e, "error during download")),
                     }
                 })?;
             }
@@ -240,61 +463,330 @@ pub mod curl {
             let code = handle
                 .response_code()
                 .chain_err(|| "failed to get response code")?;
+            if (300..400).contains(&code) {
+                if let Some(location) = redirect_url(handle)? {
+                    return Err(ErrorKind::Redirected(location).into());
+                }
+            }
             match code {
                 0 | 200..=299 => {}
+                304 => return Err(ErrorKind::NotModified.into()),
                 _ => {
-                    return Err(ErrorKind::HttpStatus(code).into());
+                    return Err(super::http_status(code, retry_after.borrow().as_deref()));
                 }
             };
 
             Ok(())
         })
     }
+
+    // Checks that a partial response carries on where the download left
+    // off, and hands on what's needed to resume the download later.
+    fn headers_received(
+        resume_from: u64,
+        restarted: bool,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+        content_range: Option<&str>,
+        callback: &dyn Fn(Event<'_>) -> Result<()>,
+    ) -> Result<()> {
+        if resume_from > 0 && !restarted {
+            super::check_content_range(content_range, resume_from)?;
+        }
+        super::version_received(etag, last_modified, callback)
+    }
+
+    pub(crate) fn content_length(
+        url: &Url,
+        options: &Options<'_>,
+        handles: &Handles,
+    ) -> Result<Option<u64>> {
+        handles.with(|handle| content_length_with(handle, url, options))
+    }
+
+    fn content_length_with(
+        handle: &mut Easy,
+        url: &Url,
+        options: &Options<'_>,
+    ) -> Result<Option<u64>> {
+        handle.reset();
+        handle
+            .url(&url.to_string())
+            .chain_err(|| "failed to set url")?;
+        handle.nobody(true).chain_err(|| "failed to set no body")?;
+        set_timeouts(handle, &options.timeouts)?;
+        set_proxy(handle, url, options.proxy)?;
+        configure(handle, options.host, None, None)?;
+        handle
+            .perform()
+            .map_err(|e| perform_error(e, "error during request"))?;
+
+        let code = handle
+            .response_code()
+            .chain_err(|| "failed to get response code")?;
+        if (300..400).contains(&code) {
+            if let Some(location) = redirect_url(handle)? {
+                return Err(ErrorKind::Redirected(location).into());
+            }
+        }
+        match code {
+            0 | 200..=299 => {}
+            _ => {
+                return Err(ErrorKind::HttpStatus(code).into());
+            }
+        };
+
+        // libcurl reports an unknown length as -1
+        let len = handle
+            .content_length_download()
+            .chain_err(|| "failed to get content length")?;
+        Ok(if len >= 0.0 { Some(len as u64) } else { None })
+    }
+
+    // Where the response just received redirects to, if anywhere.
+    fn redirect_url(handle: &mut Easy) -> Result<Option<Url>> {
+        let location = handle
+            .redirect_url()
+            .chain_err(|| "failed to get redirect url")?;
+        match location {
+            Some(location) => Url::parse(location)
+                .map(Some)
+                .chain_err(|| format!("bad redirect url: '{}'", location)),
+            None => Ok(None),
+        }
+    }
+
+    // `e`, from performing a transfer, explained by `context`, and as
+    // `ErrorKind::TimedOut` or `ErrorKind::ConnectionFailed` if it's from
+    // the network rather than, say, the server not being trusted.
+    fn perform_error(e: curl::Error, context: &'static str) -> Error {
+        if e.is_file_couldnt_read_file() {
+            Error::with_chain(e, ErrorKind::FileNotFound)
+        } else if e.is_operation_timedout() {
+            Error::with_chain(e, ErrorKind::TimedOut)
+        } else if e.is_couldnt_connect()
+            || e.is_couldnt_resolve_host()
+            || e.is_couldnt_resolve_proxy()
+            || e.is_send_error()
+            || e.is_recv_error()
+            || e.is_got_nothing()
+            || e.is_partial_file()
+            || e.is_http2_error()
+        {
+            Error::with_chain(e, ErrorKind::ConnectionFailed)
+        } else {
+            Error::with_chain(e, context)
+        }
+    }
+
+    // Gives up on connecting after `timeouts.connect`, and on the transfer
+    // once nothing at all has been received for `timeouts.idle`.
+    fn set_timeouts(handle: &mut Easy, timeouts: &Timeouts) -> Result<()> {
+        handle
+            .connect_timeout(timeouts.connect)
+            .chain_err(|| "failed to set connect timeout")?;
+        handle
+            .low_speed_limit(1)
+            .chain_err(|| "failed to set idle timeout")?;
+        handle
+            .low_speed_time(timeouts.idle)
+            .chain_err(|| "failed to set idle timeout")?;
+        Ok(())
+    }
+
+    // Goes through the proxy for `url`, worked out here rather than by
+    // libcurl so that it's the same one reqwest would go through.
+    fn set_proxy(handle: &mut Easy, url: &Url, config: Option<&ProxyConfig>) -> Result<()> {
+        // An empty proxy connects directly, and an empty list of hosts not
+        // to proxy keeps libcurl from reading `no_proxy` itself
+        let proxy = proxy::proxy_for_url(config, url);
+        handle
+            .proxy(proxy.as_ref().map_or("", Url::as_str))
+            .chain_err(|| "failed to set proxy")?;
+        handle.noproxy("").chain_err(|| "failed to set proxy")?;
+        Ok(())
+    }
+
+    // Sets up `handle` to make requests as `host` says, if given, only to
+    // resume a download of the file `if_range` identifies, if given, and
+    // only to download the file if it's changed since the `cached` version,
+    // if given.
+    fn configure(
+        handle: &mut Easy,
+        host: Option<&HostConfig>,
+        if_range: Option<&str>,
+        cached: Option<&CachedVersion>,
+    ) -> Result<()> {
+        let mut headers = List::new();
+        if let Some(if_range) = if_range {
+            headers
+                .append(&format!("If-Range: {}", if_range))
+                .chain_err(|| "failed to add header")?;
+        }
+        for (name, value) in super::cache_headers(cached) {
+            headers
+                .append(&format!("{}: {}", name, value))
+                .chain_err(|| "failed to add header")?;
+        }
+        let host = match host {
+            Some(host) => host,
+            None => {
+                return handle
+                    .http_headers(headers)
+                    .chain_err(|| "failed to set headers");
+            }
+        };
+        if let Some(ca_bundle) = &host.ca_bundle {
+            handle
+                .cainfo(ca_bundle)
+                .chain_err(|| "failed to set CA bundle")?;
+        }
+        if let Some(client_cert) = &host.client_cert {
+            let cert_type = if host::is_pem(&host::read_tls_file(client_cert)?) {
+                "PEM"
+            } else {
+                "P12"
+            };
+            handle
+                .ssl_cert(client_cert)
+                .chain_err(|| "failed to set client certificate")?;
+            handle
+                .ssl_cert_type(cert_type)
+                .chain_err(|| "failed to set client certificate type")?;
+        }
+        if let Some(client_key) = &host.client_key {
+            handle
+                .ssl_key(client_key)
+                .chain_err(|| "failed to set client key")?;
+        }
+        for (name, value) in &host.headers {
+            headers
+                .append(&format!("{}: {}", name, value))
+                .chain_err(|| "failed to add header")?;
+        }
+        handle
+            .http_headers(headers)
+            .chain_err(|| "failed to set headers")
+    }
 }
 
 #[cfg(feature = "reqwest-backend")]
 pub mod reqwest_be {
-    use super::Event;
     use super::TlsBackend;
+    use super::{Event, Options};
     use crate::errors::*;
+    use crate::host::{self, HostConfig};
+    use crate::proxy::{self, ProxyConfig};
+    use crate::timeouts::Timeouts;
     use lazy_static::lazy_static;
     use reqwest::blocking::{Client, ClientBuilder, Response};
-    use reqwest::{header, Proxy};
+    use reqwest::{header, Certificate, Identity, Proxy, StatusCode};
+    use std::collections::HashMap;
     use std::io;
-    use std::time::Duration;
+    use std::path::Path;
+    use std::sync::Mutex;
     use url::Url;
 
-    pub fn download(
+    /// The clients built for requests that can't be made with the default
+    /// ones, kept to be reused along with the connections they have open.
+    pub(crate) enum Clients {
+        /// The clients shared by every download in the process
+        Shared,
+        /// Clients of their own
+        Own(Mutex<ClientMap>),
+    }
+
+    // Clients by the TLS backend they use, the host they're for if it has
+    // TLS set up differently, how long they wait on the server and which
+    // proxy they go through.
+    type ClientMap = HashMap<(TlsBackend, Option<String>, Timeouts, Option<ProxyConfig>), Client>;
+
+    lazy_static! {
+        static ref SHARED_CLIENTS: Mutex<ClientMap> = Mutex::new(HashMap::new());
+    }
+
+    impl Clients {
+        pub(crate) fn shared() -> Self {
+            Clients::Shared
+        }
+
+        pub(crate) fn own() -> Self {
+            Clients::Own(Mutex::new(HashMap::new()))
+        }
+
+        fn map(&self) -> &Mutex<ClientMap> {
+            match self {
+                Clients::Shared => &SHARED_CLIENTS,
+                Clients::Own(clients) => clients,
+            }
+        }
+    }
+
+    pub(crate) fn download(
         url: &Url,
         resume_from: u64,
+        if_range: Option<&str>,
+        options: &Options<'_>,
         callback: &dyn Fn(Event<'_>) -> Result<()>,
         tls: TlsBackend,
+        clients: &Clients,
     ) -> Result<()> {
         // Short-circuit reqwest for the "file:" URL scheme
         if download_from_file_url(url, resume_from, callback)? {
             return Ok(());
         }
 
-        let mut res =
-            request(url, resume_from, tls).chain_err(|| "failed to make network request")?;
+        let client = client_for(tls, url, options, clients)?;
+        let mut res = request(&client, url, resume_from, if_range, options)
+            .map_err(|e| request_error(e, "failed to make network request"))?;
 
+        if res.status() == StatusCode::NOT_MODIFIED {
+            return Err(ErrorKind::NotModified.into());
+        }
+        if let Some(location) = redirect_url(url, &res)? {
+            return Err(ErrorKind::Redirected(location).into());
+        }
         if !res.status().is_success() {
             let code: u16 = res.status().into();
-            return Err(ErrorKind::HttpStatus(u32::from(code)).into());
+            let retry_after = res
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok());
+            return Err(super::http_status(u32::from(code), retry_after));
         }
 
+        // Servers send the whole file when it's changed since the download
+        // started, or if they don't do ranges
+        let header_value = |name: header::HeaderName| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let restarted = resume_from > 0 && res.status() != StatusCode::PARTIAL_CONTENT;
+        if restarted {
+            callback(Event::DownloadRestarted)?;
+        } else if resume_from > 0 {
+            super::check_content_range(header_value(header::CONTENT_RANGE), resume_from)?;
+        }
+        let offset = if restarted { 0 } else { resume_from };
+
         let buffer_size = 0x10000;
         let mut buffer = vec![0u8; buffer_size];
 
         if let Some(len) = res.headers().get(header::CONTENT_LENGTH) {
             // TODO possible issues during unwrap?
-            let len = len.to_str().unwrap().parse::<u64>().unwrap() + resume_from;
+            let len = len.to_str().unwrap().parse::<u64>().unwrap() + offset;
             callback(Event::DownloadContentLengthReceived(len))?;
         }
+        super::version_received(
+            header_value(header::ETAG),
+            header_value(header::LAST_MODIFIED),
+            callback,
+        )?;
 
         loop {
-            let bytes_read =
-                io::Read::read(&mut res, &mut buffer).chain_err(|| "error reading from socket")?;
+            let bytes_read = io::Read::read(&mut res, &mut buffer)
+                .map_err(|e| request_error(e.into(), "error reading from socket"))?;
 
             if bytes_read != 0 {
                 callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
@@ -304,17 +796,128 @@ pub mod reqwest_be {
         }
     }
 
-    fn client_generic() -> ClientBuilder {
+    pub(crate) fn content_length(
+        url: &Url,
+        options: &Options<'_>,
+        tls: TlsBackend,
+        clients: &Clients,
+    ) -> Result<Option<u64>> {
+        if url.scheme() == "file" {
+            let src = url
+                .to_file_path()
+                .map_err(|_| Error::from(format!("bogus file url: '{}'", url)))?;
+            return match std::fs::metadata(src) {
+                Ok(metadata) if metadata.is_file() => Ok(Some(metadata.len())),
+                _ => Err(ErrorKind::FileNotFound.into()),
+            };
+        }
+
+        let client = client_for(tls, url, options, clients)?;
+        let res = with_headers(client.head(url.as_str()), options.host)
+            .send()
+            .map_err(|e| request_error(e.into(), "failed to make network request"))?;
+
+        if let Some(location) = redirect_url(url, &res)? {
+            return Err(ErrorKind::Redirected(location).into());
+        }
+        if !res.status().is_success() {
+            let code: u16 = res.status().into();
+            return Err(ErrorKind::HttpStatus(u32::from(code)).into());
+        }
+
+        Ok(res
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse().ok()))
+    }
+
+    // Where `res`, the response to a request for `url`, redirects to, if
+    // anywhere.
+    fn redirect_url(url: &Url, res: &Response) -> Result<Option<Url>> {
+        if !res.status().is_redirection() {
+            return Ok(None);
+        }
+        match res.headers().get(header::LOCATION) {
+            Some(location) => {
+                let location = location.to_str().chain_err(|| "bad redirect url")?;
+                url.join(location)
+                    .map(Some)
+                    .chain_err(|| format!("bad redirect url: '{}'", location))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // The blocking client's timeout bounds each wait on the server, for
+    // the response and then for each read of its body, rather than the
+    // whole transfer, so it's the idle timeout. Redirects are left to the
+    // `Downloader` to follow, so that one host isn't sent what's meant for
+    // another.
+    fn client_generic(timeouts: &Timeouts, config: Option<&ProxyConfig>) -> ClientBuilder {
+        let config = config.cloned();
         Client::builder()
             .gzip(false)
-            .proxy(Proxy::custom(env_proxy))
-            .timeout(Duration::from_secs(30))
+            .redirect(reqwest::redirect::Policy::none())
+            .proxy(Proxy::custom(move |url| {
+                proxy::proxy_for_url(config.as_ref(), url)
+            }))
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.idle)
+    }
+
+    // `e`, explained by `context`, and as `ErrorKind::TimedOut` if it's
+    // from waiting too long on the server, or as
+    // `ErrorKind::ConnectionFailed` if the connection failed or dropped.
+    fn request_error(e: Error, context: &'static str) -> Error {
+        let (is_timeout, is_connection_failure) = match e.kind() {
+            ErrorKind::Reqwest(e) => (e.is_timeout(), is_connection_failure(e)),
+            ErrorKind::Io(e) => {
+                let inner = e.get_ref().and_then(|e| e.downcast_ref::<reqwest::Error>());
+                (
+                    e.kind() == io::ErrorKind::TimedOut
+                        || inner.map_or(false, reqwest::Error::is_timeout),
+                    is_connection_failure(e) || inner.map_or(false, |e| is_connection_failure(e)),
+                )
+            }
+            _ => (false, false),
+        };
+        let e = Error::with_chain(e, context);
+        if is_timeout {
+            Error::with_chain(e, ErrorKind::TimedOut)
+        } else if is_connection_failure {
+            Error::with_chain(e, ErrorKind::ConnectionFailed)
+        } else {
+            e
+        }
+    }
+
+    // Whether `e` is, or was caused by, the connection to the server
+    // failing or dropping. TLS failures, such as the server not being
+    // trusted, aren't reported as any of these.
+    fn is_connection_failure(e: &(dyn std::error::Error + 'static)) -> bool {
+        let mut source = Some(e);
+        while let Some(cause) = source {
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                match e.kind() {
+                    io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof => return true,
+                    _ => {}
+                }
+            }
+            source = cause.source();
+        }
+        false
     }
     #[cfg(feature = "reqwest-rustls-tls")]
     lazy_static! {
         static ref CLIENT_RUSTLS_TLS: Client = {
             let catcher = || {
-                client_generic().use_rustls_tls()
+                client_generic(&Timeouts::default(), None).use_rustls_tls()
                     .build()
             };
 
@@ -331,7 +934,7 @@ pub mod reqwest_be {
     lazy_static! {
         static ref CLIENT_DEFAULT_TLS: Client = {
             let catcher = || {
-                client_generic()
+                client_generic(&Timeouts::default(), None)
                     .build()
             };
 
@@ -345,12 +948,8 @@ pub mod reqwest_be {
         };
     }
 
-    fn env_proxy(url: &Url) -> Option<Url> {
-        env_proxy::for_url(url).to_url()
-    }
-
-    fn request(url: &Url, resume_from: u64, backend: TlsBackend) -> Result<Response> {
-        let client: &Client = match backend {
+    fn client(backend: TlsBackend) -> Result<&'static Client> {
+        let client: &'static Client = match backend {
             #[cfg(feature = "reqwest-rustls-tls")]
             TlsBackend::Rustls => &CLIENT_RUSTLS_TLS,
             #[cfg(not(feature = "reqwest-rustls-tls"))]
@@ -364,10 +963,141 @@ pub mod reqwest_be {
                 return Err(ErrorKind::BackendUnavailable("reqwest default TLS").into());
             }
         };
-        let mut req = client.get(url.as_str());
+        Ok(client)
+    }
+
+    // The client for requests to `url`, which needs one of its own if TLS
+    // is to be set up differently for its host, it's to be waited on for
+    // longer or shorter than usual, or it's to go through a configured
+    // proxy. Clients are cheap to clone, sharing their connections.
+    fn client_for(
+        backend: TlsBackend,
+        url: &Url,
+        options: &Options<'_>,
+        clients: &Clients,
+    ) -> Result<Client> {
+        let has_tls_settings = options.host.map_or(false, HostConfig::has_tls_settings);
+        if !has_tls_settings && options.timeouts == Timeouts::default() && options.proxy.is_none() {
+            return Ok(client(backend)?.clone());
+        }
+        let host = url
+            .host_str()
+            .filter(|_| has_tls_settings)
+            .map(str::to_ascii_lowercase);
+        let key = (backend, host, options.timeouts, options.proxy.cloned());
+        let mut clients = clients.map().lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = host_client(backend, options)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    fn host_client(backend: TlsBackend, options: &Options<'_>) -> Result<Client> {
+        let (timeouts, proxy) = (&options.timeouts, options.proxy);
+        let mut builder = match backend {
+            #[cfg(feature = "reqwest-rustls-tls")]
+            TlsBackend::Rustls => client_generic(timeouts, proxy).use_rustls_tls(),
+            #[cfg(not(feature = "reqwest-rustls-tls"))]
+            TlsBackend::Rustls => {
+                return Err(ErrorKind::BackendUnavailable("reqwest rustls").into());
+            }
+            #[cfg(feature = "reqwest-default-tls")]
+            TlsBackend::Default => client_generic(timeouts, proxy),
+            #[cfg(not(feature = "reqwest-default-tls"))]
+            TlsBackend::Default => {
+                return Err(ErrorKind::BackendUnavailable("reqwest default TLS").into());
+            }
+        };
+
+        let host = match options.host {
+            Some(host) => host,
+            None => return Ok(builder.build()?),
+        };
+        if let Some(ca_bundle) = &host.ca_bundle {
+            let bundle = host::read_tls_file(ca_bundle)?;
+            let certificates = host::pem_certificates(&bundle);
+            if certificates.is_empty() {
+                return Err(ErrorKind::NoCertificates(ca_bundle.clone()).into());
+            }
+            builder = builder.tls_built_in_root_certs(false);
+            for certificate in certificates {
+                builder = builder.add_root_certificate(Certificate::from_pem(certificate)?);
+            }
+        }
+        if let Some(client_cert) = &host.client_cert {
+            let identity = identity(backend, client_cert, host.client_key.as_deref())?;
+            builder = builder.identity(identity);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    // The certificate to identify ourselves with, which rustls needs as PEM
+    // and the default TLS as PKCS#12.
+    fn identity(
+        backend: TlsBackend,
+        cert_path: &Path,
+        key_path: Option<&Path>,
+    ) -> Result<Identity> {
+        let cert = host::read_tls_file(cert_path)?;
+        let wrong_format = |tls: &'static str, format: &'static str| -> Result<Identity> {
+            Err(ErrorKind::ClientCertificateFormat(cert_path.to_owned(), tls, format).into())
+        };
+        match backend {
+            #[cfg(feature = "reqwest-rustls-tls")]
+            TlsBackend::Rustls => {
+                if !host::is_pem(&cert) {
+                    return wrong_format("rustls", "a PEM file");
+                }
+                let mut pem = cert;
+                if let Some(key_path) = key_path {
+                    pem.push(b'\n');
+                    pem.extend(host::read_tls_file(key_path)?);
+                }
+                Ok(Identity::from_pem(&pem)?)
+            }
+            #[cfg(feature = "reqwest-default-tls")]
+            TlsBackend::Default => {
+                if host::is_pem(&cert) {
+                    return wrong_format("the default TLS", "a PKCS#12 archive");
+                }
+                Ok(Identity::from_pkcs12_der(&cert, "")?)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(ErrorKind::BackendUnavailable("reqwest").into()),
+        }
+    }
+
+    // Adds the headers `host` asks for to a request.
+    fn with_headers(
+        mut req: reqwest::blocking::RequestBuilder,
+        host: Option<&HostConfig>,
+    ) -> reqwest::blocking::RequestBuilder {
+        for (name, value) in host.map_or(&[][..], |host| &host.headers[..]) {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        req
+    }
+
+    fn request(
+        client: &Client,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+        options: &Options<'_>,
+    ) -> Result<Response> {
+        let mut req = with_headers(client.get(url.as_str()), options.host);
+        for (name, value) in super::cache_headers(options.cached) {
+            req = req.header(name, value);
+        }
 
         if resume_from != 0 {
             req = req.header(header::RANGE, format!("bytes={}-", resume_from));
+            if let Some(if_range) = if_range {
+                req = req.header(header::IF_RANGE, if_range);
+            }
         }
 
         Ok(req.send()?)
@@ -416,33 +1146,80 @@ pub mod reqwest_be {
 #[cfg(not(feature = "curl-backend"))]
 pub mod curl {
 
-    use super::Event;
+    use super::{Event, Options};
     use crate::errors::*;
     use url::Url;
 
-    pub fn download(
+    pub(crate) struct Handles;
+
+    impl Handles {
+        pub(crate) fn thread_local() -> Self {
+            Handles
+        }
+
+        pub(crate) fn pool() -> Self {
+            Handles
+        }
+    }
+
+    pub(crate) fn download(
         _url: &Url,
         _resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &Options<'_>,
         _callback: &dyn Fn(Event<'_>) -> Result<()>,
+        _handles: &Handles,
     ) -> Result<()> {
         Err(ErrorKind::BackendUnavailable("curl").into())
     }
+
+    pub(crate) fn content_length(
+        _url: &Url,
+        _options: &Options<'_>,
+        _handles: &Handles,
+    ) -> Result<Option<u64>> {
+        Err(ErrorKind::BackendUnavailable("curl").into())
+    }
 }
 
 #[cfg(not(feature = "reqwest-backend"))]
 pub mod reqwest_be {
 
-    use super::Event;
     use super::TlsBackend;
+    use super::{Event, Options};
     use crate::errors::*;
     use url::Url;
 
-    pub fn download(
+    pub(crate) struct Clients;
+
+    impl Clients {
+        pub(crate) fn shared() -> Self {
+            Clients
+        }
+
+        pub(crate) fn own() -> Self {
+            Clients
+        }
+    }
+
+    pub(crate) fn download(
         _url: &Url,
         _resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &Options<'_>,
         _callback: &dyn Fn(Event<'_>) -> Result<()>,
         _tls: TlsBackend,
+        _clients: &Clients,
     ) -> Result<()> {
         Err(ErrorKind::BackendUnavailable("reqwest").into())
     }
+
+    pub(crate) fn content_length(
+        _url: &Url,
+        _options: &Options<'_>,
+        _tls: TlsBackend,
+        _clients: &Clients,
+    ) -> Result<Option<u64>> {
+        Err(ErrorKind::BackendUnavailable("reqwest").into())
+    }
 }
@@ -0,0 +1,50 @@
+//! Limiting how fast data is downloaded
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cap on how fast data is received. Clones share the cap, so that
+/// downloads running at the same time keep to it between them.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    /// In bytes per second
+    rate: u64,
+    /// When the data received so far would all have been received had it
+    /// come at `rate`
+    caught_up: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Throttle {
+    /// A cap of `rate` bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn new(rate: u64) -> Self {
+        assert!(rate > 0, "a download rate must be more than zero");
+        Self {
+            rate,
+            caught_up: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The cap, in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Accounts for `len` bytes having just been received, returning how
+    /// long to wait before receiving more to keep to the cap. Time spent
+    /// receiving nothing isn't saved up to allow a burst later.
+    pub(crate) fn received(&self, len: usize) -> Duration {
+        let now = Instant::now();
+        let mut caught_up = self.caught_up.lock().unwrap();
+        let start = match *caught_up {
+            Some(caught_up) if caught_up > now => caught_up,
+            _ => now,
+        };
+        let end = start + Duration::from_secs_f64(len as f64 / self.rate as f64);
+        *caught_up = Some(end);
+        end - now
+    }
+}
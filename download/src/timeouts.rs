@@ -0,0 +1,87 @@
+//! How long to wait on a server before giving up on a download
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+
+/// How long to wait on a server before giving up on a download. A download
+/// that keeps making progress may take as long as it needs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Timeouts {
+    /// To connect to the server
+    pub connect: Duration,
+    /// To receive anything, whether the response or more of its body
+    pub idle: Duration,
+    /// To give up on a download that's too slow to be worth waiting for,
+    /// if given
+    pub stall: Option<Stall>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(30),
+            idle: Duration::from_secs(30),
+            stall: None,
+        }
+    }
+}
+
+/// A download is stalled if it receives less than `min_rate` bytes per
+/// second, on average, for `window`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Stall {
+    pub min_rate: u64,
+    pub window: Duration,
+}
+
+// Watches how fast a download receives data, to give up on it if stalled.
+// Time spent holding back for a `Throttle` isn't counted.
+pub(crate) struct StallDetector {
+    stall: Option<Stall>,
+    window_start: Cell<Instant>,
+    received: Cell<u64>,
+    paused: Cell<Duration>,
+}
+
+impl StallDetector {
+    pub(crate) fn new(stall: Option<Stall>) -> Self {
+        Self {
+            stall,
+            window_start: Cell::new(Instant::now()),
+            received: Cell::new(0),
+            paused: Cell::new(Duration::from_secs(0)),
+        }
+    }
+
+    // Accounts for `len` bytes having been received, failing if that
+    // finishes a window in which too little was.
+    pub(crate) fn received(&self, len: usize) -> Result<()> {
+        let stall = match self.stall {
+            Some(stall) => stall,
+            None => return Ok(()),
+        };
+        self.received.set(self.received.get() + len as u64);
+        let now = Instant::now();
+        let elapsed = (now - self.window_start.get())
+            .checked_sub(self.paused.get())
+            .unwrap_or_default();
+        if elapsed < stall.window {
+            return Ok(());
+        }
+        let rate = (self.received.get() as f64 / elapsed.as_secs_f64()) as u64;
+        if rate < stall.min_rate {
+            return Err(ErrorKind::Stalled(rate, stall).into());
+        }
+        self.window_start.set(now);
+        self.received.set(0);
+        self.paused.set(Duration::from_secs(0));
+        Ok(())
+    }
+
+    // Accounts for having held back from receiving for `wait`.
+    pub(crate) fn paused(&self, wait: Duration) {
+        self.paused.set(self.paused.get() + wait);
+    }
+}
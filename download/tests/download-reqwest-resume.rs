@@ -1,14 +1,20 @@
 #![cfg(feature = "reqwest-backend")]
 
+use std::net::TcpListener;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use url::Url;
 
 use download::*;
 
 mod support;
-use crate::support::{serve_file, tmp_dir, write_file};
+use crate::support::{
+    serve_authorized, serve_file, serve_ignoring_ranges, serve_proxy, serve_status, serve_trickle,
+    serve_versioned, tmp_dir, write_file, Tokens,
+};
 
 #[test]
 fn resume_partial_from_file_url() {
@@ -26,6 +32,8 @@ fn resume_partial_from_file_url() {
         &target_path,
         true,
         None,
+        None,
+        None,
     )
     .expect("Test download failed");
 
@@ -51,6 +59,8 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
         &from_url,
         &target_path,
         true,
+        None,
+        None,
         Some(&|msg| {
             match msg {
                 Event::ResumingPartialDownload => {
@@ -67,6 +77,13 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
                         received_in_callback.lock().unwrap().push(*b);
                     }
                 }
+                Event::DownloadThrottled(_) => panic!("download throttled without a throttle"),
+                Event::DownloadValidatorReceived(_) => {}
+                Event::DownloadRestarted => {
+                    panic!("download restarted from a range-capable server")
+                }
+                Event::DownloadVersionReceived(..) => {}
+                _ => {}
             }
 
             Ok(())
@@ -80,3 +97,539 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
     assert_eq!(observed_bytes, vec![b'1', b'2', b'3', b'4', b'5']);
     assert_eq!(std::fs::read_to_string(&target_path).unwrap(), "12345");
 }
+
+#[test]
+fn unsuccessful_status_notes_retry_after() {
+    let addr = serve_status(503, Some("120"));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options::default(),
+        &|_| Ok(()),
+    )
+    .expect_err("Test download succeeded");
+
+    match e.kind() {
+        ErrorKind::HttpStatus(503) => {}
+        kind => panic!("unexpected error: {}", kind),
+    }
+    match std::error::Error::source(&e).and_then(|cause| cause.downcast_ref::<Error>()) {
+        Some(cause) => match cause.kind() {
+            ErrorKind::RetryAfter(delay) => assert_eq!(*delay, Duration::from_secs(120)),
+            kind => panic!("unexpected cause: {}", kind),
+        },
+        None => panic!("no Retry-After noted"),
+    }
+}
+
+#[test]
+fn throttled_download_keeps_to_the_rate() {
+    let addr = serve_file(vec![0; 96 * 1024]);
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let throttle = Throttle::new(64 * 1024);
+
+    let throttled = AtomicBool::new(false);
+    let start = Instant::now();
+    download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            throttle: Some(&throttle),
+            ..Options::default()
+        },
+        &|msg| {
+            if let Event::DownloadThrottled(rate) = msg {
+                assert_eq!(rate, 64 * 1024);
+                throttled.store(true, Ordering::SeqCst);
+            }
+            Ok(())
+        },
+    )
+    .expect("Test download failed");
+
+    assert!(start.elapsed() >= Duration::from_millis(1400));
+    assert!(throttled.into_inner());
+}
+
+#[test]
+fn throttle_caps_concurrent_downloads_together() {
+    let addr = serve_file(vec![0; 64 * 1024]);
+    let throttle = Throttle::new(64 * 1024);
+
+    let start = Instant::now();
+    let downloads: Vec<_> = (0..2)
+        .map(|_| {
+            let throttle = throttle.clone();
+            thread::spawn(move || {
+                let from_url = format!("http://{}", addr).parse().unwrap();
+                download_with_backend(
+                    Backend::Reqwest(TlsBackend::Default),
+                    &from_url,
+                    0,
+                    &Options {
+                        throttle: Some(&throttle),
+                        ..Options::default()
+                    },
+                    &|_| Ok(()),
+                )
+                .expect("Test download failed");
+            })
+        })
+        .collect();
+    for download in downloads {
+        download.join().unwrap();
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(1900));
+}
+
+#[test]
+fn rejected_credentials_are_fetched_again() {
+    let addr = serve_authorized("Bearer fresh", b"12345".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let host = HostConfig {
+        credentials: Some(Credentials::new(Tokens::new(&[
+            "Bearer stale",
+            "Bearer fresh",
+        ]))),
+        ..HostConfig::default()
+    };
+
+    let received = Mutex::new(Vec::new());
+    download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            host: Some(&host),
+            ..Options::default()
+        },
+        &|msg| {
+            if let Event::DownloadDataReceived(data) = msg {
+                received.lock().unwrap().extend_from_slice(data);
+            }
+            Ok(())
+        },
+    )
+    .expect("Test download failed");
+
+    assert_eq!(received.into_inner().unwrap(), b"12345");
+}
+
+#[test]
+fn rejected_credentials_are_fetched_again_only_once() {
+    let addr = serve_authorized("Bearer fresh", b"12345".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let host = HostConfig {
+        credentials: Some(Credentials::new(Tokens::new(&[
+            "Bearer stale",
+            "Bearer stale",
+        ]))),
+        ..HostConfig::default()
+    };
+
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            host: Some(&host),
+            ..Options::default()
+        },
+        &|msg| {
+            assert!(!matches!(msg, Event::DownloadDataReceived(_)));
+            Ok(())
+        },
+    )
+    .expect_err("Test download succeeded");
+
+    match e.kind() {
+        ErrorKind::HttpStatus(401) => {}
+        kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(!format!("{:?}", e).contains("stale"));
+}
+
+#[test]
+fn unresponsive_server_times_out() {
+    let addr = serve_trickle(vec![0; 8], 4, Duration::from_secs(3600));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let options = Options {
+        timeouts: Timeouts {
+            idle: Duration::from_secs(1),
+            ..Timeouts::default()
+        },
+        ..Options::default()
+    };
+
+    let start = Instant::now();
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &options,
+        &|_| Ok(()),
+    )
+    .expect_err("Test download succeeded");
+
+    match e.kind() {
+        ErrorKind::TimedOut => {}
+        kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn refused_connection_is_a_connection_failure() {
+    // Nothing listens on the port once the listener is dropped
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options::default(),
+        &|_| Ok(()),
+    )
+    .expect_err("Test download succeeded");
+
+    match e.kind() {
+        ErrorKind::ConnectionFailed => {}
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn slow_but_steady_download_is_not_timed_out() {
+    let addr = serve_trickle(vec![b'x'; 8], 1, Duration::from_millis(300));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let options = Options {
+        timeouts: Timeouts {
+            idle: Duration::from_secs(1),
+            ..Timeouts::default()
+        },
+        ..Options::default()
+    };
+
+    let received = Mutex::new(Vec::new());
+    download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &options,
+        &|msg| {
+            if let Event::DownloadDataReceived(data) = msg {
+                received.lock().unwrap().extend_from_slice(data);
+            }
+            Ok(())
+        },
+    )
+    .expect("Test download failed");
+
+    assert_eq!(received.into_inner().unwrap(), b"xxxxxxxx");
+}
+
+#[test]
+fn download_slower_than_the_minimum_rate_stalls() {
+    let addr = serve_trickle(vec![0; 64], 4, Duration::from_millis(200));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let stall = Stall {
+        min_rate: 1024,
+        window: Duration::from_secs(1),
+    };
+    let options = Options {
+        timeouts: Timeouts {
+            stall: Some(stall),
+            ..Timeouts::default()
+        },
+        ..Options::default()
+    };
+
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &options,
+        &|_| Ok(()),
+    )
+    .expect_err("Test download succeeded");
+
+    match e.kind() {
+        ErrorKind::Stalled(rate, stalled) => {
+            assert!(*rate < 1024);
+            assert_eq!(*stalled, stall);
+        }
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn unchanged_file_is_resumed() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&tmpdir.path().join("downloaded.validator"), "\"v1\"");
+
+    let addr = serve_versioned("\"v1\"", b"xxx45".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let restarted = AtomicBool::new(false);
+    download_to_path_with_options(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &target_path,
+        true,
+        &Options::default(),
+        Some(&|msg| {
+            if let Event::DownloadRestarted = msg {
+                restarted.store(true, Ordering::SeqCst);
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert!(!restarted.into_inner());
+    assert_eq!(std::fs::read_to_string(&target_path).unwrap(), "12345");
+    assert!(!tmpdir.path().join("downloaded.validator").exists());
+}
+
+#[test]
+fn changed_file_is_downloaded_again_in_full() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&tmpdir.path().join("downloaded.validator"), "\"v1\"");
+
+    let addr = serve_versioned("\"v2\"", b"abcde".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let received = Mutex::new(Vec::new());
+    download_to_path_with_options(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &target_path,
+        true,
+        &Options::default(),
+        Some(&|msg| {
+            match msg {
+                Event::DownloadDataReceived(data) => {
+                    received.lock().unwrap().extend_from_slice(data)
+                }
+                Event::DownloadRestarted => received.lock().unwrap().clear(),
+                _ => {}
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(received.into_inner().unwrap(), b"abcde");
+    assert_eq!(std::fs::read_to_string(&target_path).unwrap(), "abcde");
+}
+
+#[test]
+fn download_from_server_ignoring_ranges_is_restarted() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+
+    let addr = serve_ignoring_ranges(b"abcde".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let len = Mutex::new(None);
+    download_to_path_with_options(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &target_path,
+        true,
+        &Options::default(),
+        Some(&|msg| {
+            if let Event::DownloadContentLengthReceived(received) = msg {
+                *len.lock().unwrap() = Some(received);
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(*len.lock().unwrap(), Some(5));
+    assert_eq!(std::fs::read_to_string(&target_path).unwrap(), "abcde");
+}
+
+#[test]
+fn partial_download_longer_than_the_file_is_restarted() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "1234567");
+
+    let addr = serve_versioned("\"v1\"", b"abcde".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    download_to_path_with_options(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &target_path,
+        true,
+        &Options::default(),
+        None,
+    )
+    .expect("Test download failed");
+
+    assert_eq!(std::fs::read_to_string(&target_path).unwrap(), "abcde");
+}
+
+#[test]
+fn validator_is_kept_while_the_download_is_partial() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let addr = serve_versioned("\"v1\"", b"abcde".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let validator_path = tmpdir.path().join("downloaded.validator");
+    download_to_path_with_options(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &target_path,
+        true,
+        &Options::default(),
+        Some(&|msg| {
+            if let Event::DownloadDataReceived(_) = msg {
+                assert_eq!(std::fs::read_to_string(&validator_path).unwrap(), "\"v1\"");
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert!(!validator_path.exists());
+}
+
+#[test]
+fn unchanged_file_is_not_downloaded_again() {
+    let addr = serve_versioned("\"v1\"", b"12345".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let cached = CachedVersion {
+        etag: Some("\"v1\"".to_owned()),
+        last_modified: None,
+    };
+
+    let e = download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            cached: Some(&cached),
+            ..Options::default()
+        },
+        &|msg| {
+            if let Event::DownloadDataReceived(_) = msg {
+                panic!("the file was downloaded again");
+            }
+            Ok(())
+        },
+    )
+    .expect_err("Test download succeeded");
+
+    assert!(matches!(e.kind(), ErrorKind::NotModified), "{}", e);
+}
+
+#[test]
+fn changed_file_is_downloaded_with_its_version() {
+    let addr = serve_versioned("\"v2\"", b"abcde".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let cached = CachedVersion {
+        etag: Some("\"v1\"".to_owned()),
+        last_modified: None,
+    };
+
+    let received = Mutex::new(Vec::new());
+    let etag = Mutex::new(None);
+    download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            cached: Some(&cached),
+            ..Options::default()
+        },
+        &|msg| {
+            match msg {
+                Event::DownloadDataReceived(data) => {
+                    received.lock().unwrap().extend_from_slice(data)
+                }
+                Event::DownloadVersionReceived(received_etag, _) => {
+                    *etag.lock().unwrap() = received_etag.map(ToOwned::to_owned)
+                }
+                _ => {}
+            }
+            Ok(())
+        },
+    )
+    .expect("Test download failed");
+
+    assert_eq!(received.into_inner().unwrap(), b"abcde");
+    assert_eq!(etag.into_inner().unwrap().as_deref(), Some("\"v2\""));
+}
+
+#[test]
+fn download_goes_through_the_configured_proxy() {
+    let addr = serve_proxy(Some("Basic dXNlcjpwYXNz"), b"12345".to_vec());
+    let proxy = ProxyConfig {
+        http: Some(format!("http://{}", addr).parse().unwrap()),
+        username: Some("user".to_owned()),
+        password: Some("pass".to_owned()),
+        ..ProxyConfig::default()
+    };
+    // The proxy fetches it from wherever it is
+    let from_url = "http://dist.example.invalid/file".parse().unwrap();
+
+    let received = Mutex::new(Vec::new());
+    download_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        0,
+        &Options {
+            proxy: Some(&proxy),
+            ..Options::default()
+        },
+        &|msg| {
+            if let Event::DownloadDataReceived(data) = msg {
+                received.lock().unwrap().extend_from_slice(data);
+            }
+            Ok(())
+        },
+    )
+    .expect("Test download failed");
+
+    assert_eq!(received.into_inner().unwrap(), b"12345");
+}
+
+#[test]
+fn hosts_not_to_proxy_are_downloaded_from_directly() {
+    // A proxy that fails every request
+    let proxy_addr = serve_status(502, None);
+    let proxy = ProxyConfig {
+        all: Some(format!("http://{}", proxy_addr).parse().unwrap()),
+        no_proxy: vec!["localhost".to_owned(), "127.0.0.1".to_owned()],
+        ..ProxyConfig::default()
+    };
+    let addr = serve_file(b"12345".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let len = content_length_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &from_url,
+        &Options {
+            proxy: Some(&proxy),
+            ..Options::default()
+        },
+    )
+    .expect("Test request failed");
+
+    assert_eq!(len, Some(5));
+}
@@ -0,0 +1,253 @@
+//! Downloads from a server whose certificate is signed by a private CA,
+//! and which may ask for a client certificate signed by it too.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use rustls::{AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig};
+use tempfile::TempDir;
+use url::Url;
+
+use download::*;
+
+fn certificate(name: &str, is_ca: bool) -> Certificate {
+    let mut params = CertificateParams::new(vec!["localhost".to_owned()]);
+    params.distinguished_name.push(DnType::CommonName, name);
+    if is_ca {
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    }
+    Certificate::from_params(params).unwrap()
+}
+
+/// A private CA, with a client certificate it signed
+struct Pki {
+    dir: TempDir,
+    ca: Certificate,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let dir = tempfile::Builder::new()
+            .prefix("rustup-download-test-")
+            .tempdir()
+            .expect("creating tempdir for test");
+        let ca = certificate("rustup test CA", true);
+        fs::write(dir.path().join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+        let client = certificate("rustup test client", false);
+        let client_pem = client.serialize_pem_with_signer(&ca).unwrap();
+        fs::write(dir.path().join("client.pem"), client_pem).unwrap();
+        let client_key_pem = client.serialize_private_key_pem();
+        fs::write(dir.path().join("client-key.pem"), client_key_pem).unwrap();
+
+        Self { dir, ca }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Serves requests over TLS with a certificate signed by the CA,
+    /// responding with the request's `Authorization` header, or `none`.
+    fn serve(&self, require_client_cert: bool) -> SocketAddr {
+        let verifier = if require_client_cert {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(&rustls::Certificate(self.ca.serialize_der().unwrap()))
+                .unwrap();
+            AllowAnyAuthenticatedClient::new(roots)
+        } else {
+            NoClientAuth::new()
+        };
+        let server = certificate("rustup test server", false);
+        let mut config = ServerConfig::new(verifier);
+        config
+            .set_single_cert(
+                vec![rustls::Certificate(
+                    server.serialize_der_with_signer(&self.ca).unwrap(),
+                )],
+                rustls::PrivateKey(server.serialize_private_key_der()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let config = config.clone();
+                // Failed handshakes are what some tests are after
+                thread::spawn(move || respond(&config, stream));
+            }
+        });
+        addr
+    }
+
+    fn host_config(&self, client_cert: bool) -> HostConfig {
+        HostConfig {
+            ca_bundle: Some(self.path("ca.pem")),
+            client_cert: if client_cert {
+                Some(self.path("client.pem"))
+            } else {
+                None
+            },
+            client_key: if client_cert {
+                Some(self.path("client-key.pem"))
+            } else {
+                None
+            },
+            headers: vec![("Authorization".to_owned(), "Bearer sesame".to_owned())],
+            credentials: None,
+        }
+    }
+}
+
+fn respond(config: &Arc<ServerConfig>, stream: TcpStream) -> io::Result<()> {
+    let mut stream = rustls::StreamOwned::new(rustls::ServerSession::new(config), stream);
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let authorization = request
+        .lines()
+        .filter_map(|line| {
+            let mut header = line.splitn(2, ':');
+            match (header.next(), header.next()) {
+                (Some(name), Some(value)) if name.eq_ignore_ascii_case("authorization") => {
+                    Some(value.trim())
+                }
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap_or("none");
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        authorization.len(),
+        authorization
+    )?;
+    stream.flush()?;
+    stream.sess.send_close_notify();
+    stream.flush()
+}
+
+fn fetch(backend: Backend, addr: SocketAddr, host: Option<&HostConfig>) -> Result<String> {
+    let url = Url::parse(&format!("https://localhost:{}/file", addr.port())).unwrap();
+    let received = RefCell::new(Vec::new());
+    let options = Options {
+        host,
+        ..Options::default()
+    };
+    download_with_backend(backend, &url, 0, &options, &|event| {
+        if let Event::DownloadDataReceived(data) = event {
+            received.borrow_mut().extend_from_slice(data);
+        }
+        Ok(())
+    })?;
+    Ok(String::from_utf8(received.into_inner()).unwrap())
+}
+
+#[test]
+#[cfg(feature = "curl-backend")]
+fn curl_trusts_the_ca_bundle_and_sends_the_client_certificate() {
+    let pki = Pki::new();
+    let addr = pki.serve(true);
+    let host = pki.host_config(true);
+
+    let body = fetch(Backend::Curl, addr, Some(&host)).expect("Test download failed");
+    assert_eq!(body, "Bearer sesame");
+}
+
+#[test]
+#[cfg(feature = "reqwest-rustls-tls")]
+fn rustls_trusts_the_ca_bundle_and_sends_the_client_certificate() {
+    let pki = Pki::new();
+    let addr = pki.serve(true);
+    let host = pki.host_config(true);
+
+    let backend = Backend::Reqwest(TlsBackend::Rustls);
+    let body = fetch(backend, addr, Some(&host)).expect("Test download failed");
+    assert_eq!(body, "Bearer sesame");
+}
+
+#[test]
+#[cfg(feature = "reqwest-rustls-tls")]
+fn servers_signed_by_a_private_ca_are_not_trusted_without_its_bundle() {
+    let pki = Pki::new();
+    let addr = pki.serve(false);
+
+    let backend = Backend::Reqwest(TlsBackend::Rustls);
+    let e = fetch(backend, addr, None).expect_err("Test download succeeded");
+    // Nothing about it suggests trying again would help
+    assert!(
+        !matches!(e.kind(), ErrorKind::ConnectionFailed | ErrorKind::TimedOut),
+        "{}",
+        e
+    );
+}
+
+#[test]
+#[cfg(feature = "curl-backend")]
+fn curl_does_not_trust_servers_signed_by_a_private_ca_without_its_bundle() {
+    let pki = Pki::new();
+    let addr = pki.serve(false);
+
+    let e = fetch(Backend::Curl, addr, None).expect_err("Test download succeeded");
+    assert!(
+        !matches!(e.kind(), ErrorKind::ConnectionFailed | ErrorKind::TimedOut),
+        "{}",
+        e
+    );
+}
+
+#[test]
+#[cfg(feature = "reqwest-rustls-tls")]
+fn servers_asking_for_a_client_certificate_refuse_requests_without_one() {
+    let pki = Pki::new();
+    let addr = pki.serve(true);
+    let host = pki.host_config(false);
+
+    let backend = Backend::Reqwest(TlsBackend::Rustls);
+    fetch(backend, addr, Some(&host)).expect_err("Test download succeeded");
+}
+
+#[test]
+#[cfg(feature = "reqwest-default-tls")]
+fn default_tls_trusts_the_ca_bundle() {
+    let pki = Pki::new();
+    let addr = pki.serve(false);
+    let host = pki.host_config(false);
+
+    let backend = Backend::Reqwest(TlsBackend::Default);
+    let body = fetch(backend, addr, Some(&host)).expect("Test download failed");
+    assert_eq!(body, "Bearer sesame");
+}
+
+#[test]
+#[cfg(feature = "reqwest-default-tls")]
+fn default_tls_needs_a_pkcs12_client_certificate() {
+    let pki = Pki::new();
+    let addr = pki.serve(true);
+    let host = pki.host_config(true);
+
+    let backend = Backend::Reqwest(TlsBackend::Default);
+    let e = fetch(backend, addr, Some(&host)).expect_err("Test download succeeded");
+    match e.kind() {
+        ErrorKind::ClientCertificateFormat(path, _, _) => assert_eq!(*path, pki.path("client.pem")),
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
@@ -0,0 +1,252 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use url::Url;
+
+use download::*;
+
+mod support;
+use crate::support::{
+    serve_authorized, serve_file, serve_file_counting_connections, serve_redirect, serve_trickle,
+    serve_unless_header, tmp_dir,
+};
+
+// Every backend built in, for each test to be run with
+fn backends() -> Vec<Backend> {
+    let mut backends = Vec::new();
+    if cfg!(feature = "curl-backend") {
+        backends.push(Backend::Curl);
+    }
+    if cfg!(all(
+        feature = "reqwest-backend",
+        feature = "reqwest-default-tls"
+    )) {
+        backends.push(Backend::Reqwest(TlsBackend::Default));
+    }
+    if cfg!(all(
+        feature = "reqwest-backend",
+        feature = "reqwest-rustls-tls"
+    )) {
+        backends.push(Backend::Reqwest(TlsBackend::Rustls));
+    }
+    backends
+}
+
+fn url(addr: std::net::SocketAddr, path: &str) -> Url {
+    format!("http://{}/{}", addr, path).parse().unwrap()
+}
+
+#[test]
+fn downloads_to_a_path() {
+    let addr = serve_file(b"12345".to_vec());
+    for backend in backends() {
+        let tmpdir = tmp_dir();
+        let path = tmpdir.path().join("downloaded");
+        let downloader = Downloader::new(backend);
+
+        assert_eq!(
+            downloader.content_length(&url(addr, "file")).unwrap(),
+            Some(5),
+            "{:?}",
+            backend
+        );
+        downloader
+            .download_to_path(&url(addr, "file"), &path, false, None)
+            .expect("Test download failed");
+        assert_eq!(std::fs::read(&path).unwrap(), b"12345", "{:?}", backend);
+    }
+}
+
+// Each host is sent only its own headers, even when one redirects to
+// another.
+#[test]
+fn redirects_to_another_host_are_not_sent_its_headers() {
+    let private_addr = serve_unless_header("x-token", b"12345".to_vec());
+    let other_addr = serve_authorized("Bearer other", b"12345".to_vec());
+    for (to, addr) in &[("private", private_addr), ("other", other_addr)] {
+        // The same server, but by another name, and so another host
+        let location = format!("http://localhost:{}/file", addr.port());
+        let redirect_addr = serve_redirect(location);
+        for backend in backends() {
+            let mut hosts = HostConfigs::new();
+            hosts
+                .entry("127.0.0.1")
+                .headers
+                .push(("X-Token".to_owned(), "sesame".to_owned()));
+            if *to == "other" {
+                hosts
+                    .entry("localhost")
+                    .headers
+                    .push(("Authorization".to_owned(), "Bearer other".to_owned()));
+            }
+            let downloader = Downloader::new(backend).hosts(hosts);
+
+            let received = Mutex::new(Vec::new());
+            downloader
+                .download(&url(redirect_addr, "file"), 0, &|event| {
+                    if let Event::DownloadDataReceived(data) = event {
+                        received.lock().unwrap().extend_from_slice(data);
+                    }
+                    Ok(())
+                })
+                .unwrap_or_else(|e| panic!("{:?} to {}: {}", backend, to, e));
+            assert_eq!(received.into_inner().unwrap(), b"12345", "{:?}", backend);
+            assert_eq!(
+                downloader
+                    .content_length(&url(redirect_addr, "file"))
+                    .unwrap(),
+                Some(5),
+                "{:?}",
+                backend
+            );
+        }
+    }
+}
+
+#[test]
+fn connections_are_reused() {
+    for backend in backends() {
+        let (addr, connections) = serve_file_counting_connections(b"12345".to_vec());
+        let downloader = Downloader::new(backend);
+
+        for _ in 0..3 {
+            downloader
+                .download(&url(addr, "file"), 0, &|_| Ok(()))
+                .expect("Test download failed");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1, "{:?}", backend);
+    }
+}
+
+#[test]
+fn downloads_with_the_same_options_share_connections() {
+    for backend in backends() {
+        let (addr, connections) = serve_file_counting_connections(b"12345".to_vec());
+        let options = Options {
+            timeouts: Timeouts {
+                connect: Duration::from_secs(10),
+                ..Timeouts::default()
+            },
+            ..Options::default()
+        };
+
+        for _ in 0..3 {
+            download_with_backend(backend, &url(addr, "file"), 0, &options, &|_| Ok(()))
+                .expect("Test download failed");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1, "{:?}", backend);
+    }
+}
+
+#[test]
+fn headers_are_sent_to_every_host() {
+    let addr = serve_authorized("Bearer t", b"12345".to_vec());
+    for backend in backends() {
+        let downloader = Downloader::new(backend).header("Authorization", "Bearer t");
+        let received = Mutex::new(Vec::new());
+        downloader
+            .download(&url(addr, "file"), 0, &|event| {
+                if let Event::DownloadDataReceived(data) = event {
+                    received.lock().unwrap().extend_from_slice(data);
+                }
+                Ok(())
+            })
+            .expect("Test download failed");
+        assert_eq!(received.into_inner().unwrap(), b"12345", "{:?}", backend);
+    }
+}
+
+#[test]
+fn batch_is_fetched_concurrently() {
+    let contents: Vec<Vec<u8>> = (0..5u8).map(|i| vec![b'a' + i; 1000]).collect();
+    let addrs: Vec<_> = contents.iter().cloned().map(serve_file).collect();
+    for backend in backends() {
+        let tmpdir = tmp_dir();
+        let fetches: Vec<_> = addrs
+            .iter()
+            .enumerate()
+            .map(|(i, &addr)| Fetch {
+                url: url(addr, "file"),
+                path: tmpdir.path().join(i.to_string()),
+                resume_from_partial: false,
+            })
+            .collect();
+        let received = Mutex::new(vec![0; fetches.len()]);
+
+        let results = Downloader::new(backend).concurrency(3).download_all(
+            &fetches,
+            &AtomicBool::new(false),
+            &|index, event| {
+                if let Event::DownloadDataReceived(data) = event {
+                    received.lock().unwrap()[index] += data.len();
+                }
+                Ok(())
+            },
+        );
+
+        assert_eq!(received.into_inner().unwrap(), vec![1000; fetches.len()]);
+        for ((result, fetch), contents) in results.iter().zip(&fetches).zip(&contents) {
+            assert!(result.is_ok(), "{:?}: {:?}", backend, result);
+            assert_eq!(&std::fs::read(&fetch.path).unwrap(), contents);
+        }
+    }
+}
+
+#[test]
+fn cancelled_batch_stops() {
+    let addr = serve_trickle(b"12345".to_vec(), 1, Duration::from_millis(100));
+    for backend in backends() {
+        let tmpdir = tmp_dir();
+        let fetches: Vec<_> = (0..3)
+            .map(|i| Fetch {
+                url: url(addr, "file"),
+                path: tmpdir.path().join(i.to_string()),
+                resume_from_partial: false,
+            })
+            .collect();
+        let cancel = AtomicBool::new(false);
+
+        let results = Downloader::new(backend).download_all(&fetches, &cancel, &|_, event| {
+            if let Event::DownloadDataReceived(_) = event {
+                cancel.store(true, Ordering::SeqCst);
+            }
+            Ok(())
+        });
+
+        for (result, fetch) in results.iter().zip(&fetches) {
+            match result {
+                Err(e) if matches!(e.kind(), ErrorKind::Cancelled) => {}
+                _ => panic!(
+                    "{:?}: expected the fetch to be cancelled: {:?}",
+                    backend, result
+                ),
+            }
+            assert!(!fetch.path.exists());
+        }
+    }
+}
+
+#[test]
+fn timed_out_download_is_kept_to_resume() {
+    let slow = serve_trickle(b"12345".to_vec(), 2, Duration::from_secs(5));
+    let addr = serve_file(b"xx345".to_vec());
+    for backend in backends() {
+        let tmpdir = tmp_dir();
+        let path = tmpdir.path().join("downloaded");
+        let downloader = Downloader::new(backend).timeouts(Timeouts {
+            idle: Duration::from_secs(1),
+            ..Timeouts::default()
+        });
+
+        downloader
+            .download_to_path(&url(slow, "file"), &path, true, None)
+            .expect_err("Test download succeeded");
+        assert_eq!(std::fs::read(&path).unwrap(), b"12", "{:?}", backend);
+
+        downloader
+            .download_to_path(&url(addr, "file"), &path, true, None)
+            .expect("Test download failed");
+        assert_eq!(std::fs::read(&path).unwrap(), b"12345", "{:?}", backend);
+    }
+}
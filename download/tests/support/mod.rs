@@ -1,14 +1,20 @@
+// Each test file uses only some of these
+#![allow(dead_code)]
+
 use std::convert::Infallible;
 use std::fs;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request};
+use hyper::{Body, Request, Response};
 use tempfile::TempDir;
 
 pub fn tmp_dir() -> TempDir {
@@ -31,16 +37,21 @@ pub fn write_file(path: &Path, contents: &str) {
     file.sync_data().expect("writing test data");
 }
 
-async fn run_server(addr_tx: Sender<SocketAddr>, addr: SocketAddr, contents: Vec<u8>) {
-    let make_svc = make_service_fn(|_: &AddrStream| {
-        let contents = contents.clone();
+async fn run_server<F>(
+    addr_tx: Sender<SocketAddr>,
+    addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    respond: F,
+) where
+    F: Fn(Request<Body>) -> Response<Body> + Clone + Send + Sync + 'static,
+{
+    let make_svc = make_service_fn(move |_: &AddrStream| {
+        connections.fetch_add(1, Ordering::SeqCst);
+        let respond = respond.clone();
         async move {
             Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                let contents = contents.clone();
-                async move {
-                    let res = serve_contents(req, contents);
-                    Ok::<_, Infallible>(res)
-                }
+                let res = respond(req);
+                async move { Ok::<_, Infallible>(res) }
             }))
         }
     });
@@ -54,26 +65,227 @@ async fn run_server(addr_tx: Sender<SocketAddr>, addr: SocketAddr, contents: Vec
     }
 }
 
-pub fn serve_file(contents: Vec<u8>) -> SocketAddr {
+fn serve<F>(respond: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> Response<Body> + Clone + Send + Sync + 'static,
+{
+    serve_counting_connections(respond).0
+}
+
+// Serves as `serve` does, counting the connections made to the server.
+fn serve_counting_connections<F>(respond: F) -> (SocketAddr, Arc<AtomicUsize>)
+where
+    F: Fn(Request<Body>) -> Response<Body> + Clone + Send + Sync + 'static,
+{
     let addr = ([127, 0, 0, 1], 0).into();
     let (addr_tx, addr_rx) = channel();
+    let connections = Arc::new(AtomicUsize::new(0));
 
+    let server_connections = connections.clone();
     thread::spawn(move || {
-        let server = run_server(addr_tx, addr, contents);
+        let server = run_server(addr_tx, addr, server_connections, respond);
         let mut rt = tokio::runtime::Runtime::new().expect("could not creating Runtime");
         rt.block_on(server);
     });
 
     let addr = addr_rx.recv();
-    addr.unwrap()
+    (addr.unwrap(), connections)
+}
+
+pub fn serve_file(contents: Vec<u8>) -> SocketAddr {
+    serve(move |req| serve_contents(req, contents.clone(), None))
+}
+
+/// Serve `contents` as `serve_file` does, counting the connections made to
+/// the server.
+pub fn serve_file_counting_connections(contents: Vec<u8>) -> (SocketAddr, Arc<AtomicUsize>) {
+    serve_counting_connections(move |req| serve_contents(req, contents.clone(), None))
+}
+
+/// Serve `contents` as the version of the file with the given `ETag`,
+/// sending all of it to requests to resume a download of another version,
+/// and none of it to requests for the file only if it's changed since
+/// that version.
+pub fn serve_versioned(etag: &'static str, contents: Vec<u8>) -> SocketAddr {
+    serve(move |req| serve_contents(req, contents.clone(), Some(etag)))
+}
+
+/// Serve all of `contents` to every request, as servers that don't do
+/// ranges do.
+pub fn serve_ignoring_ranges(contents: Vec<u8>) -> SocketAddr {
+    serve(move |_| {
+        hyper::Response::builder()
+            .header(hyper::header::CONTENT_LENGTH, contents.len())
+            .body(hyper::Body::from(contents.clone()))
+            .unwrap()
+    })
+}
+
+/// Serve every request with an empty response with the given status and,
+/// if given, `Retry-After` header.
+pub fn serve_status(status: u16, retry_after: Option<&'static str>) -> SocketAddr {
+    serve(move |_| {
+        let mut res = Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            res = res.header(hyper::header::RETRY_AFTER, retry_after);
+        }
+        res.body(Body::empty()).unwrap()
+    })
+}
+
+/// Serve `contents` to requests with the given `Authorization` header,
+/// rejecting any others as unauthorized.
+pub fn serve_authorized(authorization: &'static str, contents: Vec<u8>) -> SocketAddr {
+    serve(
+        move |req| match req.headers().get(hyper::header::AUTHORIZATION) {
+            Some(value) if value == authorization => serve_contents(req, contents.clone(), None),
+            _ => Response::builder()
+                .status(hyper::StatusCode::UNAUTHORIZED)
+                .body(Body::from("who are you?"))
+                .unwrap(),
+        },
+    )
+}
+
+/// Serve `contents` to requests without the header `name`, and refuse
+/// those with it.
+pub fn serve_unless_header(name: &'static str, contents: Vec<u8>) -> SocketAddr {
+    serve(move |req| match req.headers().get(name) {
+        None => serve_contents(req, contents.clone(), None),
+        Some(_) => Response::builder()
+            .status(hyper::StatusCode::BAD_REQUEST)
+            .body(Body::from("that's not for us"))
+            .unwrap(),
+    })
+}
+
+/// Redirect every request to `location`.
+pub fn serve_redirect(location: String) -> SocketAddr {
+    serve(move |_| {
+        Response::builder()
+            .status(hyper::StatusCode::FOUND)
+            .header(hyper::header::LOCATION, location.as_str())
+            .body(Body::empty())
+            .unwrap()
+    })
+}
+
+/// Serve `contents` as a proxy would serve a file from any server, if the
+/// request is for one and has the given `Proxy-Authorization` header, if
+/// any is given.
+pub fn serve_proxy(authorization: Option<&'static str>, contents: Vec<u8>) -> SocketAddr {
+    serve(move |req| {
+        let authorized = authorization.map_or(true, |authorization| {
+            req.headers()
+                .get(hyper::header::PROXY_AUTHORIZATION)
+                .map_or(false, |value| value == authorization)
+        });
+        if !authorized {
+            Response::builder()
+                .status(hyper::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .body(Body::empty())
+                .unwrap()
+        } else if req.uri().authority().is_none() {
+            // Only requests to proxies name the server
+            Response::builder()
+                .status(hyper::StatusCode::BAD_REQUEST)
+                .body(Body::from("not a proxy request"))
+                .unwrap()
+        } else {
+            serve_contents(req, contents.clone(), None)
+        }
+    })
+}
+
+/// Serve `contents` a `chunk` of bytes at a time, pausing for `pause`
+/// before each but the first, as a slow or unresponsive server would.
+pub fn serve_trickle(contents: Vec<u8>, chunk: usize, pause: Duration) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let contents = contents.clone();
+            // Clients giving up partway through is what some tests are after
+            thread::spawn(move || trickle(stream, &contents, chunk, pause));
+        }
+    });
+    addr
+}
+
+fn trickle(
+    mut stream: TcpStream,
+    contents: &[u8],
+    chunk: usize,
+    pause: Duration,
+) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = io::Read::read(&mut stream, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        contents.len()
+    );
+    io::Write::write_all(&mut stream, head.as_bytes())?;
+    for (i, data) in contents.chunks(chunk).enumerate() {
+        if i > 0 {
+            thread::sleep(pause);
+        }
+        io::Write::write_all(&mut stream, data)?;
+        io::Write::flush(&mut stream)?;
+    }
+    Ok(())
+}
+
+/// Hands out the `Authorization` header values it was made with in turn,
+/// counting how many it has.
+pub struct Tokens(Mutex<Vec<&'static str>>);
+
+impl Tokens {
+    pub fn new(tokens: &[&'static str]) -> Self {
+        Self(Mutex::new(tokens.iter().rev().cloned().collect()))
+    }
+}
+
+impl download::CredentialProvider for Tokens {
+    fn authorization(&self) -> download::Result<Option<String>> {
+        let token = self.0.lock().unwrap().pop().expect("too many fetches");
+        Ok(Some(token.to_owned()))
+    }
 }
 
 fn serve_contents(
     req: hyper::Request<hyper::Body>,
     contents: Vec<u8>,
+    etag: Option<&'static str>,
 ) -> hyper::Response<hyper::Body> {
+    if let (Some(etag), Some(if_none_match)) =
+        (etag, req.headers().get(hyper::header::IF_NONE_MATCH))
+    {
+        if if_none_match == etag {
+            return hyper::Response::builder()
+                .status(hyper::StatusCode::NOT_MODIFIED)
+                .header(hyper::header::ETAG, etag)
+                .body(hyper::Body::empty())
+                .unwrap();
+        }
+    }
+
+    // A range is only of the version `If-Range` names, if given
+    let range = req.headers().get(hyper::header::RANGE).filter(|_| {
+        match req.headers().get(hyper::header::IF_RANGE) {
+            Some(if_range) => etag.map_or(false, |etag| if_range == etag),
+            None => true,
+        }
+    });
     let mut range_header = None;
-    let (status, body) = if let Some(range) = req.headers().get(hyper::header::RANGE) {
+    let (status, body) = if let Some(range) = range {
         // extract range "bytes={start}-"
         let range = range.to_str().expect("unexpected Range header");
         assert!(range.starts_with("bytes="));
@@ -82,6 +294,16 @@ fn serve_contents(
         let range = range.trim_end_matches('-');
         assert_eq!(range.split('-').count(), 1);
         let start: u64 = range.parse().expect("unexpected Range header");
+        if start >= contents.len() as u64 {
+            return hyper::Response::builder()
+                .status(hyper::StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(
+                    hyper::header::CONTENT_RANGE,
+                    format!("bytes */{}", contents.len()),
+                )
+                .body(hyper::Body::empty())
+                .unwrap();
+        }
 
         range_header = Some(format!("bytes {}-{len}/{len}", start, len = contents.len()));
         (
@@ -101,5 +323,9 @@ fn serve_contents(
         res.headers_mut()
             .insert(hyper::header::CONTENT_RANGE, range.parse().unwrap());
     }
+    if let Some(etag) = etag {
+        res.headers_mut()
+            .insert(hyper::header::ETAG, etag.parse().unwrap());
+    }
     res
 }
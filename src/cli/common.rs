@@ -8,18 +8,22 @@ use std::{cmp, env, iter};
 
 use git_testament::{git_testament, render_testament};
 use lazy_static::lazy_static;
+use serde::Serialize;
 use term2::Terminal;
 
 use super::errors::*;
 use super::self_update;
 use super::term2;
+use crate::dist::manifestation::UpdatePlan;
 use crate::dist::notifications as dist_notifications;
+use crate::dist::report::{ComponentChange, UpdateReport};
 use crate::process;
 use crate::toolchain::DistributableToolchain;
 use crate::utils::notifications as util_notifications;
 use crate::utils::notify::NotificationLevel;
+use crate::utils::units::{Size, Unit, UnitMode};
 use crate::utils::utils;
-use crate::{Cfg, Notification, Toolchain, UpdateStatus};
+use crate::{Cfg, Notification, SelfUpdateMode, Toolchain, UpdateStatus};
 
 pub const WARN_COMPLETE_PROFILE: &str = "downloading with complete profile isn't recommended unless you are a developer of the rust language";
 
@@ -175,14 +179,15 @@ pub fn show_channel_update(
     name: &str,
     updated: crate::Result<UpdateStatus>,
 ) -> Result<()> {
-    show_channel_updates(cfg, vec![(name.to_string(), updated)])
+    show_channel_updates(cfg, &[(name.to_string(), updated)])
 }
 
 fn show_channel_updates(
     cfg: &Cfg,
-    toolchains: Vec<(String, crate::Result<UpdateStatus>)>,
+    toolchains: &[(String, crate::Result<UpdateStatus>)],
 ) -> Result<()> {
-    let data = toolchains.into_iter().map(|(name, result)| {
+    let data = toolchains.iter().map(|(name, result)| {
+        let name = name.clone();
         let toolchain = cfg.get_toolchain(&name, false).unwrap();
         let mut version: String = toolchain.rustc_version();
 
@@ -194,12 +199,12 @@ fn show_channel_updates(
                 banner = "installed";
                 color = Some(term2::color::GREEN);
             }
-            Ok(UpdateStatus::Updated(v)) => {
+            Ok(UpdateStatus::Updated(v, _)) => {
                 if name == "rustup" {
                     previous_version = Some(env!("CARGO_PKG_VERSION").into());
-                    version = v;
+                    version = v.clone();
                 } else {
-                    previous_version = Some(v);
+                    previous_version = Some(v.clone());
                 }
                 banner = "updated";
                 color = Some(term2::color::GREEN);
@@ -211,6 +216,10 @@ fn show_channel_updates(
                 banner = "unchanged";
                 color = None;
             }
+            Ok(UpdateStatus::Planned(_)) => {
+                banner = "changes planned";
+                color = Some(term2::color::YELLOW);
+            }
             Err(_) => {
                 banner = "update failed";
                 color = Some(term2::color::RED);
@@ -248,6 +257,162 @@ fn show_channel_updates(
     }
     let _ = writeln!(t);
 
+    for (name, result) in toolchains {
+        if let Ok(UpdateStatus::Updated(_, report)) = result {
+            if report.has_changes() {
+                show_component_changes(name, report)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Print how an update changed the components of `name`.
+fn show_component_changes(name: &str, report: &UpdateReport) -> Result<()> {
+    let mut t = term2::stdout();
+    let _ = t.attr(term2::Attr::Bold);
+    writeln!(t, "component changes for '{}'", name)?;
+    let _ = t.reset();
+
+    let version = |v: &Option<String>| v.clone().unwrap_or_default();
+    for change in &report.components {
+        match change {
+            ComponentChange::Unchanged { .. } => {}
+            ComponentChange::Updated {
+                component,
+                old_version,
+                new_version,
+            } => writeln!(
+                t,
+                "  updated   {} {} -> {}",
+                component,
+                version(old_version),
+                version(new_version)
+            )?,
+            ComponentChange::Renamed {
+                old_component,
+                new_component,
+                old_version,
+                new_version,
+            } => writeln!(
+                t,
+                "  renamed   {} -> {} {} -> {}",
+                old_component,
+                new_component,
+                version(old_version),
+                version(new_version)
+            )?,
+            ComponentChange::Added {
+                component,
+                version: v,
+            } => writeln!(t, "  added     {} {}", component, version(v))?,
+            ComponentChange::Removed {
+                component,
+                version: v,
+            } => writeln!(t, "  removed   {} {}", component, version(v))?,
+            ComponentChange::Dropped {
+                component,
+                version: v,
+            } => writeln!(
+                t,
+                "  dropped   {} {} (no longer available)",
+                component,
+                version(v)
+            )?,
+        }
+    }
+    writeln!(t)?;
+    Ok(())
+}
+
+/// Write the outcome of updating `toolchains` to `path` as JSON.
+pub fn write_update_report(
+    path: &Path,
+    toolchains: &[(String, crate::Result<UpdateStatus>)],
+) -> Result<()> {
+    #[derive(Serialize)]
+    struct ToolchainReport<'a> {
+        name: &'a str,
+        status: &'static str,
+        components: &'a [ComponentChange],
+    }
+
+    #[derive(Serialize)]
+    struct Report<'a> {
+        toolchains: Vec<ToolchainReport<'a>>,
+    }
+
+    let toolchains = toolchains
+        .iter()
+        .map(|(name, result)| {
+            let (status, components) = match result {
+                Ok(UpdateStatus::Installed) => ("installed", &[][..]),
+                Ok(UpdateStatus::Updated(_, report)) => ("updated", report.components.as_slice()),
+                Ok(UpdateStatus::Unchanged) => ("unchanged", &[][..]),
+                Ok(UpdateStatus::Planned(_)) => ("planned", &[][..]),
+                Err(_) => ("failed", &[][..]),
+            };
+            ToolchainReport {
+                name,
+                status,
+                components,
+            }
+        })
+        .collect();
+
+    let json = serde_json::to_string_pretty(&Report { toolchains })?;
+    utils::write_file("update report", path, &json)?;
+    Ok(())
+}
+
+/// Print the changes a dry run found an update of `name` would make.
+pub fn show_update_plan(name: &str, plan: &UpdatePlan) -> Result<()> {
+    let mut t = term2::stdout();
+    let _ = t.attr(term2::Attr::Bold);
+    write!(t, "planned changes for '{}'", name)?;
+    let _ = t.reset();
+    match &plan.old_date {
+        Some(old_date) if *old_date != plan.new_date => {
+            writeln!(t, " ({} -> {})", old_date, plan.new_date)?
+        }
+        _ => writeln!(t, " ({})", plan.new_date)?,
+    }
+
+    for change in &plan.changes {
+        let (action, version) = match (&change.old_version, &change.new_version) {
+            _ if change.download.is_none() => {
+                ("remove", change.old_version.clone().unwrap_or_default())
+            }
+            (Some(old), Some(new)) if old != new => ("update", format!("{} -> {}", old, new)),
+            (Some(_), new) => ("reinstall", new.clone().unwrap_or_default()),
+            (None, new) => ("install", new.clone().unwrap_or_default()),
+        };
+        writeln!(t, "  {:<9} {} {}", action, change.component, version)?;
+        if let Some(download) = &change.download {
+            let note = if download.cached {
+                " (cached)".to_owned()
+            } else if let Some(size) = download.size {
+                let size = Size::new(size as usize, Unit::B, UnitMode::Norm).to_string();
+                format!(" ({})", size.trim())
+            } else {
+                String::new()
+            };
+            writeln!(t, "            {}{}", download.url, note)?;
+        }
+    }
+
+    let (size, all_known) = plan.download_size();
+    let size = Size::new(size as usize, Unit::B, UnitMode::Norm).to_string();
+    if all_known {
+        writeln!(t, "  total download size: {}", size.trim())?;
+    } else {
+        writeln!(
+            t,
+            "  total download size: at least {} (the server didn't report them all)",
+            size.trim()
+        )?;
+    }
     Ok(())
 }
 
@@ -255,24 +420,40 @@ pub fn update_all_channels(
     cfg: &Cfg,
     do_self_update: bool,
     force_update: bool,
+    dry_run: bool,
+    json_report: Option<&Path>,
 ) -> Result<utils::ExitCode> {
-    let toolchains = cfg.update_all_channels(force_update)?;
+    let toolchains = cfg.update_all_channels(force_update, dry_run)?;
 
     if toolchains.is_empty() {
         info!("no updatable toolchains installed");
     }
 
+    if dry_run {
+        for (name, status) in &toolchains {
+            match status {
+                Ok(UpdateStatus::Planned(plan)) => show_update_plan(name, plan)?,
+                Ok(_) => writeln!(process().stdout(), "no changes planned for '{}'", name)?,
+                Err(_) => {}
+            }
+        }
+        return Ok(utils::ExitCode(0));
+    }
+
     let show_channel_updates = || {
         if !toolchains.is_empty() {
             writeln!(process().stdout())?;
 
-            show_channel_updates(cfg, toolchains)?;
+            show_channel_updates(cfg, &toolchains)?;
+        }
+        if let Some(path) = json_report {
+            write_update_report(path, &toolchains)?;
         }
         Ok(utils::ExitCode(0))
     };
 
     if do_self_update {
-        self_update(show_channel_updates)
+        self_update(cfg, show_channel_updates)
     } else {
         show_channel_updates()
     }
@@ -282,10 +463,20 @@ pub fn update_all_channels(
 pub enum SelfUpdatePermission {
     HardFail,
     Skip,
+    /// Only say whether there's an update
+    CheckOnly,
     Permit,
 }
 
-pub fn self_update_permitted(explicit: bool) -> Result<SelfUpdatePermission> {
+pub fn self_update_permitted(cfg: &Cfg, explicit: bool) -> Result<SelfUpdatePermission> {
+    // Asking for a self-update outright is never held back by the setting
+    if !explicit {
+        match cfg.get_self_update_mode()? {
+            SelfUpdateMode::Disable => return Ok(SelfUpdatePermission::Skip),
+            SelfUpdateMode::CheckOnly => return Ok(SelfUpdatePermission::CheckOnly),
+            SelfUpdateMode::Enable => {}
+        }
+    }
     if cfg!(windows) {
         Ok(SelfUpdatePermission::Permit)
     } else {
@@ -333,20 +524,25 @@ pub fn self_update_permitted(explicit: bool) -> Result<SelfUpdatePermission> {
     }
 }
 
-pub fn self_update<F>(before_restart: F) -> Result<utils::ExitCode>
+pub fn self_update<F>(cfg: &Cfg, before_restart: F) -> Result<utils::ExitCode>
 where
     F: FnOnce() -> Result<utils::ExitCode>,
 {
-    match self_update_permitted(false)? {
+    match self_update_permitted(cfg, false)? {
         SelfUpdatePermission::HardFail => {
             err!("Unable to self-update.  STOP");
             return Ok(utils::ExitCode(1));
         }
         SelfUpdatePermission::Skip => return Ok(utils::ExitCode(0)),
+        SelfUpdatePermission::CheckOnly => {
+            before_restart()?;
+            check_rustup_update(cfg)?;
+            return Ok(utils::ExitCode(0));
+        }
         SelfUpdatePermission::Permit => {}
     }
 
-    let setup_path = self_update::prepare_update()?;
+    let setup_path = self_update::prepare_update(cfg, None)?;
 
     before_restart()?;
 
@@ -360,6 +556,20 @@ where
     Ok(utils::ExitCode(0))
 }
 
+/// Says whether there's a version of rustup to update to, without updating
+/// to it.
+pub fn check_rustup_update(cfg: &Cfg) -> Result<()> {
+    let current_version = env!("CARGO_PKG_VERSION");
+    let available_version = self_update::get_target_rustup_version(cfg)?;
+    if available_version != current_version {
+        info!(
+            "rustup {} is available (this is {}); run `rustup self update` to install it",
+            available_version, current_version
+        );
+    }
+    Ok(())
+}
+
 pub fn list_targets(toolchain: &Toolchain<'_>) -> Result<utils::ExitCode> {
     let mut t = term2::stdout();
     let distributable = DistributableToolchain::new(&toolchain)
@@ -467,6 +677,20 @@ fn print_toolchain_path(
         if_override,
         toolchain_path
     )?;
+    if verbose {
+        let toolchain = cfg.get_toolchain(toolchain, false)?;
+        if let Ok(distributable) = DistributableToolchain::new(&toolchain) {
+            for date in distributable.retained_versions()? {
+                let path = cfg.retained_dir.join(toolchain.name()).join(&date);
+                writeln!(
+                    process().stdout(),
+                    "  {} (retained)\t{}",
+                    date,
+                    path.display()
+                )?;
+            }
+        }
+    }
     Ok(())
 }
 
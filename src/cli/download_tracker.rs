@@ -7,6 +7,7 @@ use term::Terminal;
 
 use super::term2;
 use crate::dist::Notification as In;
+use crate::utils::notify::NotificationLevel;
 use crate::utils::tty;
 use crate::utils::units::{Size, Unit, UnitMode};
 use crate::utils::Notification as Un;
@@ -15,6 +16,17 @@ use crate::Notification;
 /// Keep track of this many past download amounts
 const DOWNLOAD_TRACK_COUNT: usize = 5;
 
+/// Show that downloading is throttled for this long after it last was
+const THROTTLED_DISPLAY_TIME: Duration = Duration::from_secs(2);
+
+/// Progress of one of a set of concurrent downloads.
+struct FileProgress {
+    name: String,
+    content_len: Option<usize>,
+    downloaded: usize,
+    finished: bool,
+}
+
 /// Tracks download progress and displays information about it to a terminal.
 pub struct DownloadTracker {
     /// Content-Length of the to-be downloaded object.
@@ -31,6 +43,9 @@ pub struct DownloadTracker {
     last_sec: Option<Instant>,
     /// Time stamp of the start of the download
     start_sec: Option<Instant>,
+    /// When downloading was last held back to keep to a maximum rate, and
+    /// that rate
+    throttled: Option<(Instant, u64)>,
     /// The terminal we write the information to.
     /// XXX: Could be a term trait, but with #1818 on the horizon that
     ///      is a pointless change to make - better to let that transition
@@ -45,6 +60,11 @@ pub struct DownloadTracker {
     /// If we have displayed progress, this is the number of characters we
     /// rendered, so we can erase it cleanly.
     displayed_charcount: Option<usize>,
+    /// The number of lines the displayed progress takes up.
+    displayed_lines: usize,
+    /// The files of a set of concurrent downloads, if we are tracking one;
+    /// the other fields then cover the set as a whole.
+    files: Vec<FileProgress>,
     /// What units to show progress in
     units: Vec<Unit>,
     /// Whether we display progress
@@ -61,8 +81,11 @@ impl DownloadTracker {
             downloaded_last_few_secs: VecDeque::with_capacity(DOWNLOAD_TRACK_COUNT),
             start_sec: None,
             last_sec: None,
+            throttled: None,
             term: term2::stdout(),
             displayed_charcount: None,
+            displayed_lines: 0,
+            files: Vec::new(),
             units: vec![Unit::B],
             display_progress: true,
         }
@@ -74,6 +97,13 @@ impl DownloadTracker {
     }
 
     pub fn handle_notification(&mut self, n: &Notification<'_>) -> bool {
+        // While a set of concurrent downloads is displayed, the progress of
+        // anything else, such as unpacking the packages already downloaded,
+        // would garble it, so is left out.
+        if !self.files.is_empty() && is_single_download_progress(n) {
+            return true;
+        }
+
         match *n {
             Notification::Install(In::Utils(Un::DownloadContentLengthReceived(content_len))) => {
                 self.content_length_received(content_len);
@@ -90,6 +120,10 @@ impl DownloadTracker {
                 self.download_finished();
                 true
             }
+            Notification::Install(In::Utils(Un::DownloadThrottled(rate))) => {
+                self.throttled = Some((Instant::now(), rate));
+                true
+            }
             Notification::Install(In::Utils(Un::DownloadPushUnit(unit))) => {
                 self.push_unit(unit);
                 true
@@ -98,8 +132,49 @@ impl DownloadTracker {
                 self.pop_unit();
                 true
             }
+            Notification::Install(In::ComponentDownloadStarted(name)) => {
+                self.file_started(name);
+                true
+            }
+            Notification::Install(In::ComponentDownloadContentLength(name, content_len)) => {
+                self.file_content_length_received(name, content_len);
+                true
+            }
+            Notification::Install(In::ComponentDownloadDataReceived(name, len)) => {
+                if let Some(file) = self.file_mut(name) {
+                    file.downloaded += len;
+                }
+                if tty::stdout_isatty() {
+                    self.data_received(len);
+                }
+                true
+            }
+            Notification::Install(In::ComponentDownloadFinished(name)) => {
+                if let Some(file) = self.file_mut(name) {
+                    file.finished = true;
+                }
+                true
+            }
+            Notification::Install(In::ComponentDownloadsFinished) => {
+                self.download_finished();
+                self.files.clear();
+                true
+            }
 
-            _ => false,
+            _ => {
+                // Anything printed now would be overwritten by the next
+                // display of a set of downloads, so leave that where it is
+                // and display it afresh below.
+                if !self.files.is_empty()
+                    && self.displayed_charcount.is_some()
+                    && !matches!(n.level(), NotificationLevel::Verbose)
+                {
+                    let _ = writeln!(self.term);
+                    self.displayed_charcount = None;
+                    self.displayed_lines = 0;
+                }
+                false
+            }
         }
     }
 
@@ -108,6 +183,37 @@ impl DownloadTracker {
         self.content_len = Some(content_len as usize);
     }
 
+    /// Notifies self that one of a set of concurrent downloads has started.
+    fn file_started(&mut self, name: &str) {
+        if self.files.is_empty() {
+            self.prepare_for_new_download();
+        }
+        self.files.push(FileProgress {
+            name: name.to_owned(),
+            content_len: None,
+            downloaded: 0,
+            finished: false,
+        });
+    }
+
+    /// Notifies self that the Content-Length of one of a set of concurrent
+    /// downloads has been received. The set's total is only known once
+    /// that of every file started so far is.
+    fn file_content_length_received(&mut self, name: &str, content_len: u64) {
+        if let Some(file) = self.file_mut(name) {
+            file.content_len = Some(content_len as usize);
+        }
+        self.content_len = self
+            .files
+            .iter()
+            .map(|f| f.content_len)
+            .sum::<Option<usize>>();
+    }
+
+    fn file_mut(&mut self, name: &str) -> Option<&mut FileProgress> {
+        self.files.iter_mut().find(|f| f.name == name)
+    }
+
     /// Notifies self that data of size `len` has been received.
     pub fn data_received(&mut self, len: usize) {
         self.total_downloaded += len;
@@ -151,7 +257,9 @@ impl DownloadTracker {
         self.downloaded_last_few_secs.clear();
         self.start_sec = Some(Instant::now());
         self.last_sec = None;
+        self.throttled = None;
         self.displayed_charcount = None;
+        self.displayed_lines = 0;
     }
     /// Display the tracked download information to the terminal.
     fn display(&mut self) {
@@ -178,16 +286,21 @@ impl DownloadTracker {
                     // This is not ideal as very narrow terminals might mess up,
                     // but it is more likely to succeed until term's windows console
                     // fixes whatever's up with delete_line().
-                    let _ = write!(self.term, "{}", " ".repeat(n));
-                    let _ = self.term.flush();
-                    let _ = self.term.carriage_return();
+                    for line in (0..self.displayed_lines).rev() {
+                        let _ = write!(self.term, "{}", " ".repeat(n));
+                        let _ = self.term.flush();
+                        let _ = self.term.carriage_return();
+                        if line > 0 {
+                            let _ = self.term.cursor_up();
+                        }
+                    }
                 }
 
-                let output = match self.content_len {
+                let mut output = match self.content_len {
                     Some(content_len) => {
                         let content_len_h = Size::new(content_len, unit, UnitMode::Norm);
                         let percent = (self.total_downloaded as f64 / content_len as f64) * 100.;
-                        let remaining = content_len - self.total_downloaded;
+                        let remaining = content_len.saturating_sub(self.total_downloaded);
                         let eta_h = Duration::from_secs(if speed == 0 {
                             std::u64::MAX
                         } else {
@@ -211,10 +324,37 @@ impl DownloadTracker {
                     ),
                 };
 
-                let _ = write!(self.term, "{}", output);
+                if let Some((_, rate)) = self
+                    .throttled
+                    .filter(|(when, _)| when.elapsed() < THROTTLED_DISPLAY_TIME)
+                {
+                    let rate_h = Size::new(rate as usize, Unit::B, UnitMode::Rate);
+                    output += &format!(" (throttled to {})", rate_h.to_string().trim_start());
+                }
+
+                // Below the totals, list the files of a set of concurrent
+                // downloads
+                let mut lines = vec![output];
+                for file in &self.files {
+                    let downloaded_h = Size::new(file.downloaded, unit, UnitMode::Norm);
+                    let progress = match (file.finished, file.content_len) {
+                        (true, _) => "done".to_owned(),
+                        (false, Some(content_len)) => format!(
+                            "{} / {} ({:3.0} %)",
+                            downloaded_h,
+                            Size::new(content_len, unit, UnitMode::Norm),
+                            (file.downloaded as f64 / content_len as f64) * 100.
+                        ),
+                        (false, None) => format!("{}", downloaded_h),
+                    };
+                    lines.push(format!("  {}: {}", file.name, progress));
+                }
+
+                let _ = write!(self.term, "{}", lines.join("\n"));
                 // Since stdout is typically line-buffered and we don't print a newline, we manually flush.
                 let _ = self.term.flush();
-                self.displayed_charcount = Some(output.chars().count());
+                self.displayed_charcount = lines.iter().map(|l| l.chars().count()).max();
+                self.displayed_lines = lines.len();
             }
         }
     }
@@ -228,6 +368,17 @@ impl DownloadTracker {
     }
 }
 
+fn is_single_download_progress(n: &Notification<'_>) -> bool {
+    match n {
+        Notification::Install(In::Utils(Un::DownloadContentLengthReceived(_)))
+        | Notification::Install(In::Utils(Un::DownloadDataReceived(_)))
+        | Notification::Install(In::Utils(Un::DownloadFinished))
+        | Notification::Install(In::Utils(Un::DownloadPushUnit(_)))
+        | Notification::Install(In::Utils(Un::DownloadPopUnit)) => true,
+        _ => false,
+    }
+}
+
 trait DurationDisplay {
     fn display(self) -> Display;
 }
@@ -24,6 +24,7 @@ error_chain! {
         Temp(temp::Error);
         Io(io::Error);
         Term(term::Error);
+        Json(serde_json::Error);
     }
 
     errors {
@@ -51,6 +52,9 @@ error_chain! {
             description("rustup is not installed")
             display("rustup is not installed at '{}'", p.display())
         }
+        NoRustupBackup {
+            description("no previous version of rustup to roll back to")
+        }
         WindowsUninstallMadness {
             description("failure during windows uninstall")
         }
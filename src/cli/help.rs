@@ -104,6 +104,25 @@ pub static TOOLCHAIN_LINK_HELP: &str = r"DISCUSSION:
     If you now compile a crate in the current directory, the custom
     toolchain 'latest-stage1' will be used.";
 
+pub static TOOLCHAIN_ROLLBACK_HELP: &str = r"DISCUSSION:
+    When `rustup set retain-versions` is set to a number greater than
+    zero, updating a toolchain that tracks a release channel, such as
+    'nightly', keeps a copy of the version being replaced. Up to that
+    many previous versions are kept, named by the date of their
+    release. They are listed by `rustup toolchain list --verbose`.
+
+    If an update breaks your build, you can go back to the version you
+    had before:
+
+        $ rustup toolchain rollback nightly
+
+    Or to a particular earlier version:
+
+        $ rustup toolchain rollback nightly --to 2019-11-04
+
+    The version rolled back from is itself retained, and the next
+    `rustup update` updates the toolchain as usual.";
+
 pub static OVERRIDE_HELP: &str = r"DISCUSSION:
     Overrides configure rustup to use a specific toolchain when
     running in a specific directory.
@@ -282,3 +301,6 @@ pub static TOPIC_ARG_HELP: &str = "Topic such as 'core', 'fn', 'usize', 'eprintl
                                    'core::arch', 'alloc::format!', 'std::fs', \
                                    'std::fs::read_dir', 'std::io::Bytes', \
                                    'std::iter::Sum', 'std::io::error::Result' etc...";
+
+pub static PROFILE_NAME_HELP: &str = "Profile name: 'minimal', 'default', 'complete', \
+                                      or a custom profile from the settings file";
@@ -11,7 +11,9 @@ use crate::utils::utils::{self, ExitCode};
 use crate::Cfg;
 
 pub fn main() -> Result<ExitCode> {
-    self_update::cleanup_self_updater()?;
+    // Runs of proxies don't count towards no longer keeping the rustup a
+    // self-update replaced
+    self_update::cleanup_self_updater(false)?;
 
     let ExitCode(c) = {
         let _setup = job::setup();
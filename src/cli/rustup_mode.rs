@@ -5,6 +5,7 @@ use std::iter;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
 
@@ -14,16 +15,21 @@ use super::self_update;
 use super::term2;
 use super::term2::Terminal;
 use super::topical_doc;
-use super::{common, self_update::get_available_rustup_version};
+use super::{common, self_update::get_target_rustup_version};
 use crate::dist::dist::{
     PartialTargetTriple, PartialToolchainDesc, Profile, TargetTriple, ToolchainDesc,
 };
 use crate::dist::manifest::Component;
+use crate::dist::manifestation::UpdatePlan;
 use crate::process;
 use crate::toolchain::{CustomToolchain, DistributableToolchain};
+use crate::utils::units;
 use crate::utils::utils;
 use crate::Notification;
-use crate::{command, Cfg, ComponentStatus, Toolchain};
+use crate::{
+    command, is_rustup_version, Cfg, ComponentStatus, SelfUpdateMode, Toolchain, UpdateStatus,
+    RUSTUP_CHANNELS,
+};
 
 fn handle_epipe(res: Result<utils::ExitCode>) -> Result<utils::ExitCode> {
     match res {
@@ -55,8 +61,6 @@ where
 }
 
 pub fn main() -> Result<utils::ExitCode> {
-    self_update::cleanup_self_updater()?;
-
     use clap::ErrorKind::*;
     let matches = match cli().get_matches_from_safe(process().args_os()) {
         Ok(matches) => Ok(matches),
@@ -114,6 +118,10 @@ pub fn main() -> Result<utils::ExitCode> {
             Err(e)
         }
     }?;
+    // Runs with arguments rustup can't make sense of don't count towards no
+    // longer keeping the rustup a self-update replaced
+    self_update::cleanup_self_updater(true)?;
+
     let verbose = matches.is_present("verbose");
     let quiet = matches.is_present("quiet");
     let cfg = &mut common::set_globals(verbose, quiet)?;
@@ -133,7 +141,7 @@ pub fn main() -> Result<utils::ExitCode> {
         ("show", Some(c)) => match c.subcommand() {
             ("active-toolchain", Some(_)) => handle_epipe(show_active_toolchain(cfg))?,
             ("home", Some(_)) => handle_epipe(show_rustup_home(cfg))?,
-            ("profile", Some(_)) => handle_epipe(show_profile(cfg))?,
+            ("profile", Some(m)) => handle_epipe(show_profile(cfg, m))?,
             ("keys", Some(_)) => handle_epipe(show_keys(cfg))?,
             (_, _) => handle_epipe(show(cfg))?,
         },
@@ -147,6 +155,7 @@ pub fn main() -> Result<utils::ExitCode> {
             ("list", Some(m)) => handle_epipe(toolchain_list(cfg, m))?,
             ("link", Some(m)) => toolchain_link(cfg, m)?,
             ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
+            ("rollback", Some(m)) => toolchain_rollback(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("target", Some(c)) => match c.subcommand() {
@@ -172,13 +181,33 @@ pub fn main() -> Result<utils::ExitCode> {
         ("doc", Some(m)) => doc(cfg, m)?,
         ("man", Some(m)) => man(cfg, m)?,
         ("self", Some(c)) => match c.subcommand() {
-            ("update", Some(_)) => self_update::update(cfg)?,
+            ("update", Some(m)) => self_update::update(cfg, m.value_of("version"))?,
+            ("rollback", Some(_)) => self_update::rollback(cfg)?,
             ("uninstall", Some(m)) => self_uninstall(m)?,
             (_, _) => unreachable!(),
         },
         ("set", Some(c)) => match c.subcommand() {
             ("default-host", Some(m)) => set_default_host_triple(cfg, m)?,
             ("profile", Some(m)) => set_profile(cfg, m)?,
+            ("retain-versions", Some(m)) => set_retain_versions(cfg, m)?,
+            ("max-retries", Some(m)) => set_max_retries(cfg, m)?,
+            ("retry-delay", Some(m)) => set_retry_delay(cfg, m)?,
+            ("concurrent-downloads", Some(m)) => set_concurrent_downloads(cfg, m)?,
+            ("stream-packages", Some(m)) => set_stream_packages(cfg, m)?,
+            ("max-download-rate", Some(m)) => set_max_download_rate(cfg, m)?,
+            ("connect-timeout", Some(m)) => set_connect_timeout(cfg, m)?,
+            ("idle-timeout", Some(m)) => set_idle_timeout(cfg, m)?,
+            ("min-download-rate", Some(m)) => set_min_download_rate(cfg, m)?,
+            ("stall-timeout", Some(m)) => set_stall_timeout(cfg, m)?,
+            ("manifest-cache-ttl", Some(m)) => set_manifest_cache_ttl(cfg, m)?,
+            ("rustup-channel", Some(m)) => set_rustup_channel(cfg, m)?,
+            ("pinned-rustup-version", Some(m)) => set_pinned_rustup_version(cfg, m)?,
+            ("rustup-backup-runs", Some(m)) => set_rustup_backup_runs(cfg, m)?,
+            ("auto-self-update", Some(m)) => set_auto_self_update(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("config", Some(c)) => match c.subcommand() {
+            ("proxy", Some(m)) => show_proxy(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("completions", Some(c)) => {
@@ -249,7 +278,15 @@ pub fn cli() -> App<'static, 'static> {
                     SubCommand::with_name("home")
                         .about("Display the computed value of RUSTUP_HOME"),
                 )
-                .subcommand(SubCommand::with_name("profile").about("Show the current profile"))
+                .subcommand(
+                    SubCommand::with_name("profile")
+                        .about("Show the current profile, or the one a toolchain was installed with")
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(false),
+                        ),
+                )
                 .subcommand(SubCommand::with_name("keys").about("Display the known PGP keys")),
         )
         .subcommand(
@@ -267,7 +304,6 @@ pub fn cli() -> App<'static, 'static> {
                     Arg::with_name("profile")
                         .long("profile")
                         .takes_value(true)
-                        .possible_values(Profile::names())
                         .required(false),
                 )
                 .arg(
@@ -281,6 +317,19 @@ pub fn cli() -> App<'static, 'static> {
                         .help("Force an update, even if some components are missing")
                         .long("force")
                         .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Show what would change, without changing anything")
+                        .long("dry-run")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("json-report")
+                        .help("Write a JSON report of what the update changed to FILE")
+                        .long("json-report")
+                        .value_name("FILE")
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -315,6 +364,19 @@ pub fn cli() -> App<'static, 'static> {
                         .help("Force an update, even if some components are missing")
                         .long("force")
                         .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Show what would change, without changing anything")
+                        .long("dry-run")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("json-report")
+                        .help("Write a JSON report of what the update changed to FILE")
+                        .long("json-report")
+                        .value_name("FILE")
+                        .takes_value(true),
                 ),
         )
         .subcommand(SubCommand::with_name("check").about("Check for updates to Rust toolchains and rustup"))
@@ -360,7 +422,6 @@ pub fn cli() -> App<'static, 'static> {
                             Arg::with_name("profile")
                                 .long("profile")
                                 .takes_value(true)
-                                .possible_values(Profile::names())
                                 .required(false),
                         )
                         .arg(
@@ -401,6 +462,19 @@ pub fn cli() -> App<'static, 'static> {
                                 .help("Allow rustup to downgrade the toolchain to satisfy your component choice")
                                 .long("allow-downgrade")
                                 .takes_value(false),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("Show what would change, without changing anything")
+                                .long("dry-run")
+                                .takes_value(false),
+                        )
+                        .arg(
+                            Arg::with_name("json-report")
+                                .help("Write a JSON report of what the update changed to FILE")
+                                .long("json-report")
+                                .value_name("FILE")
+                                .takes_value(true),
                         ),
                 )
                 .subcommand(
@@ -428,6 +502,22 @@ pub fn cli() -> App<'static, 'static> {
                                 .help("Path to the directory")
                                 .required(true),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Go back to a previous version of a toolchain")
+                        .after_help(TOOLCHAIN_ROLLBACK_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .help("Date of the retained version to go back to")
+                                .long("to")
+                                .takes_value(true),
+                        ),
                 ),
         )
         .subcommand(
@@ -464,6 +554,12 @@ pub fn cli() -> App<'static, 'static> {
                                 .help(TOOLCHAIN_ARG_HELP)
                                 .long("toolchain")
                                 .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("Show what would change, without changing anything")
+                                .long("dry-run")
+                                .takes_value(false),
                         ),
                 )
                 .subcommand(
@@ -476,6 +572,12 @@ pub fn cli() -> App<'static, 'static> {
                                 .help(TOOLCHAIN_ARG_HELP)
                                 .long("toolchain")
                                 .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("Show what would change, without changing anything")
+                                .long("dry-run")
+                                .takes_value(false),
                         ),
                 ),
         )
@@ -510,7 +612,13 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("toolchain")
                                 .takes_value(true),
                         )
-                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                        .arg(Arg::with_name("target").long("target").takes_value(true))
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("Show what would change, without changing anything")
+                                .long("dry-run")
+                                .takes_value(false),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("remove")
@@ -522,7 +630,13 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("toolchain")
                                 .takes_value(true),
                         )
-                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                        .arg(Arg::with_name("target").long("target").takes_value(true))
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("Show what would change, without changing anything")
+                                .long("dry-run")
+                                .takes_value(false),
+                        ),
                 ),
         )
         .subcommand(
@@ -658,7 +772,25 @@ pub fn cli() -> App<'static, 'static> {
                 .setting(AppSettings::DeriveDisplayOrder)
                 .setting(AppSettings::SubcommandRequiredElseHelp)
                 .subcommand(
-                    SubCommand::with_name("update").about("Download and install updates to rustup"),
+                    SubCommand::with_name("update")
+                        .about("Download and install updates to rustup")
+                        .arg(
+                            Arg::with_name("version")
+                                .help("The version of rustup to install, such as 1.22.1")
+                                .long("version")
+                                .takes_value(true)
+                                .validator(|s| {
+                                    if is_rustup_version(&s) {
+                                        Ok(())
+                                    } else {
+                                        Err("The version must be such as 1.22.1".into())
+                                    }
+                                }),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Go back to the rustup the last self-update replaced"),
                 )
                 .subcommand(
                     SubCommand::with_name("uninstall")
@@ -684,10 +816,191 @@ pub fn cli() -> App<'static, 'static> {
                         .about("The default components installed")
                         .arg(
                             Arg::with_name("profile-name")
+                                .help(PROFILE_NAME_HELP)
                                 .required(true)
-                                .possible_values(Profile::names())
                                 .default_value(Profile::default_name()),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("retain-versions")
+                        .about("How many previous versions of each toolchain to keep for rollback")
+                        .arg(Arg::with_name("count").required(true).validator(|s| {
+                            s.parse::<usize>()
+                                .map(|_| ())
+                                .map_err(|_| "The count must be a non-negative integer".into())
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("max-retries")
+                        .about("How many times to retry a failed download")
+                        .arg(Arg::with_name("count").required(true).validator(|s| {
+                            s.parse::<usize>()
+                                .map(|_| ())
+                                .map_err(|_| "The count must be a non-negative integer".into())
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("retry-delay")
+                        .about(
+                            "About how long to wait before first retrying a failed download, \
+                             in milliseconds",
+                        )
+                        .arg(
+                            Arg::with_name("milliseconds")
+                                .required(true)
+                                .validator(|s| {
+                                    s.parse::<u64>().map(|_| ()).map_err(|_| {
+                                        "The delay must be a number of milliseconds".into()
+                                    })
+                                }),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("concurrent-downloads")
+                        .about("How many packages to download at once")
+                        .arg(Arg::with_name("count").required(true).validator(|s| {
+                            match s.parse::<usize>() {
+                                Ok(n) if n > 0 => Ok(()),
+                                _ => Err("The count must be a positive integer".into()),
+                            }
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("stream-packages")
+                        .about(
+                            "Whether to unpack packages as they're downloaded, rather than \
+                             first save them to the download cache",
+                        )
+                        .arg(
+                            Arg::with_name("enabled")
+                                .required(true)
+                                .possible_values(&["true", "false"]),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("max-download-rate")
+                        .about("The most to download per second, such as 2MiB/s")
+                        .arg(Arg::with_name("rate").required(true).validator(|s| {
+                            if s == "unlimited" || units::parse_rate(&s).is_some() {
+                                Ok(())
+                            } else {
+                                Err("The rate must be such as 2MiB/s, or 'unlimited'".into())
+                            }
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("connect-timeout")
+                        .about("How long to wait to connect to a server, in seconds")
+                        .arg(
+                            Arg::with_name("seconds")
+                                .required(true)
+                                .validator(validate_timeout),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("idle-timeout")
+                        .about(
+                            "How long a download may receive nothing before it's given up on, \
+                             in seconds",
+                        )
+                        .arg(
+                            Arg::with_name("seconds")
+                                .required(true)
+                                .validator(validate_timeout),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("min-download-rate")
+                        .about("The slowest a download may be before it's given up on as stalled")
+                        .arg(Arg::with_name("rate").required(true).validator(|s| {
+                            if s == "none" || units::parse_rate(&s).is_some() {
+                                Ok(())
+                            } else {
+                                Err("The rate must be such as 10KiB/s, or 'none'".into())
+                            }
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("stall-timeout")
+                        .about(
+                            "How long a download may be slower than the minimum rate before \
+                             it's given up on, in seconds",
+                        )
+                        .arg(
+                            Arg::with_name("seconds")
+                                .required(true)
+                                .validator(validate_timeout),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("manifest-cache-ttl")
+                        .about(
+                            "How long to use a cached channel manifest before asking whether \
+                             it's changed, in seconds",
+                        )
+                        .arg(Arg::with_name("seconds").required(true).validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| "The time must be a number of seconds".into())
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("rustup-channel")
+                        .about("The channel rustup updates itself from")
+                        .arg(
+                            Arg::with_name("channel")
+                                .required(true)
+                                .possible_values(RUSTUP_CHANNELS),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("pinned-rustup-version")
+                        .about("The version rustup updates itself to, or 'none' for the latest")
+                        .arg(Arg::with_name("version").required(true).validator(|s| {
+                            if s == "none" || is_rustup_version(&s) {
+                                Ok(())
+                            } else {
+                                Err("The version must be such as 1.22.1, or 'none'".into())
+                            }
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("rustup-backup-runs")
+                        .about(
+                            "How many times rustup may run after a self-update before the \
+                             previous version is no longer kept",
+                        )
+                        .arg(Arg::with_name("runs").required(true).validator(|s| {
+                            s.parse::<usize>()
+                                .map(|_| ())
+                                .map_err(|_| "The number of runs must be a number".into())
+                        })),
+                )
+                .subcommand(
+                    SubCommand::with_name("auto-self-update")
+                        .about(
+                            "Whether rustup update and rustup toolchain install update rustup \
+                             itself, or only say when there's an update",
+                        )
+                        .arg(
+                            Arg::with_name("auto-self-update-mode")
+                                .required(true)
+                                .possible_values(SelfUpdateMode::names()),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Show how rustup is configured")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("proxy")
+                        .about("Show which proxy downloads from a URL go through")
+                        .arg(Arg::with_name("url").required(true).validator(|s| {
+                            url::Url::parse(&s)
+                                .map(|_| ())
+                                .map_err(|e| format!("Invalid URL: {}", e))
+                        })),
                 ),
         );
 
@@ -895,8 +1208,8 @@ fn check_updates(cfg: &Cfg) -> Result<utils::ExitCode> {
     // Get current rustup version
     let current_version = env!("CARGO_PKG_VERSION");
 
-    // Get available rustup version
-    let available_version = get_available_rustup_version()?;
+    // Get the rustup version it would update to
+    let available_version = get_target_rustup_version(cfg)?;
 
     let _ = t.attr(term2::Attr::Bold);
     write!(t, "rustup - ")?;
@@ -916,16 +1229,22 @@ fn check_updates(cfg: &Cfg) -> Result<utils::ExitCode> {
 }
 
 fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let self_update = !m.is_present("no-self-update") && !self_update::NEVER_SELF_UPDATE;
+    let dry_run = m.is_present("dry-run");
+    let json_report = m.value_of("json-report").map(Path::new);
+    let self_update = !m.is_present("no-self-update")
+        && !self_update::NEVER_SELF_UPDATE
+        && !dry_run
+        && cfg.get_self_update_mode()? != SelfUpdateMode::Disable;
     if let Some(p) = m.value_of("profile") {
-        let p = Profile::from_str(p)?;
+        let p = cfg.resolve_profile(p)?;
         cfg.set_profile_override(p);
     }
     let cfg = &cfg;
-    if cfg.get_profile()? == Profile::Complete {
+    if cfg.get_profile()?.base() == Some(Profile::Complete) {
         warn!("{}", common::WARN_COMPLETE_PROFILE);
     }
     if let Some(names) = m.values_of("toolchain") {
+        let mut statuses = Vec::new();
         for name in names {
             update_bare_triple_check(cfg, name)?;
 
@@ -969,6 +1288,7 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
                     &components,
                     &targets,
                     None,
+                    dry_run,
                 )?)
             } else if !toolchain.exists() {
                 return Err(ErrorKind::InvalidToolchainName(toolchain.name().to_string()).into());
@@ -976,26 +1296,48 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
                 None
             };
 
-            if let Some(status) = status.clone() {
-                writeln!(process().stdout())?;
-                common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+            match status.clone() {
+                Some(UpdateStatus::Planned(plan)) => {
+                    common::show_update_plan(toolchain.name(), &plan)?
+                }
+                Some(_) if dry_run => writeln!(
+                    process().stdout(),
+                    "no changes planned for '{}'",
+                    toolchain.name()
+                )?,
+                Some(status) => {
+                    writeln!(process().stdout())?;
+                    common::show_channel_update(cfg, toolchain.name(), Ok(status.clone()))?;
+                    statuses.push((toolchain.name().to_owned(), Ok(status)));
+                }
+                None => {}
             }
 
             if cfg.get_default()?.is_none() {
-                use crate::UpdateStatus;
                 if let Some(UpdateStatus::Installed) = status {
                     toolchain.make_default()?;
                 }
             }
         }
+        if let Some(path) = json_report.filter(|_| !dry_run) {
+            common::write_update_report(path, &statuses)?;
+        }
         if self_update {
-            common::self_update(|| Ok(utils::ExitCode(0)))?;
+            common::self_update(cfg, || Ok(utils::ExitCode(0)))?;
         }
     } else {
-        common::update_all_channels(cfg, self_update, m.is_present("force"))?;
-        info!("cleaning up downloads & tmp directories");
-        utils::delete_dir_contents(&cfg.download_dir);
-        cfg.temp_cfg.clean();
+        common::update_all_channels(
+            cfg,
+            self_update,
+            m.is_present("force"),
+            dry_run,
+            json_report,
+        )?;
+        if !dry_run {
+            info!("cleaning up downloads & tmp directories");
+            utils::delete_dir_contents(&cfg.download_dir);
+            cfg.temp_cfg.clean();
+        }
     }
 
     Ok(utils::ExitCode(0))
@@ -1255,7 +1597,8 @@ fn target_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
             false,
         );
         let distributable = DistributableToolchain::new(&toolchain)?;
-        distributable.add_component(new_component)?;
+        let plan = distributable.add_component(new_component, m.is_present("dry-run"))?;
+        show_component_plan(&toolchain, plan, m.is_present("dry-run"))?;
     }
 
     Ok(utils::ExitCode(0))
@@ -1272,7 +1615,8 @@ fn target_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
         );
         let distributable = DistributableToolchain::new(&toolchain)
             .chain_err(|| crate::ErrorKind::ComponentsUnsupported(toolchain.name().to_string()))?;
-        distributable.remove_component(new_component)?;
+        let plan = distributable.remove_component(new_component, m.is_present("dry-run"))?;
+        show_component_plan(&toolchain, plan, m.is_present("dry-run"))?;
     }
 
     Ok(utils::ExitCode(0))
@@ -1302,7 +1646,8 @@ fn component_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     for component in m.values_of("component").unwrap() {
         let new_component = Component::new_with_target(component, false)
             .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true));
-        distributable.add_component(new_component)?;
+        let plan = distributable.add_component(new_component, m.is_present("dry-run"))?;
+        show_component_plan(&toolchain, plan, m.is_present("dry-run"))?;
     }
 
     Ok(utils::ExitCode(0))
@@ -1323,12 +1668,30 @@ fn component_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     for component in m.values_of("component").unwrap() {
         let new_component = Component::new_with_target(component, false)
             .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true));
-        distributable.remove_component(new_component)?;
+        let plan = distributable.remove_component(new_component, m.is_present("dry-run"))?;
+        show_component_plan(&toolchain, plan, m.is_present("dry-run"))?;
     }
 
     Ok(utils::ExitCode(0))
 }
 
+fn show_component_plan(
+    toolchain: &Toolchain<'_>,
+    plan: Option<UpdatePlan>,
+    dry_run: bool,
+) -> Result<()> {
+    match plan {
+        Some(plan) => common::show_update_plan(toolchain.name(), &plan)?,
+        None if dry_run => writeln!(
+            process().stdout(),
+            "no changes planned for '{}'",
+            toolchain.name()
+        )?,
+        None => {}
+    }
+    Ok(())
+}
+
 fn explicit_or_dir_toolchain<'a>(cfg: &'a Cfg, m: &ArgMatches<'_>) -> Result<Toolchain<'a>> {
     let toolchain = m.value_of("toolchain");
     if let Some(toolchain) = toolchain {
@@ -1367,6 +1730,14 @@ fn toolchain_remove(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode
     Ok(utils::ExitCode(0))
 }
 
+fn toolchain_rollback(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").unwrap(), false)?;
+    let distributable = DistributableToolchain::new(&toolchain)?;
+    let date = distributable.rollback(m.value_of("to"))?;
+    info!("'{}' is now at version {}", toolchain.name(), date);
+    Ok(utils::ExitCode(0))
+}
+
 fn override_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     let toolchain = m.value_of("toolchain").unwrap();
     let toolchain = cfg.get_toolchain(toolchain, false)?;
@@ -1541,8 +1912,147 @@ fn set_profile(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     Ok(utils::ExitCode(0))
 }
 
-fn show_profile(cfg: &Cfg) -> Result<utils::ExitCode> {
-    writeln!(process().stdout(), "{}", cfg.get_profile()?)?;
+fn set_retain_versions(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let count = m.value_of("count").unwrap().parse().unwrap();
+    cfg.set_retain_versions(count)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_max_retries(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let count = m.value_of("count").unwrap().parse().unwrap();
+    cfg.set_max_retries(count)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_retry_delay(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let ms = m.value_of("milliseconds").unwrap().parse().unwrap();
+    cfg.set_retry_delay(Duration::from_millis(ms))?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_concurrent_downloads(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let count = m.value_of("count").unwrap().parse().unwrap();
+    cfg.set_concurrent_downloads(count)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_stream_packages(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's possible values
+    let stream = m.value_of("enabled").unwrap() == "true";
+    cfg.set_stream_packages(stream)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_max_download_rate(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let rate = m.value_of("rate").filter(|&rate| rate != "unlimited");
+    cfg.set_max_download_rate(rate)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_connect_timeout(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let secs = m.value_of("seconds").unwrap().parse().unwrap();
+    cfg.set_connect_timeout(Duration::from_secs(secs))?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_idle_timeout(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let secs = m.value_of("seconds").unwrap().parse().unwrap();
+    cfg.set_idle_timeout(Duration::from_secs(secs))?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_min_download_rate(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let rate = m.value_of("rate").filter(|&rate| rate != "none");
+    cfg.set_min_download_rate(rate)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_stall_timeout(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let secs = m.value_of("seconds").unwrap().parse().unwrap();
+    cfg.set_stall_timeout(Duration::from_secs(secs))?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_manifest_cache_ttl(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let secs = m.value_of("seconds").unwrap().parse().unwrap();
+    cfg.set_manifest_cache_ttl(Duration::from_secs(secs))?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_rustup_channel(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_rustup_channel(m.value_of("channel").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_rustup_backup_runs(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's validator
+    let runs = m.value_of("runs").unwrap().parse().unwrap();
+    cfg.set_rustup_backup_runs(runs)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_auto_self_update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    // Already checked by the argument's possible values
+    let mode = m.value_of("auto-self-update-mode").unwrap().parse()?;
+    cfg.set_self_update_mode(mode)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_pinned_rustup_version(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let version = m.value_of("version").filter(|&version| version != "none");
+    cfg.set_pinned_rustup_version(version)?;
+    Ok(utils::ExitCode(0))
+}
+
+fn validate_timeout(s: String) -> std::result::Result<(), String> {
+    match s.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(()),
+        _ => Err("The timeout must be a positive number of seconds".into()),
+    }
+}
+
+fn show_proxy(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.check_download_settings()?;
+    let url = url::Url::parse(m.value_of("url").unwrap()).unwrap();
+    let source = if cfg.proxy().is_some() {
+        "the settings file"
+    } else {
+        "the environment"
+    };
+    match download::proxy_for_url(cfg.proxy(), &url) {
+        Some(mut proxy) => {
+            if proxy.password().is_some() {
+                let _ = proxy.set_password(Some("***"));
+            }
+            writeln!(process().stdout(), "{} (from {})", proxy, source)?;
+        }
+        None => writeln!(process().stdout(), "none; connecting directly")?,
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn show_profile(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    if let Some(name) = m.value_of("toolchain") {
+        let toolchain = cfg.get_toolchain(name, false)?;
+        if !toolchain.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(name.to_owned()).into());
+        }
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        match distributable.installed_profile_name()? {
+            Some(profile) => writeln!(process().stdout(), "{}", profile)?,
+            None => writeln!(process().stdout(), "unknown")?,
+        }
+    } else {
+        writeln!(process().stdout(), "{}", cfg.get_profile()?)?;
+    }
     Ok(utils::ExitCode(0))
 }
 
@@ -59,14 +59,16 @@ use super::errors::*;
 use super::markdown::md;
 use super::term2;
 use crate::dist::dist::{self, Profile, TargetTriple};
+use crate::dist::report::UpdateReport;
 use crate::process;
+use crate::settings::SettingsFile;
 use crate::toolchain::{DistributableToolchain, Toolchain};
 use crate::utils::utils;
 use crate::utils::Notification;
 use crate::{Cfg, UpdateStatus};
 use crate::{DUP_TOOLS, TOOLS};
 use os::*;
-pub use os::{delete_rustup_and_cargo_home, run_update, self_replace};
+pub use os::{delete_rustup_and_cargo_home, restore_rustup, run_update, self_replace};
 #[cfg(windows)]
 pub use windows::complete_windows_uninstall;
 
@@ -250,6 +252,10 @@ tools, but otherwise, install the C++ build tools before proceeding.
 
 static UPDATE_ROOT: &str = "https://static.rust-lang.org/rustup";
 
+/// How many times rustup may run after a self-update before the rustup it
+/// replaced is no longer kept, when the settings file doesn't say
+pub const DEFAULT_BACKUP_RUNS: usize = 10;
+
 /// `CARGO_HOME` suitable for display, possibly with $HOME
 /// substituted for the directory prefix
 fn canonical_cargo_home() -> Result<Cow<'static, str>> {
@@ -741,7 +747,8 @@ fn maybe_install_rust(
             warn!("Updating existing toolchain, profile choice will be ignored");
         }
         let distributable = DistributableToolchain::new(&toolchain)?;
-        let status = distributable.install_from_dist(true, false, components, targets, None)?;
+        let status =
+            distributable.install_from_dist(true, false, components, targets, None, false)?;
         let toolchain_str = toolchain.name().to_owned();
         toolchain.cfg().set_default(&toolchain_str)?;
         writeln!(process().stdout())?;
@@ -918,12 +925,15 @@ pub fn uninstall(no_prompt: bool) -> Result<utils::ExitCode> {
 /// (and on windows this process will not be running to do it),
 /// rustup-init is stored in `CARGO_HOME`/bin, and then deleted next
 /// time rustup runs.
-pub fn update(cfg: &Cfg) -> Result<utils::ExitCode> {
+///
+/// It updates to `version` if given, or else to the version rustup is
+/// pinned to, or else to the latest version on its channel.
+pub fn update(cfg: &Cfg, version: Option<&str>) -> Result<utils::ExitCode> {
     use common::SelfUpdatePermission::*;
     let update_permitted = if NEVER_SELF_UPDATE {
         HardFail
     } else {
-        common::self_update_permitted(true)?
+        common::self_update_permitted(cfg, true)?
     };
     match update_permitted {
         HardFail => {
@@ -932,14 +942,14 @@ pub fn update(cfg: &Cfg) -> Result<utils::ExitCode> {
             err!("you should probably use your system package manager to update rustup");
             return Ok(utils::ExitCode(1));
         }
-        Skip => {
+        Skip | CheckOnly => {
             info!("Skipping self-update at this time");
             return Ok(utils::ExitCode(0));
         }
         Permit => {}
     }
 
-    match prepare_update()? {
+    match prepare_update(cfg, version)? {
         Some(setup_path) => {
             let version = match get_new_rustup_version(&setup_path) {
                 Some(new_version) => parse_new_rustup_version(new_version),
@@ -949,7 +959,11 @@ pub fn update(cfg: &Cfg) -> Result<utils::ExitCode> {
                 }
             };
 
-            let _ = common::show_channel_update(cfg, "rustup", Ok(UpdateStatus::Updated(version)));
+            let _ = common::show_channel_update(
+                cfg,
+                "rustup",
+                Ok(UpdateStatus::Updated(version, UpdateReport::default())),
+            );
             return run_update(&setup_path);
         }
         None => {
@@ -988,7 +1002,9 @@ fn parse_new_rustup_version(version: String) -> String {
     String::from(matched_version)
 }
 
-pub fn prepare_update() -> Result<Option<PathBuf>> {
+/// Downloads the version of rustup to update to, as `update` says, and
+/// returns where to, or `None` if rustup is already that version.
+pub fn prepare_update(cfg: &Cfg, version: Option<&str>) -> Result<Option<PathBuf>> {
     let cargo_home = utils::cargo_home()?;
     let rustup_path = cargo_home.join(&format!("bin{}rustup{}", MAIN_SEPARATOR, EXE_SUFFIX));
     let setup_path = cargo_home.join(&format!("bin{}rustup-init{}", MAIN_SEPARATOR, EXE_SUFFIX));
@@ -1021,9 +1037,22 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
     // Get current version
     let current_version = env!("CARGO_PKG_VERSION");
 
-    // Get available version
-    info!("checking for self-updates");
-    let available_version = get_available_rustup_version()?;
+    // Get the version to update to
+    let available_version = match version {
+        Some(version) => version.to_owned(),
+        None => match cfg.get_pinned_rustup_version()? {
+            // Nothing to ask the server when it's pinned
+            Some(pinned) if pinned == current_version => return Ok(None),
+            Some(pinned) => {
+                info!("rustup is pinned to version {}", pinned);
+                pinned
+            }
+            None => {
+                info!("checking for self-updates");
+                get_available_rustup_version(cfg)?
+            }
+        },
+    };
 
     // If up-to-date
     if available_version == current_version {
@@ -1041,7 +1070,7 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
 
     // Download new version
     info!("downloading self-update");
-    utils::download_file(&download_url, &setup_path, None, &|_| ())?;
+    download_file(cfg, &download_url, &setup_path)?;
 
     // Mark as executable
     utils::make_executable(&setup_path)?;
@@ -1049,7 +1078,159 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
     Ok(Some(setup_path))
 }
 
-pub fn get_available_rustup_version() -> Result<String> {
+/// Restores the rustup the last self-update replaced, and the proxies
+/// linked to it.
+pub fn rollback(cfg: &Cfg) -> Result<utils::ExitCode> {
+    use common::SelfUpdatePermission::*;
+    let rollback_permitted = if NEVER_SELF_UPDATE {
+        HardFail
+    } else {
+        common::self_update_permitted(cfg, true)?
+    };
+    match rollback_permitted {
+        HardFail => {
+            err!("self-update is disabled for this build of rustup");
+            err!("you should probably use your system package manager to downgrade rustup");
+            return Ok(utils::ExitCode(1));
+        }
+        Skip | CheckOnly => {
+            info!("Skipping self-rollback at this time");
+            return Ok(utils::ExitCode(0));
+        }
+        Permit => {}
+    }
+
+    let (version, backup) = find_backup()?.ok_or(ErrorKind::NoRustupBackup)?;
+    info!(
+        "rolling back rustup from {} to {}",
+        env!("CARGO_PKG_VERSION"),
+        version
+    );
+    let exit_code = restore_rustup(&backup)?;
+    utils::remove_dir("self-backup", &backup_dir()?, &|_: Notification<'_>| {})?;
+    info!(
+        "to keep rustup from updating itself again, run `rustup set pinned-rustup-version {}`",
+        version
+    );
+    Ok(exit_code)
+}
+
+// Where the rustup replaced by the last self-update is kept, as
+// `rustup-{version}`.
+fn backup_dir() -> Result<PathBuf> {
+    Ok(utils::rustup_home()?.join("self-backup"))
+}
+
+// Keeps a copy of this version of rustup, as installed in `CARGO_HOME`/bin,
+// in place of whichever was kept before.
+fn backup_rustup() -> Result<()> {
+    let rustup_path = utils::cargo_home()?.join(&format!("bin/rustup{}", EXE_SUFFIX));
+    let backup_dir = backup_dir()?;
+    if backup_dir.exists() {
+        utils::remove_dir("self-backup", &backup_dir, &|_: Notification<'_>| {})?;
+    }
+    utils::ensure_dir_exists("self-backup", &backup_dir, &|_: Notification<'_>| {})?;
+    let backup = backup_dir.join(&format!(
+        "rustup-{}{}",
+        env!("CARGO_PKG_VERSION"),
+        EXE_SUFFIX
+    ));
+    utils::copy_file(&rustup_path, &backup)?;
+    Ok(())
+}
+
+// The version of the rustup kept by the last self-update, and where it is,
+// if there is one.
+fn find_backup() -> Result<Option<(String, PathBuf)>> {
+    let backup_dir = backup_dir()?;
+    if !backup_dir.exists() {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(&backup_dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.starts_with("rustup-") && name.ends_with(EXE_SUFFIX) {
+            let version = &name["rustup-".len()..name.len() - EXE_SUFFIX.len()];
+            return Ok(Some((version.to_owned(), path)));
+        }
+    }
+    Ok(None)
+}
+
+// Counts another run of rustup since the last self-update, and once there
+// have been as many as the settings file's `rustup_backup_runs` stops
+// keeping the rustup it replaced.
+fn count_run_since_update() -> Result<()> {
+    let backup_dir = backup_dir()?;
+    if !backup_dir.exists() {
+        return Ok(());
+    }
+    let runs_file = backup_dir.join("runs");
+    let runs = fs::read_to_string(&runs_file)
+        .ok()
+        .and_then(|runs| runs.trim().parse::<usize>().ok())
+        .unwrap_or(0)
+        + 1;
+    let settings_file = SettingsFile::new(utils::rustup_home()?.join("settings.toml"));
+    let max_runs =
+        settings_file.with(|s| Ok(s.rustup_backup_runs.unwrap_or(DEFAULT_BACKUP_RUNS)))?;
+    if runs > max_runs {
+        utils::remove_dir("self-backup", &backup_dir, &|_: Notification<'_>| {})?;
+    } else {
+        utils::write_file("runs", &runs_file, &runs.to_string())?;
+    }
+    Ok(())
+}
+
+// Downloads `url` to `path`, making the request and retrying as `cfg`
+// says.
+fn download_file(cfg: &Cfg, url: &url::Url, path: &Path) -> Result<()> {
+    cfg.check_download_settings()?;
+    cfg.retry_policy().retry(
+        None,
+        || {
+            let options = download::Options {
+                host: cfg.host_configs().for_url(url),
+                timeouts: cfg.timeouts(),
+                proxy: cfg.proxy(),
+                ..download::Options::default()
+            };
+            utils::download_file_with_backend(
+                utils::download_backend(),
+                &options,
+                url,
+                path,
+                None,
+                false,
+                None,
+                &|_| (),
+            )
+        },
+        &|delay| {
+            info!(
+                "retrying download for '{}' in {:.1}s",
+                url,
+                delay.as_secs_f64()
+            )
+        },
+    )?;
+    Ok(())
+}
+
+/// The version of rustup a self-update would install: the one it's pinned
+/// to, if it is, or else the latest on its channel.
+pub fn get_target_rustup_version(cfg: &Cfg) -> Result<String> {
+    match cfg.get_pinned_rustup_version()? {
+        Some(pinned) => Ok(pinned),
+        None => get_available_rustup_version(cfg),
+    }
+}
+
+/// The latest version of rustup on the channel it updates itself from.
+pub fn get_available_rustup_version(cfg: &Cfg) -> Result<String> {
     let update_root = process()
         .var("RUSTUP_UPDATE_ROOT")
         .unwrap_or_else(|_| String::from(UPDATE_ROOT));
@@ -1059,10 +1240,11 @@ pub fn get_available_rustup_version() -> Result<String> {
         .chain_err(|| "error creating temp directory")?;
 
     // Parse the release file.
-    let release_file_url = format!("{}/release-stable.toml", update_root);
+    let release_file_name = format!("release-{}.toml", cfg.get_rustup_channel()?);
+    let release_file_url = format!("{}/{}", update_root, release_file_name);
     let release_file_url = utils::parse_url(&release_file_url)?;
-    let release_file = tempdir.path().join("release-stable.toml");
-    utils::download_file(&release_file_url, &release_file, None, &|_| ())?;
+    let release_file = tempdir.path().join(&release_file_name);
+    download_file(cfg, &release_file_url, &release_file)?;
     let release_toml_str = utils::read_file("rustup release", &release_file)?;
     let release_toml: toml::Value = toml::from_str(&release_toml_str)
         .map_err(|_| Error::from("unable to parse rustup release file"))?;
@@ -1090,7 +1272,10 @@ pub fn get_available_rustup_version() -> Result<String> {
     Ok(String::from(available_version))
 }
 
-pub fn cleanup_self_updater() -> Result<()> {
+/// Cleans up after a self-update: removes the rustup-init it ran and, if
+/// `count_run`, counts this run of rustup towards no longer keeping the
+/// rustup it replaced.
+pub fn cleanup_self_updater(count_run: bool) -> Result<()> {
     let cargo_home = utils::cargo_home()?;
     let setup = cargo_home.join(&format!("bin/rustup-init{}", EXE_SUFFIX));
 
@@ -1098,6 +1283,13 @@ pub fn cleanup_self_updater() -> Result<()> {
         utils::remove_file("setup", &setup)?;
     }
 
+    // Not counting a run only keeps the backup for longer
+    if count_run {
+        if let Err(e) = count_run_since_update() {
+            warn!("unable to count this run since the last self-update: {}", e);
+        }
+    }
+
     Ok(())
 }
 
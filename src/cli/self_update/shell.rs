@@ -22,8 +22,11 @@
 //! Rustup addresses this by:
 //! 1) using a shell script that updates PATH if the path is not in PATH
 //! 2) sourcing this script in any known and appropriate rc file
+//!
+//! Shells that don't understand POSIX sh, like fish, nushell, elvish and the
+//! csh family, each get a script of their own, in their own language.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use error_chain::bail;
 
@@ -66,11 +69,32 @@ pub fn cargo_home_str() -> Result<Cow<'static, str>> {
     })
 }
 
-// TODO: Tcsh (BSD)
-// TODO?: Make a decision on Ion Shell, Power Shell, Nushell
-// Cross-platform non-POSIX shells have not been assessed for integration yet
+// The directory shells following the XDG Base Directory spec keep their
+// configuration in.
+fn xdg_config_home() -> Option<PathBuf> {
+    match process().var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => utils::home_dir().map(|dir| dir.join(".config")),
+    }
+}
+
+// Whether `name` is the user's shell, or at least is installed.
+fn has_shell(name: &str) -> bool {
+    matches!(process().var("SHELL"), Ok(sh) if sh.ends_with(name))
+        || matches!(utils::find_cmd(&[name]), Some(_))
+}
+
+// TODO?: Make a decision on Ion Shell, Power Shell
 fn enumerate_shells() -> Vec<Shell> {
-    vec![Box::new(Posix), Box::new(Bash), Box::new(Zsh)]
+    vec![
+        Box::new(Posix),
+        Box::new(Bash),
+        Box::new(Zsh),
+        Box::new(Fish),
+        Box::new(Nushell),
+        Box::new(Elvish),
+        Box::new(Tcsh),
+    ]
 }
 
 pub fn get_available_shells() -> impl Iterator<Item = Shell> {
@@ -100,6 +124,12 @@ pub trait UnixShell {
     fn source_string(&self) -> Result<String> {
         Ok(format!(r#". "{}/env""#, cargo_home_str()?))
     }
+
+    // Whether `rc` is a file of rustup's own, which is removed rather than
+    // left empty once it no longer sources the env script.
+    fn owns_rcfile(&self, _rc: &Path) -> bool {
+        false
+    }
 }
 
 struct Posix;
@@ -200,6 +230,174 @@ impl UnixShell for Zsh {
     }
 }
 
+struct Fish;
+
+impl UnixShell for Fish {
+    fn does_exist(&self) -> bool {
+        has_shell("fish") || self.rcfiles().iter().any(|rc| rc.is_file())
+    }
+
+    fn rcfiles(&self) -> Vec<PathBuf> {
+        // fish sources everything in conf.d, so rustup gets a file of its
+        // own there rather than editing the user's config.fish.
+        xdg_config_home()
+            .into_iter()
+            .map(|dir| dir.join("fish/conf.d/rustup.fish"))
+            .collect()
+    }
+
+    fn update_rcs(&self) -> Vec<PathBuf> {
+        self.rcfiles()
+    }
+
+    fn env_script(&self) -> ShellScript {
+        ShellScript {
+            name: "env.fish",
+            content: include_str!("env.fish"),
+        }
+    }
+
+    fn source_string(&self) -> Result<String> {
+        Ok(format!(r#"source "{}/env.fish""#, cargo_home_str()?))
+    }
+
+    fn owns_rcfile(&self, rc: &Path) -> bool {
+        self.rcfiles().iter().any(|own| own == rc)
+    }
+}
+
+struct Nushell;
+
+impl UnixShell for Nushell {
+    fn does_exist(&self) -> bool {
+        !self.update_rcs().is_empty()
+    }
+
+    fn rcfiles(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = xdg_config_home().into_iter().collect();
+        // Without XDG_CONFIG_HOME, nushell on macOS keeps its config in the
+        // platform's own directory.
+        if cfg!(target_os = "macos") && process().var_os("XDG_CONFIG_HOME").is_none() {
+            dirs.extend(utils::home_dir().map(|dir| dir.join("Library/Application Support")));
+        }
+        dirs.into_iter()
+            .map(|dir| dir.join("nushell/env.nu"))
+            .collect()
+    }
+
+    fn update_rcs(&self) -> Vec<PathBuf> {
+        // nushell writes its env.nu on first run, so only shells that have
+        // been run are set up.
+        self.rcfiles()
+            .into_iter()
+            .filter(|rc| rc.is_file())
+            .collect()
+    }
+
+    fn env_script(&self) -> ShellScript {
+        ShellScript {
+            name: "env.nu",
+            content: include_str!("env.nu"),
+        }
+    }
+
+    fn source_string(&self) -> Result<String> {
+        // `source` takes its path when the script is parsed, before $env is
+        // around, but it does expand `~`.
+        let cargo_home = cargo_home_str()?;
+        Ok(if cargo_home.starts_with("$HOME") {
+            format!("source {}/env.nu", cargo_home.replacen("$HOME", "~", 1))
+        } else {
+            format!(r#"source "{}/env.nu""#, cargo_home)
+        })
+    }
+}
+
+struct Elvish;
+
+impl UnixShell for Elvish {
+    fn does_exist(&self) -> bool {
+        has_shell("elvish") || self.rcfiles().iter().any(|rc| rc.is_file())
+    }
+
+    fn rcfiles(&self) -> Vec<PathBuf> {
+        // Older elvish reads ~/.elvish/rc.elv, newer the XDG one.
+        xdg_config_home()
+            .map(|dir| dir.join("elvish/rc.elv"))
+            .into_iter()
+            .chain(utils::home_dir().map(|dir| dir.join(".elvish/rc.elv")))
+            .collect()
+    }
+
+    fn update_rcs(&self) -> Vec<PathBuf> {
+        // Only one of the rc files is read, so write to the one that exists,
+        // or create the XDG one.
+        self.rcfiles()
+            .into_iter()
+            .filter(|rc| rc.is_file())
+            .chain(self.rcfiles().into_iter())
+            .take(1)
+            .collect()
+    }
+
+    fn env_script(&self) -> ShellScript {
+        ShellScript {
+            name: "env.elv",
+            content: include_str!("env.elv"),
+        }
+    }
+
+    fn source_string(&self) -> Result<String> {
+        // elvish has no `source`, and doesn't expand variables in strings.
+        let cargo_home = cargo_home_str()?;
+        Ok(if cargo_home.starts_with("$HOME") {
+            format!(
+                "eval (slurp < {}/env.elv)",
+                cargo_home.replacen("$HOME", "$E:HOME", 1)
+            )
+        } else {
+            format!("eval (slurp < '{}/env.elv')", cargo_home)
+        })
+    }
+}
+
+struct Tcsh;
+
+impl UnixShell for Tcsh {
+    fn does_exist(&self) -> bool {
+        has_shell("tcsh") || has_shell("csh") || self.rcfiles().iter().any(|rc| rc.is_file())
+    }
+
+    fn rcfiles(&self) -> Vec<PathBuf> {
+        [".tcshrc", ".cshrc"]
+            .iter()
+            .filter_map(|rc| utils::home_dir().map(|dir| dir.join(rc)))
+            .collect()
+    }
+
+    fn update_rcs(&self) -> Vec<PathBuf> {
+        // tcsh reads .cshrc only if there's no .tcshrc, so write to the
+        // first that exists, or create .tcshrc.
+        self.rcfiles()
+            .into_iter()
+            .filter(|rc| rc.is_file())
+            .chain(self.rcfiles().into_iter())
+            .take(1)
+            .collect()
+    }
+
+    fn env_script(&self) -> ShellScript {
+        ShellScript {
+            name: "env.csh",
+            content: include_str!("env.csh"),
+        }
+    }
+
+    fn source_string(&self) -> Result<String> {
+        Ok(format!(r#"source "{}/env.csh""#, cargo_home_str()?))
+    }
+}
+
 pub fn legacy_paths() -> impl Iterator<Item = PathBuf> {
     let zprofiles = Zsh::zdotdir()
         .into_iter()
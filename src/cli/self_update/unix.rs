@@ -2,8 +2,8 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use super::super::errors::*;
-use super::install_bins;
 use super::shell;
+use super::{backup_rustup, install_bins, install_proxies};
 use crate::process;
 use crate::utils::utils;
 use crate::utils::Notification;
@@ -70,7 +70,11 @@ pub fn do_remove_from_path() -> Result<()> {
                 let mut new_bytes = file_bytes[..idx].to_vec();
                 new_bytes.extend(&file_bytes[idx + source_bytes.len()..]);
                 let new_file = String::from_utf8(new_bytes).unwrap();
-                utils::write_file("rcfile", &rc, &new_file)?;
+                if new_file.trim().is_empty() && sh.owns_rcfile(rc) {
+                    utils::remove_file("rcfile", &rc)?;
+                } else {
+                    utils::write_file("rcfile", &rc, &new_file)?;
+                }
             }
         }
     }
@@ -85,6 +89,11 @@ pub fn do_add_to_path() -> Result<()> {
         let source_cmd = sh.source_string()?;
         for rc in sh.update_rcs() {
             if !rc.is_file() || !utils::read_file("rcfile", &rc)?.contains(&source_cmd) {
+                // Some shells keep their rc files in directories of their own,
+                // which may not have been made yet.
+                if let Some(dir) = rc.parent() {
+                    utils::ensure_dir_exists("rcfile directory", dir, &|_: Notification<'_>| ())?;
+                }
                 utils::append_file("rcfile", &rc, &source_cmd).chain_err(|| {
                     ErrorKind::WritingShellProfile {
                         path: rc.to_path_buf(),
@@ -123,6 +132,9 @@ pub fn do_write_env_files() -> Result<()> {
 /// really hard to succeed, because at this point the upgrade is
 /// considered successful.
 pub fn run_update(setup_path: &Path) -> Result<utils::ExitCode> {
+    // Keep this version, for `rustup self rollback` to go back to
+    backup_rustup()?;
+
     let status = Command::new(setup_path)
         .arg("--self-replace")
         .status()
@@ -145,6 +157,26 @@ pub fn self_replace() -> Result<utils::ExitCode> {
     Ok(utils::ExitCode(0))
 }
 
+/// Replaces `CARGO_HOME`/bin/rustup with `backup` in a single rename, so
+/// that there's no moment when it's missing, then links the proxies to it.
+pub fn restore_rustup(backup: &Path) -> Result<utils::ExitCode> {
+    let bin_path = utils::cargo_home()?.join("bin");
+    let rustup_path = bin_path.join("rustup");
+    let staged_path = bin_path.join(".rustup-rollback");
+
+    utils::copy_file(backup, &staged_path)?;
+    utils::make_executable(&staged_path)?;
+    utils::rename_file(
+        "rustup-bin",
+        &staged_path,
+        &rustup_path,
+        &|_: Notification<'_>| {},
+    )?;
+    install_proxies()?;
+
+    Ok(utils::ExitCode(0))
+}
+
 fn remove_legacy_paths() -> Result<()> {
     let export = format!("export PATH=\"{}/bin:$PATH\"\n", shell::cargo_home_str()?).into_bytes();
     for rc in shell::legacy_paths().filter(|rc| rc.is_file()) {
@@ -4,7 +4,7 @@ use std::process::Command;
 
 use super::super::errors::*;
 use super::common;
-use super::{install_bins, InstallOpts};
+use super::{backup_rustup, install_bins, InstallOpts};
 use crate::dist::dist::TargetTriple;
 use crate::process;
 use crate::utils::utils;
@@ -277,6 +277,24 @@ pub fn do_remove_from_path() -> Result<()> {
 }
 
 pub fn run_update(setup_path: &Path) -> Result<utils::ExitCode> {
+    // Keep this version, for `rustup self rollback` to go back to
+    backup_rustup()?;
+
+    spawn_self_replace(setup_path)
+}
+
+/// Restores `backup` as `CARGO_HOME`/bin/rustup.exe the way a self-update
+/// replaces it, since it can't be while this rustup is running.
+pub fn restore_rustup(backup: &Path) -> Result<utils::ExitCode> {
+    let setup_path = utils::cargo_home()?
+        .join("bin")
+        .join(&format!("rustup-init{}", EXE_SUFFIX));
+    utils::copy_file(backup, &setup_path)?;
+    spawn_self_replace(&setup_path)
+}
+
+// Has the rustup-init at `setup_path` replace this rustup once it exits.
+fn spawn_self_replace(setup_path: &Path) -> Result<utils::ExitCode> {
     Command::new(setup_path)
         .arg("--self-replace")
         .spawn()
@@ -60,7 +60,6 @@ pub fn main() -> Result<utils::ExitCode> {
         .arg(
             Arg::with_name("profile")
                 .long("profile")
-                .possible_values(Profile::names())
                 .default_value(Profile::default_name()),
         )
         .arg(
@@ -1,15 +1,19 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt::{self, Display};
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use pgp::{Deserializable, SignedPublicKey};
 use serde::Deserialize;
 
 use crate::dist::download::DownloadCfg;
+use crate::dist::manifest_cache::ManifestCache;
 use crate::dist::{dist, temp};
 use crate::errors::*;
 use crate::fallback_settings::FallbackSettings;
@@ -17,8 +21,73 @@ use crate::notifications::*;
 use crate::process;
 use crate::settings::{Settings, SettingsFile, DEFAULT_METADATA_VERSION};
 use crate::toolchain::{DistributableToolchain, Toolchain, UpdateStatus};
+use crate::utils::credentials;
+use crate::utils::retry_policy::RetryPolicy;
+use crate::utils::units;
 use crate::utils::utils;
 
+// How long a download may stay below the minimum rate, if one is set, when
+// the settings file doesn't say
+const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How many packages to download at once when neither the settings file nor
+// `RUSTUP_CONCURRENT_DOWNLOADS` says
+const DEFAULT_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// The channels rustup itself is released on, `release-{channel}.toml`
+/// saying which version is latest on each.
+pub const RUSTUP_CHANNELS: &[&str] = &["stable", "beta"];
+
+/// Whether `rustup update` and `rustup toolchain install` update rustup
+/// itself when they're done.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfUpdateMode {
+    Enable,
+    Disable,
+    /// Only say when there's an update to rustup
+    CheckOnly,
+}
+
+impl SelfUpdateMode {
+    pub fn names() -> &'static [&'static str] {
+        &["enable", "disable", "check-only"]
+    }
+}
+
+impl FromStr for SelfUpdateMode {
+    type Err = Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "enable" => Ok(Self::Enable),
+            "disable" => Ok(Self::Disable),
+            "check-only" => Ok(Self::CheckOnly),
+            _ => Err(ErrorKind::InvalidSelfUpdateMode(mode.to_owned()).into()),
+        }
+    }
+}
+
+impl Display for SelfUpdateMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+            Self::CheckOnly => "check-only",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether `version` is a version of rustup, such as `1.22.1`, which may
+/// be downloaded from the archive of its releases.
+pub fn is_rustup_version(version: &str) -> bool {
+    let parts: Vec<_> = version.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
 #[derive(Debug, Default, Deserialize, PartialEq, Eq)]
 struct OverrideFile {
     toolchain: ToolchainSection,
@@ -79,7 +148,7 @@ struct OverrideCfg<'a> {
     toolchain: Option<Toolchain<'a>>,
     components: Vec<String>,
     targets: Vec<String>,
-    profile: Option<dist::Profile>,
+    profile: Option<dist::InstallProfile>,
 }
 
 impl<'a> OverrideCfg<'a> {
@@ -95,7 +164,7 @@ impl<'a> OverrideCfg<'a> {
                 .toolchain
                 .profile
                 .as_deref()
-                .map(dist::Profile::from_str)
+                .map(|p| cfg.resolve_profile(p))
                 .transpose()?,
         })
     }
@@ -176,16 +245,87 @@ impl Display for PgpPublicKey {
 
 pub const UNIX_FALLBACK_SETTINGS: &str = "/etc/rustup/settings.toml";
 
+// Uses the netrc file, `NETRC` or else `~/.netrc` (`~/_netrc` on Windows),
+// for the credentials of the hosts it has, unless they have credentials
+// or an `Authorization` header set in the settings file.
+fn add_netrc_credentials(hosts: &mut download::HostConfigs) {
+    let path = match process().var_os("NETRC") {
+        Some(path) => PathBuf::from(path),
+        None => match utils::home_dir() {
+            Some(home) => home.join(if cfg!(windows) { "_netrc" } else { ".netrc" }),
+            None => return,
+        },
+    };
+    // Not being able to read it mustn't stop anything that doesn't download
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return,
+    };
+    for machine in credentials::netrc_machines(&contents) {
+        let config = hosts.entry(&machine);
+        let has_authorization = config
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("authorization"));
+        if config.credentials.is_none() && !has_authorization {
+            config.credentials = Some(download::Credentials::new(credentials::Netrc::new(
+                &path, &machine,
+            )));
+        }
+    }
+}
+
+// The cap on how fast to download, as set in the settings file.
+fn download_rate_limit(s: &Settings) -> Result<Option<u64>> {
+    match &s.max_download_rate {
+        Some(rate) => units::parse_rate(rate)
+            .map(Some)
+            .ok_or_else(|| ErrorKind::InvalidDownloadRate(rate.clone()).into()),
+        None => Ok(None),
+    }
+}
+
+// How long to wait on servers before giving up on a download, as set in
+// the settings file.
+fn download_timeouts(s: &Settings) -> Result<download::Timeouts> {
+    let default = download::Timeouts::default();
+    let secs = |secs: Option<usize>, default| {
+        secs.map_or(default, |secs| Duration::from_secs(secs as u64))
+    };
+    let stall = match &s.min_download_rate {
+        Some(rate) => Some(download::Stall {
+            min_rate: units::parse_rate(rate)
+                .ok_or_else(|| ErrorKind::InvalidDownloadRate(rate.clone()))?,
+            window: secs(s.stall_timeout, DEFAULT_STALL_TIMEOUT),
+        }),
+        None => None,
+    };
+    Ok(download::Timeouts {
+        connect: secs(s.connect_timeout, default.connect),
+        idle: secs(s.idle_timeout, default.idle),
+        stall,
+    })
+}
+
 pub struct Cfg {
-    pub profile_override: Option<dist::Profile>,
+    pub profile_override: Option<dist::InstallProfile>,
     pub rustup_dir: PathBuf,
     pub settings_file: SettingsFile,
     pub fallback_settings: Option<FallbackSettings>,
     pub toolchains_dir: PathBuf,
     pub update_hash_dir: PathBuf,
+    pub retained_dir: PathBuf,
     pub download_dir: PathBuf,
     pub temp_cfg: temp::Cfg,
     pgp_keys: Vec<PgpPublicKey>,
+    retry_policy: RetryPolicy,
+    concurrent_downloads: usize,
+    stream_packages: bool,
+    hosts: download::HostConfigs,
+    throttle: Option<download::Throttle>,
+    timeouts: download::Timeouts,
+    proxy: Option<download::ProxyConfig>,
+    manifest_cache: ManifestCache,
     pub toolchain_override: Option<String>,
     pub env_override: Option<String>,
     pub dist_root_url: String,
@@ -218,6 +358,7 @@ impl Cfg {
 
         let toolchains_dir = rustup_dir.join("toolchains");
         let update_hash_dir = rustup_dir.join("update-hashes");
+        let retained_dir = rustup_dir.join("retained");
         let download_dir = rustup_dir.join("downloads");
 
         // PGP keys
@@ -243,6 +384,74 @@ impl Cfg {
             Ok(())
         })?;
 
+        // How failed downloads are retried
+        let mut retry_policy = settings_file.with(|s| {
+            let default = RetryPolicy::default();
+            Ok(RetryPolicy {
+                max_retries: s.max_retries.unwrap_or(default.max_retries),
+                base_delay: s
+                    .retry_delay
+                    .map_or(default.base_delay, |ms| Duration::from_millis(ms as u64)),
+            })
+        })?;
+        if let Some(max_retries) = process()
+            .var("RUSTUP_MAX_RETRIES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            retry_policy.max_retries = max_retries;
+        }
+
+        // How many packages to download at once
+        let mut concurrent_downloads = settings_file
+            .with(|s| Ok(s.concurrent_downloads))?
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_CONCURRENT_DOWNLOADS);
+        if let Some(n) = process()
+            .var("RUSTUP_CONCURRENT_DOWNLOADS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&n| n > 0)
+        {
+            concurrent_downloads = n;
+        }
+
+        // Whether packages are unpacked as they're downloaded
+        let mut stream_packages = settings_file
+            .with(|s| Ok(s.stream_packages))?
+            .unwrap_or(false);
+        match process().var("RUSTUP_STREAM_PACKAGES").ok().as_deref() {
+            Some("1") | Some("true") => stream_packages = true,
+            Some("0") | Some("false") => stream_packages = false,
+            _ => (),
+        }
+
+        // How to make requests to hosts that need more than the defaults
+        let mut hosts = settings_file.with(|s| Ok(s.host_configs()))?;
+        add_netrc_credentials(&mut hosts);
+
+        // The cap on how fast to download, how long to wait on servers
+        // and which proxies to go through, if not those the environment
+        // says. Settings that can't be parsed are left out here, and
+        // reported by `check_download_settings` once something is to be
+        // downloaded, as they mustn't stop anything that doesn't download.
+        let throttle = settings_file.with(|s| {
+            Ok(download_rate_limit(s)
+                .ok()
+                .and_then(|rate| rate.map(download::Throttle::new)))
+        })?;
+        let timeouts = settings_file.with(|s| Ok(download_timeouts(s).unwrap_or_default()))?;
+        let proxy = settings_file.with(|s| Ok(s.proxy_config().ok().and_then(|proxy| proxy)))?;
+
+        // Manifests kept from earlier checks for updates
+        let manifest_cache = settings_file.with(|s| {
+            let ttl = s.manifest_cache_ttl.unwrap_or(0) as u64;
+            Ok(ManifestCache::new(
+                rustup_dir.join("manifests"),
+                Duration::from_secs(ttl),
+            ))
+        })?;
+
         // Environment override
         let env_override = process()
             .var("RUSTUP_TOOLCHAIN")
@@ -279,9 +488,18 @@ impl Cfg {
             fallback_settings,
             toolchains_dir,
             update_hash_dir,
+            retained_dir,
             download_dir,
             temp_cfg,
             pgp_keys,
+            retry_policy,
+            concurrent_downloads,
+            stream_packages,
+            hosts,
+            throttle,
+            timeouts,
+            proxy,
+            manifest_cache,
             notify_handler,
             toolchain_override: None,
             env_override,
@@ -298,25 +516,72 @@ impl Cfg {
         Ok(cfg)
     }
 
-    /// construct a download configuration
+    /// construct a download configuration, failing if the settings file's
+    /// download settings can't be parsed
     pub fn download_cfg<'a>(
         &'a self,
         notify_handler: &'a dyn Fn(crate::dist::Notification<'_>),
-    ) -> DownloadCfg<'a> {
-        DownloadCfg {
+    ) -> Result<DownloadCfg<'a>> {
+        self.check_download_settings()?;
+        Ok(DownloadCfg {
             dist_root: &self.dist_root_url,
             temp_cfg: &self.temp_cfg,
             download_dir: &self.download_dir,
             notify_handler,
             pgp_keys: self.get_pgp_keys(),
-        }
+            retry_policy: self.retry_policy,
+            concurrent_downloads: self.concurrent_downloads,
+            stream_packages: self.stream_packages,
+            hosts: &self.hosts,
+            throttle: self.throttle.as_ref(),
+            timeouts: self.timeouts,
+            proxy: self.proxy.as_ref(),
+            manifest_cache: Some(&self.manifest_cache),
+        })
+    }
+
+    /// Fails if the download rates or proxies set in the settings file
+    /// can't be parsed. Those are only reported once something is to be
+    /// downloaded, and otherwise left out.
+    pub fn check_download_settings(&self) -> Result<()> {
+        self.settings_file.with(|s| {
+            download_rate_limit(s)?;
+            download_timeouts(s)?;
+            s.proxy_config()?;
+            Ok(())
+        })
     }
 
     pub fn get_pgp_keys(&self) -> &[PgpPublicKey] {
         &self.pgp_keys
     }
 
-    pub fn set_profile_override(&mut self, profile: dist::Profile) {
+    /// How failed downloads are retried, as set in the settings file or
+    /// overridden by `RUSTUP_MAX_RETRIES`.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// How to make requests to hosts that need more than the defaults, as
+    /// set in the settings file's `hosts` table.
+    pub fn host_configs(&self) -> &download::HostConfigs {
+        &self.hosts
+    }
+
+    /// How long to wait on servers before giving up on a download, as set
+    /// in the settings file. Check `check_download_settings` first.
+    pub fn timeouts(&self) -> download::Timeouts {
+        self.timeouts
+    }
+
+    /// Which proxies to go through, as set in the settings file's `proxy`
+    /// table, or `None` to go through those the environment says. Check
+    /// `check_download_settings` first.
+    pub fn proxy(&self) -> Option<&download::ProxyConfig> {
+        self.proxy.as_ref()
+    }
+
+    pub fn set_profile_override(&mut self, profile: dist::InstallProfile) {
         self.profile_override = Some(profile);
     }
 
@@ -330,9 +595,7 @@ impl Cfg {
     }
 
     pub fn set_profile(&mut self, profile: &str) -> Result<()> {
-        if !dist::Profile::names().contains(&profile) {
-            return Err(ErrorKind::UnknownProfile(profile.to_owned()).into());
-        }
+        self.resolve_profile(profile)?;
         self.profile_override = None;
         self.settings_file.with_mut(|s| {
             s.profile = Some(profile.to_owned());
@@ -342,6 +605,224 @@ impl Cfg {
         Ok(())
     }
 
+    pub fn set_retain_versions(&self, count: usize) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.retain_versions = Some(count);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetRetainVersions(count));
+        Ok(())
+    }
+
+    pub fn set_max_retries(&self, count: usize) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.max_retries = Some(count);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetMaxRetries(count));
+        Ok(())
+    }
+
+    pub fn set_concurrent_downloads(&self, count: usize) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.concurrent_downloads = Some(count);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetConcurrentDownloads(count));
+        Ok(())
+    }
+
+    pub fn set_stream_packages(&self, stream: bool) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.stream_packages = Some(stream);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetStreamPackages(stream));
+        Ok(())
+    }
+
+    pub fn set_retry_delay(&self, delay: Duration) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.retry_delay = Some(delay.as_millis() as usize);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetRetryDelay(delay));
+        Ok(())
+    }
+
+    /// Sets the cap on how fast to download, such as `2MiB/s`, or removes
+    /// it if `None`.
+    pub fn set_max_download_rate(&self, rate: Option<&str>) -> Result<()> {
+        if let Some(rate) = rate {
+            if units::parse_rate(rate).is_none() {
+                return Err(ErrorKind::InvalidDownloadRate(rate.to_owned()).into());
+            }
+        }
+        self.settings_file.with_mut(|s| {
+            s.max_download_rate = rate.map(ToOwned::to_owned);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetMaxDownloadRate(rate));
+        Ok(())
+    }
+
+    pub fn set_connect_timeout(&self, timeout: Duration) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.connect_timeout = Some(timeout.as_secs() as usize);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetConnectTimeout(timeout));
+        Ok(())
+    }
+
+    pub fn set_idle_timeout(&self, timeout: Duration) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.idle_timeout = Some(timeout.as_secs() as usize);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetIdleTimeout(timeout));
+        Ok(())
+    }
+
+    /// Sets the rate below which a download is given up on as stalled,
+    /// such as `10KiB/s`, or stops giving up on slow downloads if `None`.
+    pub fn set_min_download_rate(&self, rate: Option<&str>) -> Result<()> {
+        if let Some(rate) = rate {
+            if units::parse_rate(rate).is_none() {
+                return Err(ErrorKind::InvalidDownloadRate(rate.to_owned()).into());
+            }
+        }
+        self.settings_file.with_mut(|s| {
+            s.min_download_rate = rate.map(ToOwned::to_owned);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetMinDownloadRate(rate));
+        Ok(())
+    }
+
+    pub fn set_stall_timeout(&self, timeout: Duration) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.stall_timeout = Some(timeout.as_secs() as usize);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetStallTimeout(timeout));
+        Ok(())
+    }
+
+    /// Sets how long after a channel manifest's been downloaded it's used
+    /// without asking the server whether it's changed.
+    pub fn set_manifest_cache_ttl(&self, ttl: Duration) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.manifest_cache_ttl = Some(ttl.as_secs() as usize);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetManifestCacheTtl(ttl));
+        Ok(())
+    }
+
+    /// How many previous versions of each tracking toolchain to keep
+    /// around for `rustup toolchain rollback`.
+    pub fn get_retain_versions(&self) -> Result<usize> {
+        self.settings_file
+            .with(|s| Ok(s.retain_versions.unwrap_or(0)))
+    }
+
+    /// Sets which of `RUSTUP_CHANNELS` rustup updates itself from.
+    pub fn set_rustup_channel(&self, channel: &str) -> Result<()> {
+        if !RUSTUP_CHANNELS.contains(&channel) {
+            return Err(ErrorKind::InvalidRustupChannel(channel.to_owned()).into());
+        }
+        self.settings_file.with_mut(|s| {
+            s.rustup_channel = Some(channel.to_owned());
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetRustupChannel(channel));
+        Ok(())
+    }
+
+    /// Which of `RUSTUP_CHANNELS` rustup updates itself from, `stable`
+    /// unless the settings file says otherwise.
+    pub fn get_rustup_channel(&self) -> Result<String> {
+        self.settings_file.with(|s| {
+            Ok(s.rustup_channel
+                .clone()
+                .unwrap_or_else(|| RUSTUP_CHANNELS[0].to_owned()))
+        })
+    }
+
+    /// Sets the version of rustup to update itself to, whatever the latest
+    /// version on its channel, or stops pinning it if `None`.
+    pub fn set_pinned_rustup_version(&self, version: Option<&str>) -> Result<()> {
+        if let Some(version) = version {
+            if !is_rustup_version(version) {
+                return Err(ErrorKind::InvalidRustupVersion(version.to_owned()).into());
+            }
+        }
+        self.settings_file.with_mut(|s| {
+            s.pinned_rustup_version = version.map(ToOwned::to_owned);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetPinnedRustupVersion(version));
+        Ok(())
+    }
+
+    /// Sets whether `rustup update` and `rustup toolchain install` update
+    /// rustup itself, unless the fallback settings file has already.
+    pub fn set_self_update_mode(&self, mode: SelfUpdateMode) -> Result<()> {
+        if let Some(locked) = self.locked_self_update_mode()? {
+            return Err(ErrorKind::SelfUpdateModeLocked(locked.to_string()).into());
+        }
+        self.settings_file.with_mut(|s| {
+            s.auto_self_update = Some(mode.to_string());
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetSelfUpdateMode(mode));
+        Ok(())
+    }
+
+    /// Whether `rustup update` and `rustup toolchain install` update
+    /// rustup itself, as the fallback settings file says if it does, or
+    /// else as the settings file does.
+    pub fn get_self_update_mode(&self) -> Result<SelfUpdateMode> {
+        if let Some(locked) = self.locked_self_update_mode()? {
+            return Ok(locked);
+        }
+        self.settings_file.with(|s| match &s.auto_self_update {
+            Some(mode) => mode.parse(),
+            None => Ok(SelfUpdateMode::Enable),
+        })
+    }
+
+    // The self-update mode an administrator has set in the fallback
+    // settings file, which users can't change.
+    fn locked_self_update_mode(&self) -> Result<Option<SelfUpdateMode>> {
+        match self
+            .fallback_settings
+            .as_ref()
+            .and_then(|s| s.auto_self_update.as_ref())
+        {
+            Some(mode) => mode.parse().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets how many times rustup may run after a self-update before the
+    /// rustup it replaced is no longer kept for `rustup self rollback`.
+    pub fn set_rustup_backup_runs(&self, runs: usize) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.rustup_backup_runs = Some(runs);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetRustupBackupRuns(runs));
+        Ok(())
+    }
+
+    /// The version of rustup to update itself to, if it's pinned to one.
+    pub fn get_pinned_rustup_version(&self) -> Result<Option<String>> {
+        self.settings_file
+            .with(|s| Ok(s.pinned_rustup_version.clone()))
+    }
+
     pub fn set_toolchain_override(&mut self, toolchain_override: &str) {
         self.toolchain_override = Some(toolchain_override.to_owned());
     }
@@ -353,18 +834,44 @@ impl Cfg {
     // if there is no profile in the settings file. The last variant happens when
     // a user upgrades from a version of Rustup without profiles to a version of
     // Rustup with profiles.
-    pub fn get_profile(&self) -> Result<dist::Profile> {
-        if let Some(p) = self.profile_override {
-            return Ok(p);
+    pub fn get_profile(&self) -> Result<dist::InstallProfile> {
+        if let Some(p) = &self.profile_override {
+            return Ok(p.clone());
+        }
+        let p = self.settings_file.with(|s| Ok(s.profile.clone()))?;
+        match p {
+            Some(p) => self.resolve_profile(&p),
+            None => Ok(dist::InstallProfile::default()),
         }
-        self.settings_file.with(|s| {
-            let p = match &s.profile {
-                Some(p) => p,
-                None => dist::Profile::default_name(),
-            };
-            let p = dist::Profile::from_str(p)?;
-            Ok(p)
-        })
+    }
+
+    /// Look up a profile by name: one of those the manifest defines, or a
+    /// custom profile from the settings file or the fallback settings file.
+    /// The manifest's profiles take precedence over custom ones.
+    pub fn resolve_profile(&self, name: &str) -> Result<dist::InstallProfile> {
+        if let Ok(p) = dist::Profile::from_str(name) {
+            return Ok(dist::InstallProfile::Builtin(p));
+        }
+        let custom = self.custom_profiles()?;
+        match custom.get(name) {
+            Some(p) => Ok(dist::InstallProfile::Custom(name.to_owned(), p.clone())),
+            None => {
+                let names = custom.keys().cloned().collect();
+                Err(ErrorKind::UnknownProfile(name.to_owned(), names).into())
+            }
+        }
+    }
+
+    // Custom profiles from the settings file, and those from the fallback
+    // settings file that it doesn't override
+    fn custom_profiles(&self) -> Result<BTreeMap<String, dist::CustomProfile>> {
+        let mut profiles = match &self.fallback_settings {
+            Some(fallback) => fallback.profiles.clone(),
+            None => BTreeMap::new(),
+        };
+        let user = self.settings_file.with(|s| Ok(s.profiles.clone()))?;
+        profiles.extend(user);
+        Ok(profiles)
     }
 
     pub fn get_toolchain(&self, name: &str, create_parent: bool) -> Result<Toolchain<'_>> {
@@ -699,9 +1206,25 @@ impl Cfg {
                 let targets: Vec<_> = targets.iter().map(AsRef::as_ref).collect();
 
                 let distributable = DistributableToolchain::new(&toolchain)?;
-                if !toolchain.exists() || !components_exist(&distributable, &components, &targets)?
-                {
-                    distributable.install_from_dist(true, false, &components, &targets, profile)?;
+                let needs_install = || -> Result<bool> {
+                    Ok(!toolchain.exists()
+                        || !components_exist(&distributable, &components, &targets)?)
+                };
+                if needs_install()? {
+                    // Another process may already be installing it, e.g. a
+                    // second proxy started at the same time. Once it's done,
+                    // use what it installed rather than installing it again.
+                    let _lock = toolchain.lock_for_changes()?;
+                    if needs_install()? {
+                        distributable.install_from_dist(
+                            true,
+                            false,
+                            &components,
+                            &targets,
+                            profile,
+                            false,
+                        )?;
+                    }
                 }
             }
 
@@ -729,6 +1252,8 @@ impl Cfg {
                 .filter_map(io::Result::ok)
                 .filter(|e| e.file_type().map(|f| !f.is_file()).unwrap_or(false))
                 .filter_map(|e| e.file_name().into_string().ok())
+                // Hidden entries are staging directories of in-progress updates
+                .filter(|name| !name.starts_with('.'))
                 .collect();
 
             utils::toolchain_sort(&mut toolchains);
@@ -755,6 +1280,7 @@ impl Cfg {
     pub fn update_all_channels(
         &self,
         force_update: bool,
+        dry_run: bool,
     ) -> Result<Vec<(String, Result<UpdateStatus>)>> {
         let channels = self.list_channels()?;
         let channels = channels.into_iter();
@@ -763,7 +1289,8 @@ impl Cfg {
         let channels = channels.map(|(n, t)| {
             let st = t.and_then(|t| {
                 let distributable = DistributableToolchain::new(&t)?;
-                let st = distributable.install_from_dist(force_update, false, &[], &[], None);
+                let st =
+                    distributable.install_from_dist(force_update, false, &[], &[], None, dry_run);
                 if let Err(ref e) = st {
                     (self.notify_handler)(Notification::NonFatalError(e));
                 }
@@ -818,7 +1345,7 @@ impl Cfg {
         let toolchain = self.get_toolchain(toolchain, false)?;
         if install_if_missing && !toolchain.exists() {
             let distributable = DistributableToolchain::new(&toolchain)?;
-            distributable.install_from_dist(true, false, &[], &[], None)?;
+            distributable.install_from_dist(true, false, &[], &[], None, false)?;
         }
 
         if let Some(cmd) = self.maybe_do_cargo_fallback(&toolchain, binary)? {
@@ -141,7 +141,7 @@ impl<'a> TarPackage<'a> {
     pub fn new<R: Read>(
         stream: R,
         temp_cfg: &'a temp::Cfg,
-        notify_handler: Option<&'a dyn Fn(Notification<'_>)>,
+        notify_handler: Option<&dyn Fn(Notification<'_>)>,
     ) -> Result<Self> {
         let temp_dir = temp_cfg.new_directory()?;
         let mut archive = tar::Archive::new(stream);
@@ -508,7 +508,7 @@ impl<'a> TarGzPackage<'a> {
     pub fn new<R: Read>(
         stream: R,
         temp_cfg: &'a temp::Cfg,
-        notify_handler: Option<&'a dyn Fn(Notification<'_>)>,
+        notify_handler: Option<&dyn Fn(Notification<'_>)>,
     ) -> Result<Self> {
         let stream = flate2::read::GzDecoder::new(stream);
         Ok(TarGzPackage(TarPackage::new(
@@ -544,7 +544,7 @@ impl<'a> TarXzPackage<'a> {
     pub fn new<R: Read>(
         stream: R,
         temp_cfg: &'a temp::Cfg,
-        notify_handler: Option<&'a dyn Fn(Notification<'_>)>,
+        notify_handler: Option<&dyn Fn(Notification<'_>)>,
     ) -> Result<Self> {
         let stream = xz2::read::XzDecoder::new(stream);
         Ok(TarXzPackage(TarPackage::new(
@@ -6,6 +6,11 @@
 //! operations. If the Transaction is dropped without committing then
 //! it will *attempt* to roll back the transaction.
 //!
+//! Every change is recorded in a journal inside the install prefix
+//! before it is made, so that a transaction interrupted by a crash or
+//! a kill can still be rolled back the next time the prefix is changed;
+//! see `recover_interrupted_transaction`.
+//!
 //! FIXME: This uses ensure_dir_exists in some places but rollback
 //! does not remove any dirs created by it.
 
@@ -18,12 +23,18 @@ use crate::utils::utils;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
+/// Name of the transaction journal, kept alongside the other metadata in
+/// the prefix's manifest directory.
+const JOURNAL_FILE: &str = "rustup-transaction-journal";
+/// Name of the directory the journal's backups are kept in, beside it.
+const BACKUP_DIR: &str = "rustup-transaction-backups";
+
 /// A Transaction tracks changes to the file system, allowing them to
 /// be rolled back in case of an error. Instead of deleting or
-/// overwriting file, the old copies are moved to a temporary
-/// folder. If the transaction is rolled back, they will be moved back
-/// into place. If the transaction is committed, these files are
-/// automatically cleaned up using the temp system.
+/// overwriting file, the old copies are moved to a backup directory
+/// beside the journal. If the transaction is rolled back, they will be
+/// moved back into place. If the transaction is committed, the backup
+/// directory is removed along with the journal.
 ///
 /// All operations that create files will automatically create any
 /// intermediate directories in the path to the file if they do not
@@ -33,9 +44,10 @@ use std::path::{Path, PathBuf};
 /// already exists.
 pub struct Transaction<'a> {
     prefix: InstallPrefix,
-    changes: Vec<ChangedItem<'a>>,
+    changes: Vec<ChangedItem>,
     temp_cfg: &'a temp::Cfg,
     notify_handler: &'a dyn Fn(Notification<'_>),
+    journal: Journal,
     committed: bool,
 }
 
@@ -45,11 +57,13 @@ impl<'a> Transaction<'a> {
         temp_cfg: &'a temp::Cfg,
         notify_handler: &'a dyn Fn(Notification<'_>),
     ) -> Self {
+        let journal = Journal::new(&prefix);
         Transaction {
             prefix,
             changes: Vec::new(),
             temp_cfg,
             notify_handler,
+            journal,
             committed: false,
         }
     }
@@ -58,9 +72,12 @@ impl<'a> Transaction<'a> {
     /// called the transaction will be rolled back on drop.
     pub fn commit(mut self) {
         self.committed = true;
+        if let Err(e) = self.journal.remove() {
+            (self.notify_handler)(Notification::NonFatalError(&e));
+        }
     }
 
-    fn change(&mut self, item: ChangedItem<'a>) {
+    fn change(&mut self, item: ChangedItem) {
         self.changes.push(item);
     }
 
@@ -69,7 +86,8 @@ impl<'a> Transaction<'a> {
     /// contents.
     pub fn add_file(&mut self, component: &str, relpath: PathBuf) -> Result<File> {
         assert!(relpath.is_relative());
-        let (item, file) = ChangedItem::add_file(&self.prefix, component, relpath)?;
+        let (item, file) =
+            ChangedItem::add_file(&self.prefix, &mut self.journal, component, relpath)?;
         self.change(item);
         Ok(file)
     }
@@ -77,7 +95,8 @@ impl<'a> Transaction<'a> {
     /// Copy a file to a relative path of the install prefix.
     pub fn copy_file(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::copy_file(&self.prefix, component, relpath, src)?;
+        let item =
+            ChangedItem::copy_file(&self.prefix, &mut self.journal, component, relpath, src)?;
         self.change(item);
         Ok(())
     }
@@ -85,7 +104,7 @@ impl<'a> Transaction<'a> {
     /// Recursively copy a directory to a relative path of the install prefix.
     pub fn copy_dir(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::copy_dir(&self.prefix, component, relpath, src)?;
+        let item = ChangedItem::copy_dir(&self.prefix, &mut self.journal, component, relpath, src)?;
         self.change(item);
         Ok(())
     }
@@ -95,10 +114,10 @@ impl<'a> Transaction<'a> {
         assert!(relpath.is_relative());
         let item = ChangedItem::remove_file(
             &self.prefix,
+            &mut self.journal,
             component,
             relpath,
-            &self.temp_cfg,
-            self.notify_handler(),
+            self.notify_handler,
         )?;
         self.change(item);
         Ok(())
@@ -110,10 +129,10 @@ impl<'a> Transaction<'a> {
         assert!(relpath.is_relative());
         let item = ChangedItem::remove_dir(
             &self.prefix,
+            &mut self.journal,
             component,
             relpath,
-            &self.temp_cfg,
-            self.notify_handler(),
+            self.notify_handler,
         )?;
         self.change(item);
         Ok(())
@@ -123,7 +142,8 @@ impl<'a> Transaction<'a> {
     /// the install prefix.
     pub fn write_file(&mut self, component: &str, relpath: PathBuf, content: String) -> Result<()> {
         assert!(relpath.is_relative());
-        let (item, mut file) = ChangedItem::add_file(&self.prefix, component, relpath.clone())?;
+        let (item, mut file) =
+            ChangedItem::add_file(&self.prefix, &mut self.journal, component, relpath.clone())?;
         self.change(item);
         utils::write_str(
             "component",
@@ -140,7 +160,7 @@ impl<'a> Transaction<'a> {
     /// This is used for arbitrarily manipulating a file.
     pub fn modify_file(&mut self, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::modify_file(&self.prefix, relpath, &self.temp_cfg)?;
+        let item = ChangedItem::modify_file(&self.prefix, &mut self.journal, relpath)?;
         self.change(item);
         Ok(())
     }
@@ -148,8 +168,14 @@ impl<'a> Transaction<'a> {
     /// Move a file to a relative path of the install prefix.
     pub fn move_file(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item =
-            ChangedItem::move_file(&self.prefix, component, relpath, src, self.notify_handler())?;
+        let item = ChangedItem::move_file(
+            &self.prefix,
+            &mut self.journal,
+            component,
+            relpath,
+            src,
+            self.notify_handler,
+        )?;
         self.change(item);
         Ok(())
     }
@@ -157,8 +183,14 @@ impl<'a> Transaction<'a> {
     /// Recursively move a directory to a relative path of the install prefix.
     pub fn move_dir(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item =
-            ChangedItem::move_dir(&self.prefix, component, relpath, src, self.notify_handler())?;
+        let item = ChangedItem::move_dir(
+            &self.prefix,
+            &mut self.journal,
+            component,
+            relpath,
+            src,
+            self.notify_handler,
+        )?;
         self.change(item);
         Ok(())
     }
@@ -177,48 +209,333 @@ impl<'a> Drop for Transaction<'a> {
     fn drop(&mut self) {
         if !self.committed {
             (self.notify_handler)(Notification::RollingBack);
-            for item in self.changes.iter().rev() {
-                // ok_ntfy!(self.notify_handler,
-                //          Notification::NonFatalError,
-                match item.roll_back(&self.prefix, self.notify_handler()) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        (self.notify_handler)(Notification::NonFatalError(&e));
-                    }
+            // The journal rather than `self.changes` drives the rollback:
+            // it also covers an operation that failed part way through, and
+            // it is kept up to date so that a rollback which is itself
+            // interrupted can be finished later.
+            self.journal.roll_back(&self.prefix, self.notify_handler);
+        }
+    }
+}
+
+/// Roll back a transaction against `prefix` that never got to commit or
+/// finish rolling back, e.g. because rustup was killed part way through
+/// an install. Does nothing if there is no such transaction.
+///
+/// Any transaction found is taken to have been interrupted, so this must
+/// only be called by a process that has just taken the lock on the
+/// prefix's toolchain, which whoever started the transaction held.
+///
+/// If the prefix can't be returned to its previous state this fails with
+/// `ToolchainNeedsRepair`, and keeps failing until the toolchain has been
+/// removed.
+pub fn recover_interrupted_transaction(
+    prefix: &InstallPrefix,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    let mut journal = match Journal::load(prefix)? {
+        Some(journal) => journal,
+        None => return Ok(()),
+    };
+
+    notify_handler(Notification::RecoveringInterruptedTransaction(
+        prefix.path(),
+    ));
+    if journal.roll_back(prefix, notify_handler) {
+        Ok(())
+    } else {
+        Err(ErrorKind::ToolchainNeedsRepair(prefix.path().to_owned()).into())
+    }
+}
+
+/// A write-ahead log of the changes made by a transaction. Each entry is
+/// flushed to disk before the change it describes is made, and every
+/// entry can be undone using only what is on disk, so rolling back works
+/// the same whether it happens in-process or after a crash.
+///
+/// The journal is a text file of one line per entry, and an `undone <n>`
+/// line for each entry that has since been rolled back. The fields of an
+/// entry are separated by tabs, with any tab, newline or backslash in a
+/// path escaped.
+///
+/// The backups the entries name are kept in a directory of the journal's
+/// own, rather than rustup's temp directory, so that nothing but the
+/// transaction or its recovery can clean them up.
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    backup_dir: PathBuf,
+    entries: Vec<JournalEntry>,
+    undone: Vec<bool>,
+    backups: usize,
+    created: bool,
+}
+
+impl Journal {
+    fn new(prefix: &InstallPrefix) -> Self {
+        Journal {
+            path: prefix.manifest_file(JOURNAL_FILE),
+            backup_dir: prefix.manifest_file(BACKUP_DIR),
+            entries: Vec::new(),
+            undone: Vec::new(),
+            backups: 0,
+            created: false,
+        }
+    }
+
+    fn load(prefix: &InstallPrefix) -> Result<Option<Self>> {
+        let path = prefix.manifest_file(JOURNAL_FILE);
+        if !utils::is_file(&path) {
+            return Ok(None);
+        }
+        let contents = utils::read_file("transaction journal", &path)?;
+
+        let mut journal = Journal {
+            path,
+            backup_dir: prefix.manifest_file(BACKUP_DIR),
+            entries: Vec::new(),
+            undone: Vec::new(),
+            backups: 0,
+            created: true,
+        };
+
+        // Lines are only ever appended, so at worst the last one was torn
+        // by the interruption; the change it describes was never started.
+        let mut lines: Vec<&str> = contents.lines().collect();
+        if !contents.ends_with('\n') {
+            lines.pop();
+        }
+        for line in lines {
+            if let Some(index) = parse_field(line, "undone") {
+                match journal.undone.get_mut(index) {
+                    Some(undone) => *undone = true,
+                    None => return Err(ErrorKind::CorruptTransactionJournal(journal.path).into()),
                 }
+            } else {
+                let entry = JournalEntry::parse(line)
+                    .ok_or_else(|| ErrorKind::CorruptTransactionJournal(journal.path.clone()))?;
+                journal.entries.push(entry);
+                journal.undone.push(false);
+            }
+        }
+
+        Ok(Some(journal))
+    }
+
+    fn create(&mut self) -> Result<()> {
+        if !self.created {
+            if let Some(p) = self.path.parent() {
+                utils::ensure_dir_exists("transaction journal", p, &|_: Notification<'_>| ())?;
             }
+            utils::write_file("transaction journal", &self.path, "")?;
+            self.created = true;
+            // Without a journal, anything left in the backup directory
+            // belongs to a transaction that had already finished.
+            if utils::path_exists(&self.backup_dir) {
+                utils::remove_dir(
+                    "transaction backups",
+                    &self.backup_dir,
+                    &|_: Notification<'_>| (),
+                )?;
+            }
+            utils::ensure_dir_exists(
+                "transaction backups",
+                &self.backup_dir,
+                &|_: Notification<'_>| (),
+            )?;
         }
+        Ok(())
+    }
+
+    fn append(&mut self, line: &str) -> Result<()> {
+        self.create()?;
+        utils::append_file("transaction journal", &self.path, line)
+    }
+
+    /// A path for a new backup to be made at.
+    fn new_backup(&mut self) -> Result<PathBuf> {
+        self.create()?;
+        self.backups += 1;
+        Ok(self.backup_dir.join(self.backups.to_string()))
+    }
+
+    /// Record a change that is about to be made.
+    fn record(&mut self, entry: JournalEntry) -> Result<()> {
+        self.append(&entry.to_line())?;
+        self.entries.push(entry);
+        self.undone.push(false);
+        Ok(())
+    }
+
+    /// Undo every entry that hasn't been undone yet, newest first, keeping
+    /// going past failures. Returns whether everything was undone, in
+    /// which case the journal has been removed.
+    fn roll_back(&mut self, prefix: &InstallPrefix, notify: &dyn Fn(Notification<'_>)) -> bool {
+        let mut complete = true;
+        for index in (0..self.entries.len()).rev() {
+            if self.undone[index] {
+                continue;
+            }
+            let result = self.entries[index]
+                .roll_back(prefix, notify)
+                .and_then(|()| self.append(&format!("undone {}", index)));
+            match result {
+                Ok(()) => self.undone[index] = true,
+                Err(e) => {
+                    notify(Notification::NonFatalError(&e));
+                    complete = false;
+                }
+            }
+        }
+        if complete {
+            if let Err(e) = self.remove() {
+                notify(Notification::NonFatalError(&e));
+                complete = false;
+            }
+        }
+        complete
+    }
+
+    fn remove(&mut self) -> Result<()> {
+        if self.created {
+            utils::remove_file("transaction journal", &self.path)?;
+            self.created = false;
+            if utils::path_exists(&self.backup_dir) {
+                utils::remove_dir(
+                    "transaction backups",
+                    &self.backup_dir,
+                    &|_: Notification<'_>| (),
+                )?;
+            }
+        }
+        Ok(())
     }
 }
 
-/// This is the set of fundamental operations supported on a
-/// Transaction. More complicated operations, such as installing a
-/// package, or updating a component, distill down into a series of
-/// these primitives.
+fn parse_field(line: &str, name: &str) -> Option<usize> {
+    let mut words = line.splitn(2, ' ');
+    if words.next() != Some(name) {
+        return None;
+    }
+    words.next()?.parse().ok()
+}
+
+fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_path(field: &str) -> Option<PathBuf> {
+    let mut path = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next()? {
+                '\\' => path.push('\\'),
+                't' => path.push('\t'),
+                'n' => path.push('\n'),
+                'r' => path.push('\r'),
+                _ => return None,
+            }
+        } else {
+            path.push(c);
+        }
+    }
+    Some(PathBuf::from(path))
+}
+
+/// The on-disk counterpart of a `ChangedItem`, naming the backups by path
+/// so that it can be undone by a later process.
 #[derive(Debug)]
-enum ChangedItem<'a> {
+enum JournalEntry {
     AddedFile(PathBuf),
     AddedDir(PathBuf),
-    RemovedFile(PathBuf, temp::File<'a>),
-    RemovedDir(PathBuf, temp::Dir<'a>),
-    ModifiedFile(PathBuf, Option<temp::File<'a>>),
+    RemovedFile(PathBuf, PathBuf),
+    RemovedDir(PathBuf, PathBuf),
+    ModifiedFile(PathBuf, Option<PathBuf>),
 }
 
-impl<'a> ChangedItem<'a> {
-    fn roll_back(
-        &self,
-        prefix: &InstallPrefix,
-        notify: &'a dyn Fn(Notification<'_>),
-    ) -> Result<()> {
-        use self::ChangedItem::*;
+impl JournalEntry {
+    fn to_line(&self) -> String {
+        use self::JournalEntry::*;
+        let (kind, path, backup) = match self {
+            AddedFile(path) => ("add-file", path, None),
+            AddedDir(path) => ("add-dir", path, None),
+            RemovedFile(path, backup) => ("remove-file", path, Some(backup)),
+            RemovedDir(path, backup) => ("remove-dir", path, Some(backup)),
+            ModifiedFile(path, backup) => ("modify-file", path, backup.as_ref()),
+        };
+        match backup {
+            Some(backup) => format!("{}\t{}\t{}", kind, escape_path(path), escape_path(backup)),
+            None => format!("{}\t{}", kind, escape_path(path)),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        use self::JournalEntry::*;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let entry = match fields.as_slice() {
+            ["add-file", path] => AddedFile(unescape_path(path)?),
+            ["add-dir", path] => AddedDir(unescape_path(path)?),
+            ["remove-file", path, backup] => {
+                RemovedFile(unescape_path(path)?, unescape_path(backup)?)
+            }
+            ["remove-dir", path, backup] => {
+                RemovedDir(unescape_path(path)?, unescape_path(backup)?)
+            }
+            ["modify-file", path] => ModifiedFile(unescape_path(path)?, None),
+            ["modify-file", path, backup] => {
+                ModifiedFile(unescape_path(path)?, Some(unescape_path(backup)?))
+            }
+            _ => return None,
+        };
+        Some(entry)
+    }
+
+    /// Undo the change. This must be idempotent, and must cope with the
+    /// change never having been made at all, since the entry is written
+    /// first.
+    fn roll_back(&self, prefix: &InstallPrefix, notify: &dyn Fn(Notification<'_>)) -> Result<()> {
+        use self::JournalEntry::*;
         match self {
-            AddedFile(path) => utils::remove_file("component", &prefix.abs_path(path))?,
-            AddedDir(path) => utils::remove_dir("component", &prefix.abs_path(path), notify)?,
-            RemovedFile(path, tmp) | ModifiedFile(path, Some(tmp)) => {
-                utils::rename_file("component", &tmp, &prefix.abs_path(path), notify)?
+            AddedFile(path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(&abs_path) {
+                    utils::remove_file("component", &abs_path)?;
+                }
             }
-            RemovedDir(path, tmp) => {
-                utils::rename_dir("component", &tmp.join("bk"), &prefix.abs_path(path), notify)?
+            AddedDir(path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_directory(&abs_path) {
+                    utils::remove_dir("component", &abs_path, notify)?;
+                }
+            }
+            RemovedFile(path, backup) => {
+                let abs_path = prefix.abs_path(path);
+                if !utils::path_exists(&abs_path) {
+                    JournalEntry::check_backup(path, backup)?;
+                    utils::rename_file("component", backup, &abs_path, notify)?;
+                }
+            }
+            RemovedDir(path, backup) => {
+                let abs_path = prefix.abs_path(path);
+                if !utils::path_exists(&abs_path) {
+                    JournalEntry::check_backup(path, backup)?;
+                    utils::rename_dir("component", backup, &abs_path, notify)?;
+                }
+            }
+            ModifiedFile(path, Some(backup)) => {
+                JournalEntry::check_backup(path, backup)?;
+                utils::rename_file("component", backup, &prefix.abs_path(path), notify)?;
             }
             ModifiedFile(path, None) => {
                 let abs_path = prefix.abs_path(path);
@@ -229,6 +546,34 @@ impl<'a> ChangedItem<'a> {
         }
         Ok(())
     }
+
+    fn check_backup(path: &Path, backup: &Path) -> Result<()> {
+        if utils::path_exists(backup) {
+            Ok(())
+        } else {
+            Err(ErrorKind::MissingRollbackBackup(path.to_owned()).into())
+        }
+    }
+}
+
+/// This is the set of fundamental operations supported on a
+/// Transaction. More complicated operations, such as installing a
+/// package, or updating a component, distill down into a series of
+/// these primitives.
+///
+/// These name the backups of anything removed or modified, which are
+/// deleted along with the journal; undoing the changes is left to the
+/// `JournalEntry` recorded alongside each one.
+#[derive(Debug)]
+enum ChangedItem {
+    AddedFile(PathBuf),
+    AddedDir(PathBuf),
+    RemovedFile(PathBuf, PathBuf),
+    RemovedDir(PathBuf, PathBuf),
+    ModifiedFile(PathBuf, Option<PathBuf>),
+}
+
+impl ChangedItem {
     fn dest_abs_path(
         prefix: &InstallPrefix,
         component: &str,
@@ -248,41 +593,50 @@ impl<'a> ChangedItem<'a> {
             Ok(abs_path)
         }
     }
-    fn add_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf) -> Result<(Self, File)> {
+    fn add_file(
+        prefix: &InstallPrefix,
+        journal: &mut Journal,
+        component: &str,
+        relpath: PathBuf,
+    ) -> Result<(Self, File)> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        journal.record(JournalEntry::AddedFile(relpath.clone()))?;
         let file = File::create(&abs_path)
             .chain_err(|| format!("error creating file '{}'", abs_path.display()))?;
         Ok((ChangedItem::AddedFile(relpath), file))
     }
     fn copy_file(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
         src: &Path,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        journal.record(JournalEntry::AddedFile(relpath.clone()))?;
         utils::copy_file(src, &abs_path)?;
         Ok(ChangedItem::AddedFile(relpath))
     }
     fn copy_dir(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
         src: &Path,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        journal.record(JournalEntry::AddedDir(relpath.clone()))?;
         utils::copy_dir(src, &abs_path, &|_: Notification<'_>| ())?;
         Ok(ChangedItem::AddedDir(relpath))
     }
     fn remove_file(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
-        temp_cfg: &'a temp::Cfg,
-        notify: &'a dyn Fn(Notification<'_>),
+        notify: &dyn Fn(Notification<'_>),
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
-        let backup = temp_cfg.new_file()?;
         if !utils::path_exists(&abs_path) {
             Err(ErrorKind::ComponentMissingFile {
                 name: component.to_owned(),
@@ -290,19 +644,20 @@ impl<'a> ChangedItem<'a> {
             }
             .into())
         } else {
+            let backup = journal.new_backup()?;
+            journal.record(JournalEntry::RemovedFile(relpath.clone(), backup.clone()))?;
             utils::rename_file("component", &abs_path, &backup, notify)?;
             Ok(ChangedItem::RemovedFile(relpath, backup))
         }
     }
     fn remove_dir(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
-        temp_cfg: &'a temp::Cfg,
-        notify: &'a dyn Fn(Notification<'_>),
+        notify: &dyn Fn(Notification<'_>),
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
-        let backup = temp_cfg.new_directory()?;
         if !utils::path_exists(&abs_path) {
             Err(ErrorKind::ComponentMissingDir {
                 name: component.to_owned(),
@@ -310,47 +665,60 @@ impl<'a> ChangedItem<'a> {
             }
             .into())
         } else {
-            utils::rename_dir("component", &abs_path, &backup.join("bk"), notify)?;
+            let backup = journal.new_backup()?;
+            journal.record(JournalEntry::RemovedDir(relpath.clone(), backup.clone()))?;
+            utils::rename_dir("component", &abs_path, &backup, notify)?;
             Ok(ChangedItem::RemovedDir(relpath, backup))
         }
     }
     fn modify_file(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         relpath: PathBuf,
-        temp_cfg: &'a temp::Cfg,
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
 
         if utils::is_file(&abs_path) {
-            let backup = temp_cfg.new_file()?;
+            // The backup is complete before it is journaled, so a journaled
+            // backup can always be restored.
+            let backup = journal.new_backup()?;
             utils::copy_file(&abs_path, &backup)?;
+            journal.record(JournalEntry::ModifiedFile(
+                relpath.clone(),
+                Some(backup.clone()),
+            ))?;
             Ok(ChangedItem::ModifiedFile(relpath, Some(backup)))
         } else {
             if let Some(p) = abs_path.parent() {
                 utils::ensure_dir_exists("component", p, &|_: Notification<'_>| {})?;
             }
+            journal.record(JournalEntry::ModifiedFile(relpath.clone(), None))?;
             Ok(ChangedItem::ModifiedFile(relpath, None))
         }
     }
     fn move_file(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
         src: &Path,
-        notify: &'a dyn Fn(Notification<'_>),
+        notify: &dyn Fn(Notification<'_>),
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        journal.record(JournalEntry::AddedFile(relpath.clone()))?;
         utils::rename_file("component", src, &abs_path, notify)?;
         Ok(ChangedItem::AddedFile(relpath))
     }
     fn move_dir(
         prefix: &InstallPrefix,
+        journal: &mut Journal,
         component: &str,
         relpath: PathBuf,
         src: &Path,
-        notify: &'a dyn Fn(Notification<'_>),
+        notify: &dyn Fn(Notification<'_>),
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        journal.record(JournalEntry::AddedDir(relpath.clone()))?;
         utils::rename_dir("component", src, &abs_path, notify)?;
         Ok(ChangedItem::AddedDir(relpath))
     }
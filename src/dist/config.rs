@@ -9,6 +9,10 @@ pub const DEFAULT_CONFIG_VERSION: &str = "1";
 pub struct Config {
     pub config_version: String,
     pub components: Vec<Component>,
+    /// The profile the toolchain was installed with, if known
+    pub profile: Option<String>,
+    /// The installed components the user asked for, rather than the profile
+    pub requested_components: Vec<Component>,
 }
 
 impl Config {
@@ -22,9 +26,19 @@ impl Config {
         let components =
             Self::toml_to_components(components, &format!("{}{}.", path, "components"))?;
 
+        let profile = get_opt_string(&mut table, "profile", path)?;
+
+        let requested_components = get_array(&mut table, "requested_components", path)?;
+        let requested_components = Self::toml_to_components(
+            requested_components,
+            &format!("{}{}.", path, "requested_components"),
+        )?;
+
         Ok(Self {
             config_version,
             components,
+            profile,
+            requested_components,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
@@ -37,6 +51,16 @@ impl Config {
         if !components.is_empty() {
             result.insert("components".to_owned(), toml::Value::Array(components));
         }
+        if let Some(profile) = self.profile {
+            result.insert("profile".to_owned(), toml::Value::String(profile));
+        }
+        let requested_components = Self::components_to_toml(self.requested_components);
+        if !requested_components.is_empty() {
+            result.insert(
+                "requested_components".to_owned(),
+                toml::Value::Array(requested_components),
+            );
+        }
         result
     }
 
@@ -80,6 +104,8 @@ impl Default for Config {
         Self {
             config_version: DEFAULT_CONFIG_VERSION.to_owned(),
             components: Vec::new(),
+            profile: None,
+            requested_components: Vec::new(),
         }
     }
 }
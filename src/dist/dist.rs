@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::env;
 use std::fmt;
 use std::ops::Deref;
@@ -7,16 +8,19 @@ use std::str::FromStr;
 use chrono::{Date, NaiveDate, TimeZone, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Deserialize;
 
+use crate::dist::config::Config;
 use crate::dist::download::DownloadCfg;
-use crate::dist::manifest::Manifest as ManifestV2;
-use crate::dist::manifestation::{Changes, Manifestation, UpdateStatus};
+use crate::dist::manifest::{Component, Manifest as ManifestV2};
+use crate::dist::manifestation::{Changes, Manifestation, UpdatePlan, UpdateStatus};
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 pub use crate::dist::triple::*;
 use crate::errors::*;
 use crate::process;
+use crate::toml_utils::*;
 use crate::utils::utils;
 
 pub static DEFAULT_DIST_SERVER: &str = "https://static.rust-lang.org";
@@ -417,7 +421,8 @@ impl<'a> Manifest<'a> {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Profile {
     Minimal,
     Default,
@@ -453,6 +458,86 @@ impl Default for Profile {
     }
 }
 
+/// A profile defined in the settings file: either the components of one
+/// of the manifest's profiles plus some more, or, without a base, an
+/// explicit list of components.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CustomProfile {
+    pub base: Option<Profile>,
+    #[serde(default)]
+    pub components: Vec<String>,
+}
+
+impl CustomProfile {
+    pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let base = get_opt_string(&mut table, "base", path)?
+            .map(|base| Profile::from_str(&base))
+            .transpose()?;
+        let components = get_array(&mut table, "components", path)?
+            .into_iter()
+            .map(|v| match v {
+                toml::Value::String(s) => Ok(s),
+                _ => Err(ErrorKind::ExpectedType("string", path.to_owned() + "components").into()),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { base, components })
+    }
+    pub fn into_toml(self) -> toml::value::Table {
+        let mut result = toml::value::Table::new();
+        if let Some(base) = self.base {
+            result.insert("base".to_owned(), toml::Value::String(base.to_string()));
+        }
+        let components = self
+            .components
+            .into_iter()
+            .map(toml::Value::String)
+            .collect();
+        result.insert("components".to_owned(), toml::Value::Array(components));
+        result
+    }
+}
+
+/// The profile to install a toolchain with: one of the manifest's
+/// profiles, or a custom profile from the settings file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallProfile {
+    Builtin(Profile),
+    Custom(String, CustomProfile),
+}
+
+impl InstallProfile {
+    /// The manifest profile whose components are installed, if any.
+    pub fn base(&self) -> Option<Profile> {
+        match self {
+            Self::Builtin(profile) => Some(*profile),
+            Self::Custom(_, profile) => profile.base,
+        }
+    }
+
+    /// Components installed in addition to those of the base profile.
+    pub fn components(&self) -> &[String] {
+        match self {
+            Self::Builtin(_) => &[],
+            Self::Custom(_, profile) => &profile.components,
+        }
+    }
+}
+
+impl Default for InstallProfile {
+    fn default() -> Self {
+        Self::Builtin(Profile::default())
+    }
+}
+
+impl fmt::Display for InstallProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Builtin(profile) => profile.fmt(f),
+            Self::Custom(name, _) => write!(f, "{}", name),
+        }
+    }
+}
+
 impl fmt::Display for TargetTriple {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -503,28 +588,43 @@ impl fmt::Display for Profile {
     }
 }
 
+/// What `update_from_dist` did, when anything needed changing.
+#[derive(Debug)]
+pub enum DistUpdate {
+    /// Installed the manifest with this hash
+    Installed(String),
+    /// Would make these changes, but it was a dry run
+    Planned(UpdatePlan),
+}
+
 // Installs or updates a toolchain from a dist server. If an initial
 // install then it will be installed with the default components. If
 // an upgrade then all the existing components will be upgraded.
 //
-// Returns the manifest's hash if anything changed.
+// With `dry_run` only the manifest is fetched, and the changes it would
+// make are returned rather than made. With `retain_old` an existing
+// installation that is updated is kept there.
+//
+// Returns `None` if nothing needed changing.
 pub fn update_from_dist<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
-    profile: Option<Profile>,
+    profile: Option<&InstallProfile>,
     prefix: &InstallPrefix,
     force_update: bool,
     allow_downgrade: bool,
     old_date: Option<&str>,
     components: &[&str],
     targets: &[&str],
-) -> Result<Option<String>> {
+    dry_run: bool,
+    retain_old: Option<&Path>,
+) -> Result<Option<DistUpdate>> {
     let fresh_install = !prefix.path().exists();
     let hash_exists = update_hash.map(Path::exists).unwrap_or(false);
 
     // fresh_install means the toolchain isn't present, but hash_exists means there is a stray hash file
-    if fresh_install && hash_exists {
+    if fresh_install && hash_exists && !dry_run {
         // It's ok to unwrap, because hash have to exist at this point
         (download.notify_handler)(Notification::StrayHash(update_hash.unwrap()));
         std::fs::remove_file(update_hash.unwrap())?;
@@ -541,10 +641,12 @@ pub fn update_from_dist<'a>(
         old_date,
         components,
         targets,
+        dry_run,
+        retain_old,
     );
 
     // Don't leave behind an empty / broken installation directory
-    if res.is_err() && fresh_install {
+    if res.is_err() && fresh_install && !dry_run {
         // FIXME Ignoring cascading errors
         let _ = utils::remove_dir("toolchain", prefix.path(), download.notify_handler);
     }
@@ -556,14 +658,16 @@ fn update_from_dist_<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
-    profile: Option<Profile>,
+    profile: Option<&InstallProfile>,
     prefix: &InstallPrefix,
     force_update: bool,
     allow_downgrade: bool,
     old_date: Option<&str>,
     components: &[&str],
     targets: &[&str],
-) -> Result<Option<String>> {
+    dry_run: bool,
+    retain_old: Option<&Path>,
+) -> Result<Option<DistUpdate>> {
     let mut toolchain = toolchain.clone();
     let mut fetched = String::new();
     let mut first_err = None;
@@ -617,6 +721,8 @@ fn update_from_dist_<'a>(
             force_update,
             components,
             targets,
+            dry_run,
+            retain_old,
             &mut fetched,
         ) {
             Ok(v) => break Ok(v),
@@ -690,13 +796,15 @@ fn try_update_from_dist_<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
-    profile: Option<Profile>,
+    profile: Option<&InstallProfile>,
     prefix: &InstallPrefix,
     force_update: bool,
     components: &[&str],
     targets: &[&str],
+    dry_run: bool,
+    retain_old: Option<&Path>,
     fetched: &mut String,
-) -> Result<Option<String>> {
+) -> Result<Option<DistUpdate>> {
     let toolchain_str = toolchain.to_string();
     let manifestation = Manifestation::open(prefix.clone(), toolchain.target.clone())?;
 
@@ -720,55 +828,95 @@ fn try_update_from_dist_<'a>(
                 m.get_rust_version().ok(),
             ));
 
-            let profile_components = match profile {
-                Some(profile) => m.get_profile_components(profile, &toolchain.target)?,
-                None => Vec::new(),
-            };
-
-            use crate::dist::manifest::Component;
-            use std::collections::HashSet;
-
-            let mut all_components: HashSet<Component> = profile_components.into_iter().collect();
-
-            let rust_package = m.get_package("rust")?;
-            let rust_target_package = rust_package.get_target(Some(&toolchain.target.clone()))?;
-
-            for component in components.iter().copied() {
-                let mut component =
-                    Component::new(component.to_string(), Some(toolchain.target.clone()), false);
-                if let Some(renamed) = m.rename_component(&component) {
-                    component = renamed;
+            let mut all_components: HashSet<Component> = HashSet::new();
+            let mut remove_components = Vec::new();
+            if let Some(profile) = profile {
+                let (wanted, missing) = profile_components(&m, profile, &toolchain.target)?;
+                if !missing.is_empty() {
+                    (download.notify_handler)(Notification::ProfileComponentsMissing(
+                        &profile.to_string(),
+                        &toolchain_str,
+                        &missing,
+                    ));
                 }
-                // Look up the newly constructed/renamed component and ensure that
-                // if it's a wildcard component we note such, otherwise we end up
-                // exacerbating the problem we thought we'd fixed with #2087 and #2115
-                if let Some(c) = rust_target_package
-                    .components
-                    .iter()
-                    .find(|c| c.short_name_in_manifest() == component.short_name_in_manifest())
-                {
-                    if c.target.is_none() {
-                        component = component.wildcard();
+
+                let config = manifestation.read_config()?;
+                let old_manifest = manifestation.load_manifest()?;
+                match (config, old_manifest) {
+                    (Some(config), Some(old_manifest)) => {
+                        // Follow only what changed in the profile itself, so
+                        // that components the user removed aren't put back
+                        let old_components =
+                            profile_components(&old_manifest, profile, &toolchain.target)
+                                .map(|(components, _)| components)
+                                .unwrap_or_else(|_| wanted.clone());
+                        let (gained, lost) = profile_changes(&m, &config, &wanted, &old_components);
+                        let names = |components: &[Component]| {
+                            components
+                                .iter()
+                                .map(|c| c.short_name(&m))
+                                .collect::<Vec<_>>()
+                        };
+                        if !gained.is_empty() {
+                            (download.notify_handler)(Notification::ProfileComponentsGained(
+                                &profile.to_string(),
+                                &names(&gained),
+                            ));
+                        }
+                        if !lost.is_empty() {
+                            (download.notify_handler)(Notification::ProfileComponentsLost(
+                                &profile.to_string(),
+                                &names(&lost),
+                            ));
+                        }
+                        all_components.extend(gained);
+                        remove_components = lost;
                     }
+                    _ => all_components.extend(wanted),
                 }
-                all_components.insert(component);
             }
 
+            let mut requested_components = Vec::new();
+            for component in components {
+                let (component, _) = find_component(&m, component, &toolchain.target)?;
+                requested_components.push(component);
+            }
             for target in targets {
                 let triple = TargetTriple::new(target);
-                all_components.insert(Component::new("rust-std".to_string(), Some(triple), false));
+                requested_components.push(Component::new(
+                    "rust-std".to_string(),
+                    Some(triple),
+                    false,
+                ));
             }
+            all_components.extend(requested_components.iter().cloned());
+            remove_components.retain(|c| !all_components.contains(c));
 
             let mut explicit_add_components: Vec<_> = all_components.into_iter().collect();
             explicit_add_components.sort();
 
             let changes = Changes {
                 explicit_add_components,
-                remove_components: Vec::new(),
+                remove_components,
+                profile: profile.map(ToString::to_string),
+                requested_components,
             };
 
             *fetched = m.date.clone();
 
+            if dry_run {
+                return Ok(manifestation
+                    .plan_update(
+                        &m,
+                        changes,
+                        force_update,
+                        &download,
+                        &download.notify_handler,
+                        &toolchain.manifest_name(),
+                    )?
+                    .map(DistUpdate::Planned));
+            }
+
             return match manifestation.update(
                 &m,
                 changes,
@@ -777,9 +925,10 @@ fn try_update_from_dist_<'a>(
                 &download.notify_handler,
                 &toolchain.manifest_name(),
                 true,
+                retain_old,
             )? {
                 UpdateStatus::Unchanged => Ok(None),
-                UpdateStatus::Changed => Ok(Some(hash)),
+                UpdateStatus::Changed => Ok(Some(DistUpdate::Installed(hash))),
             };
         }
         Ok(None) => return Ok(None),
@@ -792,6 +941,13 @@ fn try_update_from_dist_<'a>(
     }
 
     // If the v2 manifest is not found then try v1
+    if dry_run {
+        return Err(format!(
+            "can't plan changes for '{}', which has no v2 manifest",
+            toolchain.manifest_name()
+        )
+        .into());
+    }
     let manifest = match dl_v1_manifest(download, toolchain) {
         Ok(m) => m,
         Err(Error(crate::ErrorKind::DownloadNotExists { .. }, _)) => {
@@ -817,9 +973,14 @@ fn try_update_from_dist_<'a>(
         &download.temp_cfg,
         &download.notify_handler,
         &download.pgp_keys,
+        download.retry_policy,
+        download.hosts,
+        download.throttle,
+        download.timeouts,
+        download.proxy,
     ) {
         Ok(None) => Ok(None),
-        Ok(Some(hash)) => Ok(Some(hash)),
+        Ok(Some(hash)) => Ok(Some(DistUpdate::Installed(hash))),
         e @ Err(Error(crate::ErrorKind::DownloadNotExists { .. }, _)) => e.chain_err(|| {
             format!(
                 "could not download nonexistent rust version `{}`",
@@ -830,6 +991,85 @@ fn try_update_from_dist_<'a>(
     }
 }
 
+// The components `profile` installs from `manifest`, and the names of
+// those a custom profile asks for that the manifest doesn't have.
+// Like those of the manifest's own profiles, but unlike explicitly
+// requested components, these are skipped rather than an error.
+fn profile_components(
+    manifest: &ManifestV2,
+    profile: &InstallProfile,
+    target: &TargetTriple,
+) -> Result<(Vec<Component>, Vec<String>)> {
+    let mut components = match profile.base() {
+        Some(base) => manifest.get_profile_components(base, target)?,
+        None => Vec::new(),
+    };
+    let mut missing = Vec::new();
+    for name in profile.components() {
+        match find_component(manifest, name, target)? {
+            (component, true) => components.push(component),
+            (_, false) => missing.push(name.clone()),
+        }
+    }
+    Ok((components, missing))
+}
+
+// The component `name` for `target`, allowing for it having been renamed
+// and for it being a wildcard component, and whether the manifest has it.
+fn find_component(
+    manifest: &ManifestV2,
+    name: &str,
+    target: &TargetTriple,
+) -> Result<(Component, bool)> {
+    let rust_target_package = manifest.get_package("rust")?.get_target(Some(target))?;
+
+    let mut component = Component::new(name.to_string(), Some(target.clone()), false);
+    if let Some(renamed) = manifest.rename_component(&component) {
+        component = renamed;
+    }
+    // Look up the newly constructed/renamed component and ensure that
+    // if it's a wildcard component we note such, otherwise we end up
+    // exacerbating the problem we thought we'd fixed with #2087 and #2115
+    match rust_target_package
+        .components
+        .iter()
+        .find(|c| c.short_name_in_manifest() == component.short_name_in_manifest())
+    {
+        Some(c) if c.target.is_none() => Ok((component.wildcard(), true)),
+        Some(_) => Ok((component, true)),
+        None => Ok((component, false)),
+    }
+}
+
+// The components a profile gained between the installed manifest and
+// `manifest` that aren't installed yet, and those it lost that are still
+// installed but weren't requested by the user.
+fn profile_changes(
+    manifest: &ManifestV2,
+    config: &Config,
+    components: &[Component],
+    old_components: &[Component],
+) -> (Vec<Component>, Vec<Component>) {
+    let renamed = |c: &Component| manifest.rename_component(c).unwrap_or_else(|| c.clone());
+    let old_renamed: Vec<_> = old_components.iter().map(renamed).collect();
+
+    let gained = components
+        .iter()
+        .filter(|c| !old_renamed.contains(c) && !config.components.contains(c))
+        .cloned()
+        .collect();
+    let lost = old_components
+        .iter()
+        .filter(|c| {
+            !components.contains(&renamed(c))
+                && config.components.contains(c)
+                && !config.requested_components.contains(c)
+        })
+        .cloned()
+        .collect();
+    (gained, lost)
+}
+
 pub fn dl_v2_manifest<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
@@ -1,15 +1,26 @@
 use crate::config::PgpPublicKey;
+use crate::dist::manifest_cache::{CachedManifest, ManifestCache};
 use crate::dist::notifications::*;
 use crate::dist::temp;
 use crate::errors::*;
+use crate::utils::lock::FileLock;
+use crate::utils::retry_policy::RetryPolicy;
 use crate::utils::utils;
+use crate::utils::Notification as Un;
 
 use sha2::{Digest, Sha256};
 use url::Url;
 
+use std::cell::RefCell;
 use std::fs;
+use std::io;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 const UPDATE_HASH_LEN: usize = 20;
 
@@ -20,6 +31,25 @@ pub struct DownloadCfg<'a> {
     pub download_dir: &'a PathBuf,
     pub notify_handler: &'a dyn Fn(Notification<'_>),
     pub pgp_keys: &'a [PgpPublicKey],
+    pub retry_policy: RetryPolicy,
+    /// How many packages to download at once
+    pub concurrent_downloads: usize,
+    /// Whether packages not already in the download cache are unpacked as
+    /// they're downloaded, rather than first saved to it
+    pub stream_packages: bool,
+    /// How to make requests to hosts that need more than the defaults,
+    /// such as a mirror with its own CA
+    pub hosts: &'a download::HostConfigs,
+    /// The cap on how fast packages and manifests are downloaded, shared by
+    /// every download at once
+    pub throttle: Option<&'a download::Throttle>,
+    /// How long to wait on dist servers before giving up on a download
+    pub timeouts: download::Timeouts,
+    /// Which proxies to go through, if not those the environment says
+    pub proxy: Option<&'a download::ProxyConfig>,
+    /// Manifests downloaded before, to ask the server whether they've
+    /// changed rather than download them again, if they're kept
+    pub manifest_cache: Option<&'a ManifestCache>,
 }
 
 pub struct File {
@@ -34,7 +64,290 @@ impl ops::Deref for File {
     }
 }
 
+/// A file for `DownloadCfg::download_concurrently` to fetch.
+pub struct PendingDownload {
+    /// The component the file is the package for, as named in progress
+    /// reports and errors
+    pub component: String,
+    pub url: Url,
+    pub hash: String,
+}
+
+// What a download worker reports back to the thread that started it.
+enum Progress {
+    Started,
+    DownloadingFile(PathBuf),
+    ContentLength(u64),
+    DataReceived(usize),
+    Throttled(u64),
+    Resuming,
+    Restarted,
+    AlreadyDownloaded,
+    CachedFileChecksumFailed,
+    Retrying(Duration),
+    Finished(Result<File>),
+}
+
+/// Downloads started by `DownloadCfg::download_concurrently`. Iterating
+/// yields the files in the order they were asked for, each as soon as it
+/// and those before it are downloaded, while the rest carry on in the
+/// background. After the first failure nothing more is yielded. Dropping
+/// this abandons any downloads still in flight.
+pub struct ConcurrentDownloads<'a> {
+    cfg: DownloadCfg<'a>,
+    downloads: Vec<PendingDownload>,
+    /// Files downloaded but not yet yielded
+    files: Vec<Option<File>>,
+    /// The index of the next file to yield
+    next: usize,
+    /// `None` once every worker has stopped
+    rx: Option<Receiver<(usize, Progress)>>,
+    cancelled: Arc<AtomicBool>,
+    started: Instant,
+    finished: Option<Instant>,
+    /// Time spent by the caller waiting for downloads
+    waited: Duration,
+    _pool: threadpool::ThreadPool,
+}
+
+impl<'a> ConcurrentDownloads<'a> {
+    /// The time from starting the downloads until they all stopped, or
+    /// until now if they haven't.
+    pub fn elapsed(&self) -> Duration {
+        self.finished
+            .unwrap_or_else(Instant::now)
+            .saturating_duration_since(self.started)
+    }
+
+    /// The time spent waiting for files to be downloaded.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+
+    fn wait_for_next(&mut self) -> Option<Result<File>> {
+        loop {
+            if let Some(file) = self.files.get_mut(self.next)?.take() {
+                self.next += 1;
+                if self.next == self.downloads.len() {
+                    self.stop();
+                }
+                return Some(Ok(file));
+            }
+            let received = self.rx.as_ref().and_then(|rx| rx.recv().ok());
+            let result = match received {
+                Some((index, progress)) => match self.handle(index, progress) {
+                    Ok(()) => continue,
+                    Err(e) => Err(e),
+                },
+                // Every worker has stopped without the file, so its worker
+                // must have panicked
+                None => Err(ErrorKind::ComponentDownloadFailed(
+                    self.downloads[self.next].component.clone(),
+                )
+                .into()),
+            };
+            self.stop();
+            self.next = self.downloads.len();
+            return Some(result);
+        }
+    }
+
+    fn handle(&mut self, index: usize, progress: Progress) -> Result<()> {
+        let notify_handler = self.cfg.notify_handler;
+        let pending = &self.downloads[index];
+        let name = &pending.component;
+        match progress {
+            Progress::Started => notify_handler(Notification::ComponentDownloadStarted(name)),
+            Progress::DownloadingFile(path) => {
+                notify_handler(Un::DownloadingFile(&pending.url, &path).into())
+            }
+            Progress::ContentLength(len) => {
+                notify_handler(Notification::ComponentDownloadContentLength(name, len))
+            }
+            Progress::DataReceived(len) => {
+                notify_handler(Notification::ComponentDownloadDataReceived(name, len))
+            }
+            Progress::Throttled(rate) => notify_handler(Un::DownloadThrottled(rate).into()),
+            Progress::Resuming => notify_handler(Un::ResumingPartialDownload.into()),
+            Progress::Restarted => notify_handler(Un::DownloadRestarted.into()),
+            Progress::AlreadyDownloaded => notify_handler(Notification::FileAlreadyDownloaded),
+            Progress::CachedFileChecksumFailed => {
+                notify_handler(Notification::CachedFileChecksumFailed)
+            }
+            Progress::Retrying(delay) => {
+                notify_handler(Notification::RetryingDownload(pending.url.as_str(), delay))
+            }
+            Progress::Finished(Ok(file)) => {
+                notify_handler(Notification::ChecksumValid(pending.url.as_str()));
+                notify_handler(Notification::ComponentDownloadFinished(name));
+                self.files[index] = Some(file);
+            }
+            Progress::Finished(Err(e)) => {
+                notify_handler(Notification::ComponentDownloadFinished(name));
+                return Err(e).chain_err(|| ErrorKind::ComponentDownloadFailed(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // Abandons the downloads still in flight and waits for the workers to
+    // stop. Failures now are most likely just the cancellation.
+    fn stop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Some(rx) = self.rx.take() {
+            for (index, progress) in rx {
+                let _ = self.handle(index, progress);
+            }
+            (self.cfg.notify_handler)(Notification::ComponentDownloadsFinished);
+            self.finished = Some(Instant::now());
+        }
+    }
+}
+
+impl<'a> Iterator for ConcurrentDownloads<'a> {
+    type Item = Result<File>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let result = self.wait_for_next();
+        self.waited += start.elapsed();
+        result
+    }
+}
+
+impl<'a> Drop for ConcurrentDownloads<'a> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A file being downloaded in the background by
+/// `DownloadCfg::stream`, to be read as it arrives. What is read is
+/// hashed on the way through, and `finish` checks the hash; until then,
+/// nothing read should be trusted.
+pub struct StreamedDownload<'a> {
+    url: Url,
+    hash: String,
+    notify_handler: &'a dyn Fn(Notification<'_>),
+    /// `None` once the download has stopped
+    rx: Option<Receiver<Chunk>>,
+    /// The data received but not yet read
+    buf: Vec<u8>,
+    pos: usize,
+    hasher: Sha256,
+    /// Why the download stopped short, if it did
+    failure: Option<Error>,
+    cancelled: Arc<AtomicBool>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+// What the worker behind a `StreamedDownload` sends back.
+enum Chunk {
+    ContentLength(u64),
+    Throttled(u64),
+    Data(Vec<u8>),
+    Finished(Result<()>),
+}
+
+impl<'a> StreamedDownload<'a> {
+    /// Reads whatever hasn't been read yet, waits for the download to
+    /// stop, and checks that it was all there and has the expected hash.
+    pub fn finish(mut self) -> Result<()> {
+        io::copy(&mut self, &mut io::sink()).chain_err(|| ErrorKind::StreamingFile {
+            url: self.url.clone(),
+        })?;
+        self.stop();
+        if let Some(e) = self.failure.take() {
+            return Err(e);
+        }
+
+        let actual_hash = format!("{:x}", self.hasher.clone().finalize());
+        if self.hash != actual_hash {
+            return Err(ErrorKind::ChecksumFailed {
+                url: self.url.to_string(),
+                expected: self.hash.clone(),
+                calculated: actual_hash,
+            }
+            .into());
+        }
+        (self.notify_handler)(Notification::ChecksumValid(self.url.as_str()));
+        Ok(())
+    }
+
+    // Waits for the worker to stop, abandoning the download if it hasn't
+    // finished.
+    fn stop(&mut self) {
+        if self.rx.take().is_some() {
+            (self.notify_handler)(Un::DownloadFinished.into());
+        }
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<'a> io::Read for StreamedDownload<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            let chunk = match &self.rx {
+                Some(rx) => rx.recv().ok(),
+                None => return Ok(0),
+            };
+            match chunk {
+                Some(Chunk::ContentLength(len)) => {
+                    (self.notify_handler)(Un::DownloadContentLengthReceived(len).into());
+                }
+                Some(Chunk::Throttled(rate)) => {
+                    (self.notify_handler)(Un::DownloadThrottled(rate).into());
+                }
+                Some(Chunk::Data(data)) => {
+                    (self.notify_handler)(Un::DownloadDataReceived(&data).into());
+                    self.hasher.update(&data);
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                Some(Chunk::Finished(result)) => {
+                    self.failure = result.err();
+                    self.stop();
+                }
+                // The worker panicked
+                None => {
+                    self.failure = Some(
+                        ErrorKind::StreamingFile {
+                            url: self.url.clone(),
+                        }
+                        .into(),
+                    );
+                    self.stop();
+                }
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<'a> Drop for StreamedDownload<'a> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
 impl<'a> DownloadCfg<'a> {
+    /// How to download `url`, as set up for its host.
+    pub fn options(&self, url: &Url) -> download::Options<'a> {
+        download::Options {
+            host: self.hosts.for_url(url),
+            throttle: self.throttle,
+            timeouts: self.timeouts,
+            proxy: self.proxy,
+            cached: None,
+        }
+    }
+
     /// Downloads a file, validating its hash, and resuming interrupted downloads
     /// Partial downloads are stored in `self.download_dir`, keyed by hash. If the
     /// target file already exists, then the hash is checked and it is returned
@@ -45,73 +358,206 @@ impl<'a> DownloadCfg<'a> {
             &self.download_dir,
             &self.notify_handler,
         )?;
-        let target_file = self.download_dir.join(Path::new(hash));
-
-        if target_file.exists() {
-            let cached_result = file_hash(&target_file, self.notify_handler)?;
-            if hash == cached_result {
-                (self.notify_handler)(Notification::FileAlreadyDownloaded);
-                (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
-                return Ok(File { path: target_file });
-            } else {
-                (self.notify_handler)(Notification::CachedFileChecksumFailed);
-                fs::remove_file(&target_file).chain_err(|| "cleaning up previous download")?;
-            }
-        }
+        download_to_dir(
+            self.download_dir,
+            url,
+            hash,
+            utils::download_backend(),
+            &self.options(url),
+            None,
+            self.notify_handler,
+        )
+    }
 
-        let partial_file_path = target_file.with_file_name(
-            target_file
-                .file_name()
-                .map(|s| s.to_str().unwrap_or("_"))
-                .unwrap_or("_")
-                .to_owned()
-                + ".partial",
-        );
+    /// Starts downloading several files as `download` does, up to
+    /// `self.concurrent_downloads` of them at once, retrying each as
+    /// `self.retry_policy` says. The returned `ConcurrentDownloads` hands
+    /// them back in order.
+    pub fn download_concurrently(
+        &self,
+        downloads: Vec<PendingDownload>,
+    ) -> Result<ConcurrentDownloads<'a>> {
+        utils::ensure_dir_exists(
+            "Download Directory",
+            &self.download_dir,
+            &self.notify_handler,
+        )?;
 
-        let partial_file_existed = partial_file_path.exists();
+        // The workers can't call the notification handler, so they report
+        // back through a channel and the notifications are sent as the
+        // downloads are waited for.
+        let backend = utils::download_backend();
+        let retry_policy = self.retry_policy;
+        let throttle = self.throttle.cloned();
+        let timeouts = self.timeouts;
+        let proxy = self.proxy.cloned();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let pool = threadpool::Builder::new()
+            .thread_name("Download".into())
+            .num_threads(self.concurrent_downloads.max(1))
+            .build();
+        let (tx, rx) = channel();
+        for (index, pending) in downloads.iter().enumerate() {
+            let tx = tx.clone();
+            let cancelled = cancelled.clone();
+            let download_dir = self.download_dir.clone();
+            let url = pending.url.clone();
+            let hash = pending.hash.clone();
+            let host = self.hosts.for_url(&url).cloned();
+            let throttle = throttle.clone();
+            let proxy = proxy.clone();
+            pool.execute(move || {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let send = |progress: Progress| {
+                    let _ = tx.send((index, progress));
+                };
+                send(Progress::Started);
+                let options = download::Options {
+                    host: host.as_ref(),
+                    throttle: throttle.as_ref(),
+                    timeouts,
+                    proxy: proxy.as_ref(),
+                    cached: None,
+                };
+                let result = retry_policy.retry(
+                    Some(&cancelled),
+                    || {
+                        download_to_dir(
+                            &download_dir,
+                            &url,
+                            &hash,
+                            backend,
+                            &options,
+                            Some(&cancelled),
+                            &|n| match n {
+                                Notification::Utils(Un::DownloadingFile(_, path)) => {
+                                    send(Progress::DownloadingFile(path.to_owned()))
+                                }
+                                Notification::Utils(Un::DownloadContentLengthReceived(len)) => {
+                                    send(Progress::ContentLength(len))
+                                }
+                                Notification::Utils(Un::DownloadDataReceived(data)) => {
+                                    send(Progress::DataReceived(data.len()))
+                                }
+                                Notification::Utils(Un::DownloadThrottled(rate)) => {
+                                    send(Progress::Throttled(rate))
+                                }
+                                Notification::Utils(Un::ResumingPartialDownload) => {
+                                    send(Progress::Resuming)
+                                }
+                                Notification::Utils(Un::DownloadRestarted) => {
+                                    send(Progress::Restarted)
+                                }
+                                Notification::FileAlreadyDownloaded => {
+                                    send(Progress::AlreadyDownloaded)
+                                }
+                                Notification::CachedFileChecksumFailed => {
+                                    send(Progress::CachedFileChecksumFailed)
+                                }
+                                _ => {}
+                            },
+                        )
+                    },
+                    &|delay| send(Progress::Retrying(delay)),
+                );
+                send(Progress::Finished(result));
+            });
+        }
 
-        let mut hasher = Sha256::new();
+        Ok(ConcurrentDownloads {
+            cfg: *self,
+            files: downloads.iter().map(|_| None).collect(),
+            downloads,
+            next: 0,
+            rx: Some(rx),
+            cancelled,
+            started: Instant::now(),
+            finished: None,
+            waited: Duration::from_secs(0),
+            _pool: pool,
+        })
+    }
 
-        if let Err(e) = utils::download_file_with_resume(
-            &url,
-            &partial_file_path,
-            Some(&mut hasher),
-            true,
-            &|n| (self.notify_handler)(n.into()),
-        ) {
-            if partial_file_existed {
-                return Err(e).chain_err(|| ErrorKind::BrokenPartialFile);
-            } else {
-                return Err(e);
-            }
+    /// Starts downloading a file in the background, to be read from the
+    /// returned `StreamedDownload` as it arrives rather than saved to
+    /// `self.download_dir`.
+    pub fn stream(&self, url: &Url, hash: &str) -> Result<StreamedDownload<'a>> {
+        // Enough to keep the network busy while the reader catches up,
+        // without holding much of the file in memory
+        const MAX_BUFFERED_CHUNKS: usize = 64;
+
+        let backend = utils::download_backend();
+        let host = self.hosts.for_url(url).cloned();
+        let throttle = self.throttle.cloned();
+        let timeouts = self.timeouts;
+        let proxy = self.proxy.cloned();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = sync_channel(MAX_BUFFERED_CHUNKS);
+        let worker = {
+            let worker_url = url.clone();
+            let cancelled = cancelled.clone();
+            thread::Builder::new()
+                .name("Download".into())
+                .spawn(move || {
+                    let send = |chunk: Chunk| {
+                        let _ = tx.send(chunk);
+                    };
+                    let options = download::Options {
+                        host: host.as_ref(),
+                        throttle: throttle.as_ref(),
+                        timeouts,
+                        proxy: proxy.as_ref(),
+                        cached: None,
+                    };
+                    let result = utils::stream_file_with_backend(
+                        backend,
+                        &options,
+                        &worker_url,
+                        &cancelled,
+                        &|data| send(Chunk::Data(data.to_vec())),
+                        &|n| match n {
+                            Un::DownloadContentLengthReceived(len) => {
+                                send(Chunk::ContentLength(len))
+                            }
+                            Un::DownloadThrottled(rate) => send(Chunk::Throttled(rate)),
+                            _ => {}
+                        },
+                    );
+                    send(Chunk::Finished(result));
+                })
+                .chain_err(|| ErrorKind::StreamingFile { url: url.clone() })?
         };
 
-        let actual_hash = format!("{:x}", hasher.finalize());
+        Ok(StreamedDownload {
+            url: url.clone(),
+            hash: hash.to_owned(),
+            notify_handler: self.notify_handler,
+            rx: Some(rx),
+            buf: Vec::new(),
+            pos: 0,
+            hasher: Sha256::new(),
+            failure: None,
+            cancelled,
+            worker: Some(worker),
+        })
+    }
 
-        if hash != actual_hash {
-            // Incorrect hash
-            if partial_file_existed {
-                self.clean(&[hash.to_string() + &".partial".to_string()])?;
-                Err(ErrorKind::BrokenPartialFile.into())
-            } else {
-                Err(ErrorKind::ChecksumFailed {
-                    url: url.to_string(),
-                    expected: hash.to_string(),
-                    calculated: actual_hash,
-                }
-                .into())
-            }
-        } else {
-            (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
-
-            utils::rename_file(
-                "downloaded",
-                &partial_file_path,
-                &target_file,
-                self.notify_handler,
-            )?;
-            Ok(File { path: target_file })
-        }
+    /// Whether the package with this hash has already been downloaded.
+    pub fn is_cached(&self, hash: &str) -> bool {
+        self.download_dir.join(hash).exists()
+    }
+
+    /// Lock the package with this hash in the download cache, which is
+    /// shared by all toolchains, against other rustup processes for as long
+    /// as the returned guard is held, so that only one of them downloads it
+    /// at a time.
+    pub fn lock(&self, hash: &str) -> Result<FileLock> {
+        let path = self.download_dir.join(format!("{}.lock", hash));
+        FileLock::acquire_transient(&path, &|n: crate::utils::Notification<'_>| {
+            (self.notify_handler)(n.into())
+        })
     }
 
     pub fn clean(&self, hashes: &[String]) -> Result<()> {
@@ -124,13 +570,84 @@ impl<'a> DownloadCfg<'a> {
         Ok(())
     }
 
+    // Downloads `url` to `path`, retrying as `self.retry_policy` says,
+    // and returns its hash.
+    fn download_file(&self, url: &Url, path: &Path) -> Result<String> {
+        self.download_file_versioned(url, path, None)
+            .map(|(hash, _)| hash)
+    }
+
+    // Downloads `url` to `path` as `download_file` does, unless it hasn't
+    // changed since the `cached` version, if given, in which case this
+    // fails as `utils::is_not_modified` says. Returns its hash and version.
+    fn download_file_versioned(
+        &self,
+        url: &Url,
+        path: &Path,
+        cached: Option<&download::CachedVersion>,
+    ) -> Result<(String, download::CachedVersion)> {
+        let options = download::Options {
+            cached,
+            ..self.options(url)
+        };
+        self.retry_policy.retry(
+            None,
+            || {
+                // A retry starts the download afresh, so the hash must too
+                let mut hasher = Sha256::new();
+                let version = RefCell::new(download::CachedVersion::default());
+                utils::download_file_with_backend(
+                    utils::download_backend(),
+                    &options,
+                    url,
+                    path,
+                    Some(&mut hasher),
+                    false,
+                    None,
+                    &|n| {
+                        if let Un::DownloadVersionReceived(etag, last_modified) = n {
+                            *version.borrow_mut() = download::CachedVersion {
+                                etag: etag.map(ToOwned::to_owned),
+                                last_modified: last_modified.map(ToOwned::to_owned),
+                            };
+                        }
+                        (self.notify_handler)(n.into())
+                    },
+                )?;
+                Ok((format!("{:x}", hasher.finalize()), version.into_inner()))
+            },
+            &|delay| (self.notify_handler)(Notification::RetryingDownload(url.as_str(), delay)),
+        )
+    }
+
+    // Asks the server whether the manifest `cached` from `url` has changed,
+    // unless it was fetched too recently to need to, returning `None` if it
+    // hasn't, or else downloading it to `path` and returning its hash and
+    // version.
+    fn download_manifest_if_changed(
+        &self,
+        url: &Url,
+        path: &Path,
+        cache: &ManifestCache,
+        cached: &CachedManifest,
+    ) -> Result<Option<(String, download::CachedVersion)>> {
+        if cache.is_fresh(cached) {
+            return Ok(None);
+        }
+        match self.download_file_versioned(url, path, Some(&cached.version)) {
+            Err(ref e) if utils::is_not_modified(e) => {
+                cache.refresh(cached)?;
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
     fn download_hash(&self, url: &str) -> Result<String> {
         let hash_url = utils::parse_url(&(url.to_owned() + ".sha256"))?;
         let hash_file = self.temp_cfg.new_file()?;
 
-        utils::download_file(&hash_url, &hash_file, None, &|n| {
-            (self.notify_handler)(n.into())
-        })?;
+        self.download_file(&hash_url, &hash_file)?;
 
         Ok(utils::read_file("hash", &hash_file).map(|s| s[0..64].to_owned())?)
     }
@@ -139,9 +656,7 @@ impl<'a> DownloadCfg<'a> {
         let sig_url = utils::parse_url(&(url.to_owned() + ".asc"))?;
         let sig_file = self.temp_cfg.new_file()?;
 
-        utils::download_file(&sig_url, &sig_file, None, &|n| {
-            (self.notify_handler)(n.into())
-        })?;
+        self.download_file(&sig_url, &sig_file)?;
 
         Ok(utils::read_file("signature", &sig_file)?)
     }
@@ -182,38 +697,54 @@ impl<'a> DownloadCfg<'a> {
     /// and if they match, the download is skipped.
     /// Verifies the signature found at the same url with a `.asc` suffix, and prints a
     /// warning when the signature does not verify, or is not found.
+    ///
+    /// Manifests, unlike tarballs, are kept in `self.manifest_cache`, if
+    /// given, and only downloaded again once the server says they've
+    /// changed since.
     pub fn download_and_check(
         &self,
         url_str: &str,
         update_hash: Option<&Path>,
         ext: &str,
     ) -> Result<Option<(temp::File<'a>, String)>> {
-        let hash = self.download_hash(url_str)?;
-        let partial_hash: String = hash.chars().take(UPDATE_HASH_LEN).collect();
+        let is_manifest = !url_str.ends_with(".tar.gz") && !url_str.ends_with(".tar.xz");
+        let cache = self.manifest_cache.filter(|_| is_manifest);
+        let cached = cache.and_then(|cache| cache.get(url_str));
+
+        let url = utils::parse_url(url_str)?;
+        let file = self.temp_cfg.new_file_with_ext("", ext)?;
 
-        if let Some(hash_file) = update_hash {
-            if utils::is_file(hash_file) {
-                if let Ok(contents) = utils::read_file("update hash", hash_file) {
-                    if contents == partial_hash {
-                        // Skip download, update hash matches
-                        return Ok(None);
+        let downloaded = match (cache, &cached) {
+            (Some(cache), Some(cached)) => {
+                match self.download_manifest_if_changed(&url, &file, cache, cached)? {
+                    None => {
+                        (self.notify_handler)(Notification::ManifestUnchanged(url_str));
+                        let partial_hash: String =
+                            cached.hash.chars().take(UPDATE_HASH_LEN).collect();
+                        if self.is_up_to_date(update_hash, &partial_hash) {
+                            return Ok(None);
+                        }
+                        utils::copy_file(&cached.path, &file)?;
+                        return Ok(Some((file, partial_hash)));
                     }
-                } else {
-                    (self.notify_handler)(Notification::CantReadUpdateHash(hash_file));
+                    downloaded => downloaded,
                 }
-            } else {
-                (self.notify_handler)(Notification::NoUpdateHash(hash_file));
             }
-        }
+            _ => None,
+        };
 
-        let url = utils::parse_url(url_str)?;
-        let file = self.temp_cfg.new_file_with_ext("", ext)?;
+        let hash = self.download_hash(url_str)?;
+        let partial_hash: String = hash.chars().take(UPDATE_HASH_LEN).collect();
 
-        let mut hasher = Sha256::new();
-        utils::download_file(&url, &file, Some(&mut hasher), &|n| {
-            (self.notify_handler)(n.into())
-        })?;
-        let actual_hash = format!("{:x}", hasher.finalize());
+        let (actual_hash, version) = match downloaded {
+            Some(downloaded) => downloaded,
+            None => {
+                if self.is_up_to_date(update_hash, &partial_hash) {
+                    return Ok(None);
+                }
+                self.download_file_versioned(&url, &file, None)?
+            }
+        };
 
         if hash != actual_hash {
             // Incorrect hash
@@ -228,15 +759,136 @@ impl<'a> DownloadCfg<'a> {
         }
 
         // No signatures for tarballs for now.
-        if !url_str.ends_with(".tar.gz") && !url_str.ends_with(".tar.xz") {
+        if is_manifest {
             match self.check_signature(&url_str, &file) {
                 Ok(key) => (self.notify_handler)(Notification::SignatureValid(url_str, key)),
                 Err(_) => (self.notify_handler)(Notification::SignatureInvalid(url_str)),
             }
         }
 
+        // Without a version, there's no asking whether it's changed
+        if let Some(cache) = cache {
+            if version != download::CachedVersion::default() {
+                cache.insert(url_str, &hash, version, &file)?;
+            }
+        }
+
+        // A manifest that had changed since it was cached may still be the
+        // one installed
+        if self.is_up_to_date(update_hash, &partial_hash) {
+            return Ok(None);
+        }
+
         Ok(Some((file, partial_hash)))
     }
+
+    // Whether the `partial_hash` of what's to be downloaded is that in the
+    // `update_hash` file, if given, so it's been installed already.
+    fn is_up_to_date(&self, update_hash: Option<&Path>, partial_hash: &str) -> bool {
+        let hash_file = match update_hash {
+            Some(hash_file) => hash_file,
+            None => return false,
+        };
+        if !utils::is_file(hash_file) {
+            (self.notify_handler)(Notification::NoUpdateHash(hash_file));
+            return false;
+        }
+        match utils::read_file("update hash", hash_file) {
+            Ok(contents) => contents == partial_hash,
+            Err(_) => {
+                (self.notify_handler)(Notification::CantReadUpdateHash(hash_file));
+                false
+            }
+        }
+    }
+}
+
+// Downloads `url` into `download_dir` as `DownloadCfg::download` does,
+// without using anything that is tied to the main thread.
+fn download_to_dir(
+    download_dir: &Path,
+    url: &Url,
+    hash: &str,
+    backend: download::Backend,
+    options: &download::Options<'_>,
+    cancel: Option<&AtomicBool>,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<File> {
+    let target_file = download_dir.join(Path::new(hash));
+
+    if target_file.exists() {
+        let cached_result = file_hash(&target_file, notify_handler)?;
+        if hash == cached_result {
+            notify_handler(Notification::FileAlreadyDownloaded);
+            notify_handler(Notification::ChecksumValid(&url.to_string()));
+            return Ok(File { path: target_file });
+        } else {
+            notify_handler(Notification::CachedFileChecksumFailed);
+            fs::remove_file(&target_file).chain_err(|| "cleaning up previous download")?;
+        }
+    }
+
+    let partial_file_path = target_file.with_file_name(
+        target_file
+            .file_name()
+            .map(|s| s.to_str().unwrap_or("_"))
+            .unwrap_or("_")
+            .to_owned()
+            + ".partial",
+    );
+
+    let partial_file_existed = partial_file_path.exists();
+
+    // Downloads the file, resuming the partial download if there is one,
+    // and returns its hash
+    let download = || -> Result<String> {
+        let mut hasher = Sha256::new();
+        utils::download_file_with_backend(
+            backend,
+            options,
+            &url,
+            &partial_file_path,
+            Some(&mut hasher),
+            true,
+            cancel,
+            &|n| notify_handler(n.into()),
+        )?;
+        Ok(format!("{:x}", hasher.finalize()))
+    };
+
+    let mut result = download();
+    let cancelled = cancel.map_or(false, |c| c.load(Ordering::Relaxed));
+    if partial_file_existed && !cancelled && result.as_ref().map_or(true, |h| h != hash) {
+        // The partial download may have been damaged, or be of another
+        // version of the file the server couldn't tell had changed, so
+        // download it afresh
+        notify_handler(Un::DownloadRestarted.into());
+        if partial_file_path.exists() {
+            fs::remove_file(&partial_file_path).chain_err(|| "cleaning up cached downloads")?;
+        }
+        result = download().chain_err(|| ErrorKind::BrokenPartialFile);
+    }
+    let actual_hash = result?;
+
+    if hash != actual_hash {
+        // Incorrect hash
+        Err(ErrorKind::ChecksumFailed {
+            url: url.to_string(),
+            expected: hash.to_string(),
+            calculated: actual_hash,
+        }
+        .into())
+    } else {
+        notify_handler(Notification::ChecksumValid(&url.to_string()));
+
+        utils::rename_file(
+            "downloaded",
+            &partial_file_path,
+            &target_file,
+            notify_handler,
+        )?;
+        Ok(File { path: target_file })
+    }
 }
 
 fn file_hash(path: &Path, notify_handler: &dyn Fn(Notification<'_>)) -> Result<String> {
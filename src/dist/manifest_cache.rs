@@ -0,0 +1,226 @@
+//! Channel manifests kept from earlier downloads, so that checking for
+//! updates can ask the server whether they've changed rather than download
+//! them again.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::*;
+use crate::utils::toml_utils::*;
+use crate::utils::utils;
+use crate::utils::Notification;
+
+/// Manifests downloaded before, kept in a directory by their URL.
+#[derive(Clone, Debug)]
+pub struct ManifestCache {
+    dir: PathBuf,
+    /// How long after a manifest's been fetched it's used without asking
+    /// the server whether it's changed
+    ttl: Duration,
+}
+
+/// A manifest in the cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedManifest {
+    pub url: String,
+    /// The manifest's SHA-256 hash, as its `.sha256` file gave it
+    pub hash: String,
+    /// What to ask the server for the manifest only if it's changed with
+    pub version: download::CachedVersion,
+    /// When the manifest was downloaded, or last found unchanged, in
+    /// seconds since the Unix epoch
+    pub fetched: u64,
+    /// Where the manifest itself is kept
+    pub path: PathBuf,
+}
+
+impl ManifestCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// The manifest cached from `url`, if there is one. One that's been
+    /// damaged since it was cached isn't returned.
+    pub fn get(&self, url: &str) -> Option<CachedManifest> {
+        let (data_path, path) = self.paths(url);
+        let data = fs::read_to_string(&data_path).ok()?;
+        let manifest = CachedManifest::parse(&data, path).ok()?;
+        let contents = fs::read(&manifest.path).ok()?;
+        let hash = format!("{:x}", Sha256::digest(&contents));
+        if manifest.url == url && manifest.hash == hash {
+            Some(manifest)
+        } else {
+            None
+        }
+    }
+
+    /// Caches the manifest downloaded from `url` to `downloaded`, with its
+    /// hash and version.
+    pub fn insert(
+        &self,
+        url: &str,
+        hash: &str,
+        version: download::CachedVersion,
+        downloaded: &Path,
+    ) -> Result<()> {
+        utils::ensure_dir_exists("manifest cache", &self.dir, &|_: Notification<'_>| ())?;
+        let (data_path, path) = self.paths(url);
+        utils::copy_file(downloaded, &path)?;
+        let manifest = CachedManifest {
+            url: url.to_owned(),
+            hash: hash.to_owned(),
+            version,
+            fetched: now(),
+            path,
+        };
+        utils::write_file("cached manifest data", &data_path, &manifest.stringify())
+    }
+
+    /// Notes that `manifest` has been found not to have changed since it
+    /// was cached, so it's fresh again.
+    pub fn refresh(&self, manifest: &CachedManifest) -> Result<()> {
+        let (data_path, _) = self.paths(&manifest.url);
+        let manifest = CachedManifest {
+            fetched: now(),
+            ..manifest.clone()
+        };
+        utils::write_file("cached manifest data", &data_path, &manifest.stringify())
+    }
+
+    /// Whether `manifest` was fetched recently enough to be used without
+    /// asking the server whether it's changed.
+    pub fn is_fresh(&self, manifest: &CachedManifest) -> bool {
+        now().saturating_sub(manifest.fetched) < self.ttl.as_secs()
+    }
+
+    // Where what's known of the manifest cached from `url` is kept, and
+    // where the manifest itself is.
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+        (
+            self.dir.join(format!("{}.toml", key)),
+            self.dir.join(format!("{}.manifest", key)),
+        )
+    }
+}
+
+impl CachedManifest {
+    fn parse(data: &str, path: PathBuf) -> Result<Self> {
+        let mut table: toml::value::Table =
+            toml::from_str(data).chain_err(|| "parsing cached manifest data")?;
+        let fetched = get_opt_integer(&mut table, "fetched", "")?.unwrap_or(0);
+        Ok(Self {
+            url: get_string(&mut table, "url", "")?,
+            hash: get_string(&mut table, "hash", "")?,
+            version: download::CachedVersion {
+                etag: get_opt_string(&mut table, "etag", "")?,
+                last_modified: get_opt_string(&mut table, "last_modified", "")?,
+            },
+            fetched: fetched.max(0) as u64,
+            path,
+        })
+    }
+
+    fn stringify(&self) -> String {
+        let mut table = toml::value::Table::new();
+        table.insert("url".to_owned(), toml::Value::String(self.url.clone()));
+        table.insert("hash".to_owned(), toml::Value::String(self.hash.clone()));
+        if let Some(etag) = &self.version.etag {
+            table.insert("etag".to_owned(), toml::Value::String(etag.clone()));
+        }
+        if let Some(last_modified) = &self.version.last_modified {
+            table.insert(
+                "last_modified".to_owned(),
+                toml::Value::String(last_modified.clone()),
+            );
+        }
+        table.insert(
+            "fetched".to_owned(),
+            toml::Value::Integer(self.fetched as i64),
+        );
+        toml::Value::Table(table).to_string()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(ttl: Duration) -> (tempfile::TempDir, ManifestCache) {
+        let dir = tempfile::Builder::new()
+            .prefix("rustup-manifest-cache")
+            .tempdir()
+            .unwrap();
+        let cache = ManifestCache::new(dir.path().join("manifests"), ttl);
+        (dir, cache)
+    }
+
+    fn download(dir: &Path, contents: &str) -> (PathBuf, String) {
+        let path = dir.join("downloaded");
+        fs::write(&path, contents).unwrap();
+        (path, format!("{:x}", Sha256::digest(contents.as_bytes())))
+    }
+
+    #[test]
+    fn cached_manifests_are_kept_by_url() {
+        let (dir, cache) = cache(Duration::from_secs(60));
+        let url = "https://static.rust-lang.org/dist/channel-rust-stable.toml";
+        let version = download::CachedVersion {
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: None,
+        };
+        let (downloaded, hash) = download(dir.path(), "manifest-version = \"2\"");
+        cache
+            .insert(url, &hash, version.clone(), &downloaded)
+            .unwrap();
+
+        let manifest = cache.get(url).unwrap();
+        assert_eq!(manifest.hash, hash);
+        assert_eq!(manifest.version, version);
+        assert_eq!(
+            fs::read_to_string(&manifest.path).unwrap(),
+            "manifest-version = \"2\""
+        );
+        assert!(cache.is_fresh(&manifest));
+        assert_eq!(cache.get(&url.replace("stable", "beta")), None);
+    }
+
+    #[test]
+    fn damaged_manifests_are_not_used() {
+        let (dir, cache) = cache(Duration::from_secs(60));
+        let url = "https://static.rust-lang.org/dist/channel-rust-stable.toml";
+        let (downloaded, hash) = download(dir.path(), "manifest-version = \"2\"");
+        cache
+            .insert(url, &hash, download::CachedVersion::default(), &downloaded)
+            .unwrap();
+
+        let manifest = cache.get(url).unwrap();
+        fs::write(&manifest.path, "manifest-vers").unwrap();
+        assert_eq!(cache.get(url), None);
+    }
+
+    #[test]
+    fn stale_manifests_are_fresh_again_once_refreshed() {
+        let (dir, cache) = cache(Duration::from_secs(60));
+        let url = "https://static.rust-lang.org/dist/channel-rust-stable.toml";
+        let (downloaded, hash) = download(dir.path(), "manifest-version = \"2\"");
+        cache
+            .insert(url, &hash, download::CachedVersion::default(), &downloaded)
+            .unwrap();
+
+        let mut manifest = cache.get(url).unwrap();
+        manifest.fetched -= 60;
+        assert!(!cache.is_fresh(&manifest));
+        cache.refresh(&manifest).unwrap();
+        assert!(cache.is_fresh(&cache.get(url).unwrap()));
+    }
+}
@@ -1,22 +1,27 @@
 //! Maintains a Rust installation by installing individual Rust
 //! platform components from a distribution server.
 
-use std::path::Path;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use retry::delay::NoDelay;
-use retry::{retry, OperationResult};
+use url::Url;
 
 use crate::config::PgpPublicKey;
-use crate::dist::component::{Components, Package, TarGzPackage, TarXzPackage, Transaction};
+use crate::dist::component::{
+    recover_interrupted_transaction, Components, Package, TarGzPackage, TarXzPackage, Transaction,
+};
 use crate::dist::config::Config;
 use crate::dist::dist::{Profile, TargetTriple, DEFAULT_DIST_SERVER};
-use crate::dist::download::{DownloadCfg, File};
+use crate::dist::download::{DownloadCfg, File, PendingDownload};
 use crate::dist::manifest::{Component, Manifest, TargetedPackage};
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 use crate::errors::*;
-use crate::process;
+use crate::utils::raw;
+use crate::utils::retry_policy::RetryPolicy;
 use crate::utils::utils;
 
 pub const DIST_MANIFEST: &str = "multirust-channel-manifest.toml";
@@ -27,6 +32,14 @@ enum Format {
     Xz,
 }
 
+// Where the package for a component is installed from.
+enum PackageSource {
+    // Downloaded to the download cache
+    Downloaded(File),
+    // To be unpacked as it is downloaded from the URL, having the hash
+    Streamed(Url, String),
+}
+
 #[derive(Debug)]
 pub struct Manifestation {
     installation: Components,
@@ -37,6 +50,12 @@ pub struct Manifestation {
 pub struct Changes {
     pub explicit_add_components: Vec<Component>,
     pub remove_components: Vec<Component>,
+    /// The profile to record the toolchain as installed with, if any;
+    /// otherwise the recorded one is kept
+    pub profile: Option<String>,
+    /// Added components to record as asked for by the user, rather than
+    /// by the profile
+    pub requested_components: Vec<Component>,
 }
 
 impl Changes {
@@ -44,6 +63,8 @@ impl Changes {
         Self {
             explicit_add_components: Vec::new(),
             remove_components: Vec::new(),
+            profile: None,
+            requested_components: Vec::new(),
         }
     }
 
@@ -76,6 +97,52 @@ pub enum UpdateStatus {
     Unchanged,
 }
 
+/// The changes an update would make, worked out without making them.
+#[derive(Clone, Debug)]
+pub struct UpdatePlan {
+    /// Date of the installed manifest, if there is one
+    pub old_date: Option<String>,
+    /// Date of the manifest being installed
+    pub new_date: String,
+    pub changes: Vec<PlannedChange>,
+}
+
+/// A component an update would install, upgrade or remove.
+#[derive(Clone, Debug)]
+pub struct PlannedChange {
+    pub component: String,
+    /// `None` if the component isn't installed yet
+    pub old_version: Option<String>,
+    /// `None` if the component is being removed
+    pub new_version: Option<String>,
+    pub download: Option<PlannedDownload>,
+}
+
+/// A package an update would need.
+#[derive(Clone, Debug)]
+pub struct PlannedDownload {
+    pub url: String,
+    /// Whether the package is already in the download cache
+    pub cached: bool,
+    /// The size of the package, if the server reports it
+    pub size: Option<u64>,
+}
+
+impl UpdatePlan {
+    /// The total size of the packages still to be downloaded, and whether
+    /// the server reported the size of all of them.
+    pub fn download_size(&self) -> (u64, bool) {
+        self.changes
+            .iter()
+            .filter_map(|c| c.download.as_ref())
+            .filter(|d| !d.cached)
+            .fold((0, true), |(total, known), d| match d.size {
+                Some(size) => (total + size, known),
+                None => (total, false),
+            })
+    }
+}
+
 impl Manifestation {
     /// Open the install prefix for updates from a distribution
     /// channel.  The install prefix directory does not need to exist;
@@ -115,20 +182,272 @@ impl Manifestation {
         notify_handler: &dyn Fn(Notification<'_>),
         toolchain_str: &str,
         implicit_modify: bool,
+        retain_old: Option<&Path>,
     ) -> Result<UpdateStatus> {
         // Some vars we're going to need a few times
         let temp_cfg = download_cfg.temp_cfg;
         let prefix = self.installation.prefix();
-        let rel_installed_manifest_path = prefix.rel_manifest_file(DIST_MANIFEST);
-        let installed_manifest_path = prefix.path().join(&rel_installed_manifest_path);
 
         // Create the lists of components needed for installation
         let config = self.read_config()?;
-        let mut update =
-            Update::build_update(self, new_manifest, &changes, &config, notify_handler)?;
+        let update = match self.prepare_update(
+            new_manifest,
+            &changes,
+            &config,
+            force_update,
+            notify_handler,
+            toolchain_str,
+        )? {
+            Some(update) => update,
+            None => return Ok(UpdateStatus::Unchanged),
+        };
+
+        // Download component packages and validate hashes. Each package
+        // stays locked in the cache until it has been installed and cleaned
+        // up, so that another process can't remove it from under us, while
+        // those needing other packages carry on. Taking the locks in order
+        // keeps two processes from each waiting on a lock the other holds.
+        let components = update.components_urls_and_hashes(new_manifest)?;
+        let mut hashes: Vec<&str> = components
+            .iter()
+            .map(|(_, _, _, hash)| hash.as_str())
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        let _cache_locks = hashes
+            .into_iter()
+            .map(|hash| download_cfg.lock(hash))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Streamed packages are unpacked as they arrive rather than first
+        // saved to the download cache, unless they are there already.
+        let mut downloads = Vec::new();
+        let mut streamed = Vec::new();
+        for (component, _, url, hash) in &components {
+            notify_handler(Notification::DownloadingComponent(
+                &component.short_name(new_manifest),
+                &self.target_triple,
+                component.target.as_ref(),
+            ));
+            let url = utils::parse_url(&dist_server_url(url.clone(), temp_cfg))?;
+            if download_cfg.stream_packages && !download_cfg.is_cached(hash) {
+                streamed.push(Some(url));
+            } else {
+                streamed.push(None);
+                downloads.push(PendingDownload {
+                    component: component.name(new_manifest),
+                    url,
+                    hash: hash.clone(),
+                });
+            }
+        }
+        let things_downloaded: Vec<String> = components
+            .iter()
+            .map(|(_, _, _, hash)| hash.clone())
+            .collect();
+
+        // Each package is installed as soon as it and those before it have
+        // been downloaded, while the rest are still downloading. Nothing
+        // takes effect unless all of them are installed and the transaction
+        // commits, so a failed download leaves the toolchain untouched.
+        let start = Instant::now();
+        let mut downloads = download_cfg.download_concurrently(downloads)?;
+        let downloaded = &mut downloads;
+        let things_to_install = components.into_iter().zip(streamed).map(
+            |((component, format, _, hash), url)| -> Result<_> {
+                let source = match url {
+                    Some(url) => PackageSource::Streamed(url, hash),
+                    None => match downloaded.next() {
+                        Some(file) => PackageSource::Downloaded(file?),
+                        None => {
+                            unreachable!("a download is started for each package not streamed")
+                        }
+                    },
+                };
+                let package = self.unpack_component(
+                    new_manifest,
+                    &component,
+                    format,
+                    source,
+                    download_cfg,
+                    notify_handler,
+                )?;
+                Ok((component, package))
+            },
+        );
+
+        // Where possible the update is applied to a staging copy of the
+        // toolchain which is then swapped in, so that anything running from
+        // the toolchain meanwhile never sees a mix of old and new files.
+        match self.stage_update(&config, notify_handler)? {
+            Some(staged) => {
+                let staging = staged.installation.prefix().path().to_owned();
+                let result = staged
+                    .apply_update(
+                        new_manifest,
+                        update,
+                        &config,
+                        things_to_install,
+                        temp_cfg,
+                        notify_handler,
+                        implicit_modify,
+                    )
+                    .and_then(|()| swap_in_staged(&staging, prefix.path(), notify_handler));
+                // By now this holds either the failed update or, once swapped
+                // in, the old version of the toolchain, which may be kept.
+                // Whether the update was applied doesn't depend on what
+                // becomes of it, so failing to remove it, e.g. because a
+                // file in it is still open, is only reported.
+                let retained = match (&result, retain_old) {
+                    (Ok(()), Some(dest)) => retain_installation(&staging, dest, notify_handler),
+                    _ => Ok(()),
+                };
+                let removed = if utils::path_exists(&staging) {
+                    utils::remove_dir("staging", &staging, notify_handler)
+                } else {
+                    Ok(())
+                };
+                result?;
+                if let Err(e) = retained.and(removed) {
+                    notify_handler(Notification::NonFatalError(&e));
+                }
+            }
+            None => {
+                // Updating in place leaves nothing behind to keep, so the
+                // old version is copied just before it's updated.
+                if let Some(dest) = retain_old {
+                    copy_installation(&prefix, dest, notify_handler)?;
+                }
+                let installed = utils::path_exists(prefix.path());
+                let result = self.apply_update(
+                    new_manifest,
+                    update,
+                    &config,
+                    things_to_install,
+                    temp_cfg,
+                    notify_handler,
+                    implicit_modify,
+                );
+                if let (Err(_), Some(dest)) = (&result, retain_old) {
+                    // FIXME Ignoring cascading errors
+                    let _ = utils::remove_dir("retained version", dest, notify_handler);
+                }
+                // Rolling back a fresh install leaves the directories the
+                // transaction made, which would be taken for a toolchain.
+                if result.is_err() && !installed && utils::path_exists(prefix.path()) {
+                    // FIXME Ignoring cascading errors
+                    let _ = utils::remove_dir("toolchain", prefix.path(), notify_handler);
+                }
+                result?;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        notify_handler(Notification::UpdateTimings(
+            downloads.elapsed(),
+            elapsed.checked_sub(downloads.waited()).unwrap_or_default(),
+            elapsed,
+        ));
+        drop(downloads);
+
+        download_cfg.clean(&things_downloaded)?;
+
+        Ok(UpdateStatus::Changed)
+    }
+
+    /// Work out what `update` would do with the same arguments, without
+    /// downloading packages or touching the installation. Returns `None`
+    /// if nothing would change.
+    pub fn plan_update(
+        &self,
+        new_manifest: &Manifest,
+        changes: Changes,
+        force_update: bool,
+        download_cfg: &DownloadCfg<'_>,
+        notify_handler: &dyn Fn(Notification<'_>),
+        toolchain_str: &str,
+    ) -> Result<Option<UpdatePlan>> {
+        let config = self.read_config()?;
+        let update = match self.prepare_update(
+            new_manifest,
+            &changes,
+            &config,
+            force_update,
+            notify_handler,
+            toolchain_str,
+        )? {
+            Some(update) => update,
+            None => return Ok(None),
+        };
+
+        let old_manifest = self.load_manifest()?;
+        let old_version = |component: &Component| {
+            old_manifest.as_ref().and_then(|m| {
+                m.get_package(&component.short_name_in_manifest())
+                    .ok()
+                    .map(|p| p.version.clone())
+            })
+        };
+
+        let mut planned = Vec::new();
+        for (component, _, url, hash) in update.components_urls_and_hashes(new_manifest)? {
+            let url = dist_server_url(url, download_cfg.temp_cfg);
+            // Failing to find the size only makes the plan less complete
+            let size = utils::parse_url(&url)
+                .and_then(|url| utils::content_length(&url, &download_cfg.options(&url)))
+                .unwrap_or(None);
+            let old_version = if update.components_to_uninstall.contains(&component) {
+                old_version(&component)
+            } else {
+                None
+            };
+            planned.push(PlannedChange {
+                component: component.name(new_manifest),
+                old_version,
+                new_version: new_manifest
+                    .get_package(&component.short_name_in_manifest())
+                    .ok()
+                    .map(|p| p.version.clone()),
+                download: Some(PlannedDownload {
+                    cached: download_cfg.is_cached(&hash),
+                    url,
+                    size,
+                }),
+            });
+        }
+        for component in &update.components_to_uninstall {
+            if !update.components_to_install.contains(component) {
+                planned.push(PlannedChange {
+                    component: component.name(new_manifest),
+                    old_version: old_version(component),
+                    new_version: None,
+                    download: None,
+                });
+            }
+        }
+
+        Ok(Some(UpdatePlan {
+            old_date: old_manifest.map(|m| m.date),
+            new_date: new_manifest.date.clone(),
+            changes: planned,
+        }))
+    }
+
+    /// Plan the components to install and uninstall, checking that those
+    /// to install are available. Returns `None` if nothing would change.
+    fn prepare_update(
+        &self,
+        new_manifest: &Manifest,
+        changes: &Changes,
+        config: &Option<Config>,
+        force_update: bool,
+        notify_handler: &dyn Fn(Notification<'_>),
+        toolchain_str: &str,
+    ) -> Result<Option<Update>> {
+        let mut update = Update::build_update(self, new_manifest, changes, config, notify_handler)?;
 
         if update.nothing_changes() {
-            return Ok(UpdateStatus::Unchanged);
+            return Ok(None);
         }
 
         // Validate that the requested components are available
@@ -150,61 +469,31 @@ impl Manifestation {
             }
         }
 
-        let altered = temp_cfg.dist_server != DEFAULT_DIST_SERVER;
-
-        // Download component packages and validate hashes
-        let mut things_to_install: Vec<(Component, Format, File)> = Vec::new();
-        let mut things_downloaded: Vec<String> = Vec::new();
-        let components = update.components_urls_and_hashes(new_manifest)?;
-
-        const DEFAULT_MAX_RETRIES: usize = 3;
-        let max_retries: usize = process()
-            .var("RUSTUP_MAX_RETRIES")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(DEFAULT_MAX_RETRIES);
-
-        for (component, format, url, hash) in components {
-            notify_handler(Notification::DownloadingComponent(
-                &component.short_name(new_manifest),
-                &self.target_triple,
-                component.target.as_ref(),
-            ));
-            let url = if altered {
-                url.replace(DEFAULT_DIST_SERVER, temp_cfg.dist_server.as_str())
-            } else {
-                url
-            };
-
-            let url_url = utils::parse_url(&url)?;
-
-            let downloaded_file = retry(NoDelay.take(max_retries), || {
-                match download_cfg.download(&url_url, &hash) {
-                    Ok(f) => OperationResult::Ok(f),
-                    Err(e) => match e.kind() {
-                        // If there was a broken partial file, try again
-                        ErrorKind::DownloadingFile { .. } | ErrorKind::BrokenPartialFile => {
-                            notify_handler(Notification::RetryingDownload(&url));
-                            OperationResult::Retry(e)
-                        }
-
-                        _ => OperationResult::Err(e),
-                    },
-                }
-            })
-            .chain_err(|| ErrorKind::ComponentDownloadFailed(component.name(new_manifest)))?;
-
-            things_downloaded.push(hash);
+        Ok(Some(update))
+    }
 
-            things_to_install.push((component, format, downloaded_file));
-        }
+    /// Uninstall and install components as planned by `update`, and record
+    /// the new manifest and configuration, in a single transaction.
+    fn apply_update<'a>(
+        &self,
+        new_manifest: &Manifest,
+        update: Update,
+        config: &Option<Config>,
+        things_to_install: impl Iterator<Item = Result<(Component, Box<dyn Package + 'a>)>>,
+        temp_cfg: &temp::Cfg,
+        notify_handler: &dyn Fn(Notification<'_>),
+        implicit_modify: bool,
+    ) -> Result<()> {
+        let prefix = self.installation.prefix();
+        let rel_installed_manifest_path = prefix.rel_manifest_file(DIST_MANIFEST);
+        let installed_manifest_path = prefix.path().join(&rel_installed_manifest_path);
 
         // Begin transaction
         let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
 
         // If the previous installation was from a v1 manifest we need
         // to uninstall it first.
-        tx = self.maybe_handle_v2_upgrade(&config, tx)?;
+        tx = self.maybe_handle_v2_upgrade(config, tx)?;
 
         // Uninstall components
         for component in &update.components_to_uninstall {
@@ -223,7 +512,8 @@ impl Manifestation {
         }
 
         // Install components
-        for (component, format, installer_file) in things_to_install {
+        for thing in things_to_install {
+            let (component, package) = thing?;
             // For historical reasons, the rust-installer component
             // names are not the same as the dist manifest component
             // names. Some are just the component name some are the
@@ -232,30 +522,6 @@ impl Manifestation {
             let short_pkg_name = component.short_name_in_manifest();
             let short_name = component.short_name(new_manifest);
 
-            notify_handler(Notification::InstallingComponent(
-                &short_name,
-                &self.target_triple,
-                component.target.as_ref(),
-            ));
-
-            let notification_converter = |notification: crate::utils::Notification<'_>| {
-                notify_handler(notification.into());
-            };
-            let gz;
-            let xz;
-            let reader =
-                utils::FileReaderWithProgress::new_file(&installer_file, &notification_converter)?;
-            let package: &dyn Package = match format {
-                Format::Gz => {
-                    gz = TarGzPackage::new(reader, temp_cfg, Some(&notification_converter))?;
-                    &gz
-                }
-                Format::Xz => {
-                    xz = TarXzPackage::new(reader, temp_cfg, Some(&notification_converter))?;
-                    &xz
-                }
-            };
-
             // If the package doesn't contain the component that the
             // manifest says it does then somebody must be playing a joke on us.
             if !package.contains(&pkg_name, Some(&short_pkg_name)) {
@@ -277,6 +543,12 @@ impl Manifestation {
         // `Components` *also* tracks what is installed, but it only tracks names, not
         // name/target. Needs to be fixed in rust-installer.
         let mut new_config = Config::new();
+        new_config.profile = update.profile;
+        new_config.requested_components = update
+            .requested_components
+            .into_iter()
+            .filter(|c| update.final_component_list.contains(c))
+            .collect();
         new_config.components = update.final_component_list;
         let config_str = new_config.stringify();
         let rel_config_path = prefix.rel_manifest_file(CONFIG_FILE);
@@ -287,9 +559,104 @@ impl Manifestation {
         // End transaction
         tx.commit();
 
-        download_cfg.clean(&things_downloaded)?;
+        Ok(())
+    }
 
-        Ok(UpdateStatus::Changed)
+    // Unpacks the package for `component` into a temporary directory,
+    // ready to be installed.
+    fn unpack_component<'a>(
+        &self,
+        new_manifest: &Manifest,
+        component: &Component,
+        format: Format,
+        source: PackageSource,
+        download_cfg: &DownloadCfg<'a>,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Box<dyn Package + 'a>> {
+        notify_handler(Notification::InstallingComponent(
+            &component.short_name(new_manifest),
+            &self.target_triple,
+            component.target.as_ref(),
+        ));
+
+        let temp_cfg = download_cfg.temp_cfg;
+        let notification_converter = |notification: crate::utils::Notification<'_>| {
+            notify_handler(notification.into());
+        };
+        match source {
+            PackageSource::Downloaded(installer_file) => {
+                let reader = utils::FileReaderWithProgress::new_file(
+                    &installer_file,
+                    &notification_converter,
+                )?;
+                unpack(reader, &format, temp_cfg, &notification_converter)
+            }
+            PackageSource::Streamed(url, hash) => {
+                // Each attempt streams the package afresh, and what it
+                // unpacks is discarded unless the hash turns out right.
+                download_cfg
+                    .retry_policy
+                    .retry(
+                        None,
+                        || {
+                            let mut download = download_cfg.stream(&url, &hash)?;
+                            let unpacked =
+                                unpack(&mut download, &format, temp_cfg, &notification_converter);
+                            // A failed download explains a failed unpack
+                            download.finish().and(unpacked)
+                        },
+                        &|delay| {
+                            notify_handler(Notification::RetryingDownload(url.as_str(), delay))
+                        },
+                    )
+                    .chain_err(|| ErrorKind::ComponentDownloadFailed(component.name(new_manifest)))
+            }
+        }
+    }
+
+    /// Set up a staging copy of the installation beside it for an update
+    /// to be applied to. Unchanged files are hardlinked rather than copied,
+    /// except for the metadata in the manifest directory, which is
+    /// modified in place.
+    ///
+    /// Returns `None`, meaning the update should be applied in place, if
+    /// nothing is installed yet or the staging copy couldn't be made.
+    fn stage_update(
+        &self,
+        config: &Option<Config>,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Option<Self>> {
+        let prefix = self.installation.prefix();
+        if config.is_none() || !utils::is_directory(prefix.path()) {
+            return Ok(None);
+        }
+        let staging = match sibling_path(prefix.path(), "staging") {
+            Some(staging) => staging,
+            None => return Ok(None),
+        };
+        // Left behind by an update that was interrupted.
+        if utils::path_exists(&staging) {
+            utils::remove_dir("staging", &staging, notify_handler)?;
+        }
+
+        notify_handler(Notification::StagingUpdate(&staging));
+        let manifest_dir = prefix.manifest_dir();
+        let linked = utils::link_dir(prefix.path(), &staging, &|path: &Path| {
+            path.parent() == Some(manifest_dir.as_path())
+        });
+        if let Err(e) = linked {
+            notify_handler(Notification::NonFatalError(&e));
+            notify_handler(Notification::UpdatingInPlace(prefix.path()));
+            if utils::path_exists(&staging) {
+                utils::remove_dir("staging", &staging, notify_handler)?;
+            }
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            installation: Components::open(InstallPrefix::from(staging))?,
+            target_triple: self.target_triple.clone(),
+        }))
     }
 
     pub fn uninstall(
@@ -375,6 +742,11 @@ impl Manifestation {
         temp_cfg: &temp::Cfg,
         notify_handler: &dyn Fn(Notification<'_>),
         pgp_keys: &[PgpPublicKey],
+        retry_policy: RetryPolicy,
+        hosts: &download::HostConfigs,
+        throttle: Option<&download::Throttle>,
+        timeouts: download::Timeouts,
+        proxy: Option<&download::ProxyConfig>,
     ) -> Result<Option<String>> {
         // If there's already a v2 installation then something has gone wrong
         if self.read_config()?.is_some() {
@@ -405,7 +777,6 @@ impl Manifestation {
             Some(&self.target_triple),
         ));
 
-        use std::path::PathBuf;
         let dld_dir = PathBuf::from("bogus");
         let dlcfg = DownloadCfg {
             dist_root: "bogus",
@@ -413,6 +784,15 @@ impl Manifestation {
             temp_cfg,
             notify_handler,
             pgp_keys,
+            retry_policy,
+            // Only the one package is downloaded
+            concurrent_downloads: 1,
+            stream_packages: false,
+            hosts,
+            throttle,
+            timeouts,
+            proxy,
+            manifest_cache: None,
         };
 
         let dl = dlcfg.download_and_check(&url, update_hash, ".tar.gz")?;
@@ -481,12 +861,144 @@ impl Manifestation {
     }
 }
 
+// Keeps the installation an update has replaced, moved aside to `old`, at
+// `dest`.
+fn retain_installation(
+    old: &Path,
+    dest: &Path,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        utils::ensure_dir_exists("retained versions", parent, notify_handler)?;
+    }
+    utils::rename_dir("retained version", old, dest, notify_handler)
+}
+
+// The hidden path beside the installation at `prefix` used for `purpose`
+// while updating it.
+fn sibling_path(prefix: &Path, purpose: &str) -> Option<PathBuf> {
+    let name = prefix.file_name()?;
+    let mut sibling_name = OsString::from(".");
+    sibling_name.push(name);
+    sibling_name.push(".");
+    sibling_name.push(purpose);
+    Some(prefix.with_file_name(sibling_name))
+}
+
+// Puts the update staged at `staging` in place of the installation at
+// `prefix`, leaving the old version at `staging`. This is atomic where the
+// platform and filesystem support it, as on Linux. Elsewhere it takes
+// three renames, and an update interrupted between them is put right by
+// `recover_interrupted_update`.
+fn swap_in_staged(
+    staging: &Path,
+    prefix: &Path,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    let exchanged =
+        raw::exchange_paths(staging, prefix).chain_err(|| ErrorKind::RenamingDirectory {
+            name: "toolchain",
+            src: staging.to_owned(),
+            dest: prefix.to_owned(),
+        })?;
+    if exchanged {
+        return Ok(());
+    }
+
+    let replaced = sibling_path(prefix, "replaced").expect("staging implies a file name");
+    // Left behind by an update that was interrupted.
+    if utils::path_exists(&replaced) {
+        utils::remove_dir("toolchain", &replaced, notify_handler)?;
+    }
+    utils::rename_dir("toolchain", prefix, &replaced, notify_handler)?;
+    if let Err(e) = utils::rename_dir("staging", staging, prefix, notify_handler) {
+        // FIXME Ignoring cascading errors
+        let _ = utils::rename_dir("toolchain", &replaced, prefix, notify_handler);
+        return Err(e);
+    }
+    utils::rename_dir("toolchain", &replaced, staging, notify_handler)
+}
+
+/// Put right an update of the installation at `prefix` that was
+/// interrupted: if it was while swapping in the new version, the old
+/// version is put back if the new one isn't yet in place, and then any
+/// transaction left in progress is rolled back.
+///
+/// Like `recover_interrupted_transaction`, this must only be called by a
+/// process that has just taken the lock on the installation.
+pub fn recover_interrupted_update(
+    prefix: &InstallPrefix,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    if let Some(replaced) = sibling_path(prefix.path(), "replaced") {
+        if utils::path_exists(&replaced) {
+            if utils::path_exists(prefix.path()) {
+                utils::remove_dir("toolchain", &replaced, notify_handler)?;
+            } else {
+                utils::rename_dir("toolchain", &replaced, prefix.path(), notify_handler)?;
+            }
+        }
+    }
+    recover_interrupted_transaction(prefix, notify_handler)
+}
+
+// Copies the installation at `prefix` to `dest`. Components are replaced
+// rather than rewritten by updates, so their files can be shared with the
+// copy; the metadata beside the manifest is rewritten in place and has to
+// be copied.
+fn copy_installation(
+    prefix: &InstallPrefix,
+    dest: &Path,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        utils::ensure_dir_exists("retained versions", parent, notify_handler)?;
+    }
+    let manifest_dir = prefix.manifest_dir();
+    let linked = utils::link_dir(prefix.path(), dest, &|path: &Path| {
+        path.parent() == Some(manifest_dir.as_path())
+    });
+    if linked.is_err() {
+        // Most likely `dest` is on another filesystem
+        if utils::path_exists(dest) {
+            utils::remove_dir("retained version", dest, notify_handler)?;
+        }
+        utils::copy_dir(prefix.path(), dest, notify_handler)?;
+    }
+    Ok(())
+}
+
+/// Point a package url from the manifest at the dist server in use, if
+/// that isn't the default one.
+fn dist_server_url(url: String, temp_cfg: &temp::Cfg) -> String {
+    if temp_cfg.dist_server != DEFAULT_DIST_SERVER {
+        url.replace(DEFAULT_DIST_SERVER, temp_cfg.dist_server.as_str())
+    } else {
+        url
+    }
+}
+
+/// Open a package of the given format read from `reader`.
+fn unpack<'a, R: Read>(
+    reader: R,
+    format: &Format,
+    temp_cfg: &'a temp::Cfg,
+    notify_handler: &dyn Fn(crate::utils::Notification<'_>),
+) -> Result<Box<dyn Package + 'a>> {
+    Ok(match format {
+        Format::Gz => Box::new(TarGzPackage::new(reader, temp_cfg, Some(notify_handler))?),
+        Format::Xz => Box::new(TarXzPackage::new(reader, temp_cfg, Some(notify_handler))?),
+    })
+}
+
 #[derive(Debug)]
 struct Update {
     components_to_uninstall: Vec<Component>,
     components_to_install: Vec<Component>,
     final_component_list: Vec<Component>,
     missing_components: Vec<Component>,
+    profile: Option<String>,
+    requested_components: Vec<Component>,
 }
 
 impl Update {
@@ -524,8 +1036,30 @@ impl Update {
             components_to_install: vec![],
             final_component_list: vec![],
             missing_components: vec![],
+            profile: changes
+                .profile
+                .clone()
+                .or_else(|| config.as_ref().and_then(|c| c.profile.clone())),
+            requested_components: vec![],
         };
 
+        // Components the user asked for, following renames, less those
+        // they have since removed
+        let previously_requested = config
+            .iter()
+            .flat_map(|c| c.requested_components.iter())
+            .filter(|c| !changes.remove_components.contains(c))
+            .map(|c| {
+                new_manifest
+                    .rename_component(c)
+                    .unwrap_or_else(|| c.clone())
+            });
+        for component in previously_requested.chain(changes.requested_components.iter().cloned()) {
+            if !result.requested_components.contains(&component) {
+                result.requested_components.push(component);
+            }
+        }
+
         // Find the final list of components we want to be left with when
         // we're done: required components, added components, and existing
         // installed components.
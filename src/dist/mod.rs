@@ -10,8 +10,10 @@ pub mod config;
 pub mod dist;
 pub mod download;
 pub mod manifest;
+pub mod manifest_cache;
 pub mod manifestation;
 pub mod notifications;
 pub mod prefix;
+pub mod report;
 pub mod signatures;
 pub mod triple;
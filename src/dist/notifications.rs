@@ -6,6 +6,7 @@ use crate::errors::*;
 use crate::utils::notify::NotificationLevel;
 use std::fmt::{self, Display};
 use std::path::Path;
+use std::time::Duration;
 
 use super::manifest::Manifest;
 
@@ -27,6 +28,12 @@ pub enum Notification<'a> {
     NonFatalError(&'a Error),
     MissingInstalledComponent(&'a str),
     DownloadingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
+    ComponentDownloadStarted(&'a str),
+    ComponentDownloadContentLength(&'a str, u64),
+    ComponentDownloadDataReceived(&'a str, usize),
+    ComponentDownloadFinished(&'a str),
+    ComponentDownloadsFinished,
+    UpdateTimings(Duration, Duration, Duration),
     InstallingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
     RemovingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
     RemovingOldComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
@@ -39,7 +46,14 @@ pub enum Notification<'a> {
     ComponentUnavailable(&'a str, Option<&'a TargetTriple>),
     StrayHash(&'a Path),
     SignatureInvalid(&'a str),
-    RetryingDownload(&'a str),
+    RetryingDownload(&'a str, Duration),
+    ManifestUnchanged(&'a str),
+    RecoveringInterruptedTransaction(&'a Path),
+    StagingUpdate(&'a Path),
+    UpdatingInPlace(&'a Path),
+    ProfileComponentsMissing(&'a str, &'a str, &'a [String]),
+    ProfileComponentsGained(&'a str, &'a [String]),
+    ProfileComponentsLost(&'a str, &'a [String]),
 }
 
 impl<'a> From<crate::utils::Notification<'a>> for Notification<'a> {
@@ -64,6 +78,15 @@ impl<'a> Notification<'a> {
             | SignatureValid(_, _)
             | NoUpdateHash(_)
             | FileAlreadyDownloaded
+            | ComponentDownloadStarted(_)
+            | ComponentDownloadContentLength(_, _)
+            | ComponentDownloadDataReceived(_, _)
+            | ComponentDownloadFinished(_)
+            | ComponentDownloadsFinished
+            | UpdateTimings(_, _, _)
+            | StagingUpdate(_)
+            | UpdatingInPlace(_)
+            | ManifestUnchanged(_)
             | DownloadingLegacyManifest => NotificationLevel::Verbose,
             Extracting(_, _)
             | DownloadingComponent(_, _, _)
@@ -75,7 +98,9 @@ impl<'a> Notification<'a> {
             | RollingBack
             | DownloadingManifest(_)
             | SkippingNightlyMissingComponent(_, _, _)
-            | RetryingDownload(_)
+            | RetryingDownload(_, _)
+            | ProfileComponentsGained(_, _)
+            | ProfileComponentsLost(_, _)
             | DownloadedManifest(_, _) => NotificationLevel::Info,
             CantReadUpdateHash(_)
             | ExtensionNotInstalled(_)
@@ -83,7 +108,9 @@ impl<'a> Notification<'a> {
             | CachedFileChecksumFailed
             | ComponentUnavailable(_, _)
             | ForcingUnavailableComponent(_)
-            | StrayHash(_) => NotificationLevel::Warn,
+            | StrayHash(_)
+            | ProfileComponentsMissing(_, _, _)
+            | RecoveringInterruptedTransaction(_) => NotificationLevel::Warn,
             NonFatalError(_) => NotificationLevel::Error,
             SignatureInvalid(_) => NotificationLevel::Warn,
         }
@@ -127,6 +154,22 @@ impl<'a> Display for Notification<'a> {
                     write!(f, "downloading component '{}' for '{}'", c, t.unwrap())
                 }
             }
+            ComponentDownloadStarted(c) => write!(f, "started downloading '{}'", c),
+            ComponentDownloadContentLength(c, len) => {
+                write!(f, "download size of '{}' is: '{}'", c, len)
+            }
+            ComponentDownloadDataReceived(c, len) => {
+                write!(f, "received some data of size {} for '{}'", len, c)
+            }
+            ComponentDownloadFinished(c) => write!(f, "finished downloading '{}'", c),
+            ComponentDownloadsFinished => write!(f, "downloads finished"),
+            UpdateTimings(downloading, installing, total) => write!(
+                f,
+                "downloading took {:.2}s and installing {:.2}s, {:.2}s in all",
+                downloading.as_secs_f64(),
+                installing.as_secs_f64(),
+                total.as_secs_f64()
+            ),
             InstallingComponent(c, h, t) => {
                 if Some(h) == t.as_ref() || t.is_none() {
                     write!(f, "installing component '{}'", c)
@@ -171,6 +214,28 @@ impl<'a> Display for Notification<'a> {
                     write!(f, "component '{}' is not available", pkg)
                 }
             }
+            ProfileComponentsMissing(profile, toolchain, components) => write!(
+                f,
+                "profile '{}' includes component{} not available for '{}', skipping: '{}'",
+                profile,
+                if components.len() > 1 { "s" } else { "" },
+                toolchain,
+                components.join("', '")
+            ),
+            ProfileComponentsGained(profile, components) => write!(
+                f,
+                "profile '{}' now includes '{}', installing {}",
+                profile,
+                components.join("', '"),
+                if components.len() > 1 { "them" } else { "it" }
+            ),
+            ProfileComponentsLost(profile, components) => write!(
+                f,
+                "profile '{}' no longer includes '{}', removing {}",
+                profile,
+                components.join("', '"),
+                if components.len() > 1 { "them" } else { "it" }
+            ),
             StrayHash(path) => write!(
                 f,
                 "removing stray hash found at '{}' in order to continue",
@@ -196,7 +261,26 @@ impl<'a> Display for Notification<'a> {
                 write!(f, "Force-skipping unavailable component '{}'", component)
             }
             SignatureInvalid(url) => write!(f, "Signature verification failed for '{}'", url),
-            RetryingDownload(url) => write!(f, "retrying download for '{}'", url),
+            RetryingDownload(url, delay) => write!(
+                f,
+                "retrying download for '{}' in {:.1}s",
+                url,
+                delay.as_secs_f64()
+            ),
+            ManifestUnchanged(url) => {
+                write!(f, "'{}' is unchanged, using the cached copy", url)
+            }
+            StagingUpdate(path) => write!(f, "staging update in '{}'", path.display()),
+            UpdatingInPlace(path) => write!(
+                f,
+                "can't stage the update, updating '{}' in place",
+                path.display()
+            ),
+            RecoveringInterruptedTransaction(path) => write!(
+                f,
+                "rolling back interrupted changes to '{}'",
+                path.display()
+            ),
         }
     }
 }
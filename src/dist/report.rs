@@ -0,0 +1,149 @@
+//! Reports of how an update changed a toolchain's components.
+
+use serde::Serialize;
+
+use crate::dist::manifest::{Component, Manifest};
+
+/// The components of an installed toolchain, and the manifest they were
+/// installed from.
+#[derive(Clone, Debug)]
+pub struct InstalledComponents {
+    pub manifest: Manifest,
+    pub components: Vec<Component>,
+}
+
+/// How an update changed each of a toolchain's components.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct UpdateReport {
+    pub components: Vec<ComponentChange>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum ComponentChange {
+    Unchanged {
+        component: String,
+        version: Option<String>,
+    },
+    Updated {
+        component: String,
+        old_version: Option<String>,
+        new_version: Option<String>,
+    },
+    Renamed {
+        old_component: String,
+        new_component: String,
+        old_version: Option<String>,
+        new_version: Option<String>,
+    },
+    Added {
+        component: String,
+        version: Option<String>,
+    },
+    Removed {
+        component: String,
+        version: Option<String>,
+    },
+    /// Removed because the new manifest doesn't have it for this target
+    Dropped {
+        component: String,
+        version: Option<String>,
+    },
+}
+
+impl UpdateReport {
+    /// Compare the components installed before an update with those
+    /// installed after it.
+    pub fn new(old: &InstalledComponents, new: &InstalledComponents) -> Self {
+        let old_version = |c: &Component| version(&old.manifest, c);
+        let new_version = |c: &Component| version(&new.manifest, c);
+
+        let mut components = Vec::new();
+        let mut matched = Vec::new();
+        for component in &old.components {
+            let name = component.name(&old.manifest);
+            if let Some(same) = find(&new.components, component) {
+                matched.push(same);
+                let (old_version, new_version) = (old_version(component), new_version(same));
+                components.push(if old_version == new_version {
+                    ComponentChange::Unchanged {
+                        component: name,
+                        version: new_version,
+                    }
+                } else {
+                    ComponentChange::Updated {
+                        component: name,
+                        old_version,
+                        new_version,
+                    }
+                });
+            } else if let Some(renamed) = new
+                .manifest
+                .rename_component(component)
+                .and_then(|renamed| find(&new.components, &renamed))
+            {
+                matched.push(renamed);
+                components.push(ComponentChange::Renamed {
+                    old_component: name,
+                    new_component: renamed.name(&new.manifest),
+                    old_version: old_version(component),
+                    new_version: new_version(renamed),
+                });
+            } else if is_available(&new.manifest, component) {
+                components.push(ComponentChange::Removed {
+                    component: name,
+                    version: old_version(component),
+                });
+            } else {
+                components.push(ComponentChange::Dropped {
+                    component: name,
+                    version: old_version(component),
+                });
+            }
+        }
+        for component in &new.components {
+            if !matched.contains(&component) {
+                components.push(ComponentChange::Added {
+                    component: component.name(&new.manifest),
+                    version: new_version(component),
+                });
+            }
+        }
+
+        UpdateReport { components }
+    }
+
+    /// Whether any component was added, removed or changed version.
+    pub fn has_changes(&self) -> bool {
+        self.components.iter().any(|c| match c {
+            ComponentChange::Unchanged { .. } => false,
+            _ => true,
+        })
+    }
+}
+
+// The same component in `components`, allowing for it having become or
+// stopped being a wildcard component.
+fn find<'a>(components: &'a [Component], component: &Component) -> Option<&'a Component> {
+    components.iter().find(|c| *c == component).or_else(|| {
+        components.iter().find(|c| {
+            (c.target.is_none() || component.target.is_none())
+                && c.short_name_in_manifest() == component.short_name_in_manifest()
+        })
+    })
+}
+
+fn version(manifest: &Manifest, component: &Component) -> Option<String> {
+    manifest
+        .get_package(component.short_name_in_manifest())
+        .ok()
+        .map(|p| p.version.clone())
+}
+
+fn is_available(manifest: &Manifest, component: &Component) -> bool {
+    manifest
+        .get_package(component.short_name_in_manifest())
+        .and_then(|p| p.get_target(component.target.as_ref()))
+        .map(|p| p.available())
+        .unwrap_or(false)
+}
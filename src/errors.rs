@@ -63,6 +63,10 @@ error_chain! {
             description("could not create directory")
             display("could not create {} directory: '{}'", name, path.display())
         }
+        LockingFile(path: PathBuf) {
+            description("could not lock file")
+            display("could not lock '{}'", path.display())
+        }
         ExpectedType(t: &'static str, n: String) {
             description("expected type")
             display("expected type: '{}' for '{}'", t, n)
@@ -106,6 +110,18 @@ error_chain! {
             description("could not download file")
             display("could not download file from '{}' to '{}'", url, path.display())
         }
+        StreamingFile {
+            url: Url,
+        } {
+            description("could not download file")
+            display("could not download file from '{}'", url)
+        }
+        StreamingFileNotExists {
+            url: Url,
+        } {
+            description("could not download file")
+            display("could not download file from '{}'", url)
+        }
         InvalidUrl {
             url: String,
         } {
@@ -192,6 +208,34 @@ error_chain! {
             description("invalid profile name")
             display("invalid profile name: '{}'; valid names are: {}", t, valid_profile_names())
         }
+        InvalidDownloadRate(r: String) {
+            description("invalid download rate")
+            display("invalid download rate: '{}'; expected a rate such as '2MiB/s'", r)
+        }
+        InvalidRustupChannel(c: String) {
+            description("invalid rustup channel")
+            display("invalid rustup channel: '{}'; valid channels are: {}", c,
+                    crate::config::RUSTUP_CHANNELS.join(", "))
+        }
+        InvalidSelfUpdateMode(m: String) {
+            description("invalid auto-self-update mode")
+            display("invalid auto-self-update mode: '{}'; valid modes are: {}", m,
+                    crate::config::SelfUpdateMode::names().join(", "))
+        }
+        SelfUpdateModeLocked(m: String) {
+            description("auto-self-update is set by the system administrator")
+            display("auto-self-update is set to '{}' by the system administrator, and can't \
+                     be changed", m)
+        }
+        InvalidRustupVersion(v: String) {
+            description("invalid rustup version")
+            display("invalid rustup version: '{}'; expected a version such as '1.22.1'", v)
+        }
+        InvalidProxyUrl(u: String) {
+            description("invalid proxy URL")
+            display("invalid proxy URL: '{}'; expected an http, https, socks5 or socks5h URL \
+                     such as 'http://proxy.example.com:3128'", u)
+        }
         ChecksumFailed {
             url: String,
             expected: String,
@@ -239,6 +283,28 @@ error_chain! {
                     name,
                     path)
         }
+        CorruptTransactionJournal(path: PathBuf) {
+            description("corrupt transaction journal")
+            display("could not parse transaction journal: '{}'", path.display())
+        }
+        MissingRollbackBackup(path: PathBuf) {
+            description("missing backup for rollback")
+            display("could not restore '{}': its backup copy is missing", path.display())
+        }
+        NoRetainedVersions(t: String) {
+            description("no previous versions of toolchain retained")
+            display("no previous versions of '{}' are retained; set how many to keep with `rustup set retain-versions`", t)
+        }
+        RetainedVersionNotFound(t: String, date: String) {
+            description("toolchain version not retained")
+            display("version {} of '{}' is not retained", date, t)
+        }
+        ToolchainNeedsRepair(path: PathBuf) {
+            description("toolchain could not be recovered from an interrupted operation")
+            display("an interrupted install left the toolchain at '{}' in an inconsistent state \
+                     that could not be rolled back; uninstall and reinstall the toolchain to repair it",
+                    path.display())
+        }
         CorruptComponent(name: String) {
             description("corrupt component manifest")
             display("component manifest for '{}' is corrupt", name)
@@ -337,12 +403,13 @@ error_chain! {
                 "".to_string()
             })
         }
-        UnknownProfile(p: String) {
+        UnknownProfile(p: String, custom: Vec<String>) {
             description("unknown profile name")
             display(
-                "unknown profile name: '{}'; valid profile names are {}",
+                "unknown profile name: '{}'; valid profile names are {}{}",
                 p,
                 valid_profile_names(),
+                custom.iter().map(|s| format!(", '{}'", s)).collect::<String>(),
             )
         }
         AddingRequiredComponent(t: String, c: String) {
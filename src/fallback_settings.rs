@@ -1,6 +1,8 @@
+use crate::dist::dist::CustomProfile;
 use crate::errors::*;
 use crate::utils::utils;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::io;
 use std::path::Path;
@@ -8,12 +10,18 @@ use std::path::Path;
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct FallbackSettings {
     pub default_toolchain: Option<String>,
+    /// Locks whether rustup updates itself, so users can't change it
+    pub auto_self_update: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, CustomProfile>,
 }
 
 impl Default for FallbackSettings {
     fn default() -> Self {
         Self {
             default_toolchain: None,
+            auto_self_update: None,
+            profiles: BTreeMap::new(),
         }
     }
 }
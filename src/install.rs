@@ -1,9 +1,10 @@
 //! Installation and upgrade of both distribution-managed and local
 //! toolchains
 
-use crate::dist::dist;
+use crate::dist::dist::{self, DistUpdate};
 use crate::dist::download::DownloadCfg;
 use crate::dist::prefix::InstallPrefix;
+use crate::dist::report::{InstalledComponents, UpdateReport};
 use crate::dist::Notification;
 use crate::errors::Result;
 use crate::notifications::Notification as RootNotification;
@@ -18,15 +19,15 @@ pub enum InstallMethod<'a> {
     // bool is whether to force an update
     Dist {
         desc: &'a dist::ToolchainDesc,
-        profile: dist::Profile,
+        // Profile to install, or for an existing toolchain the profile it
+        // was installed with, if that's known
+        profile: Option<&'a dist::InstallProfile>,
         update_hash: Option<&'a Path>,
         dl_cfg: DownloadCfg<'a>,
         // --force
         force_update: bool,
         // --allow-downgrade
         allow_downgrade: bool,
-        // toolchain already exists
-        exists: bool,
         // currently installed date
         old_date: Option<&'a str>,
         // Extra components to install from dist
@@ -34,17 +35,33 @@ pub enum InstallMethod<'a> {
         // Extra targets to install from dist
         targets: &'a [&'a str],
         distributable: &'a DistributableToolchain<'a>,
+        // --dry-run
+        dry_run: bool,
+        // Where to keep the currently installed version once it's updated
+        retain_old: Option<&'a Path>,
     },
 }
 
 impl<'a> InstallMethod<'a> {
     // Install a toolchain
     pub fn install(&self, toolchain: &Toolchain<'a>) -> Result<UpdateStatus> {
+        if let InstallMethod::Dist { dry_run: true, .. } = self {
+            return Ok(match self.update_from_dist(&toolchain.path())? {
+                Some(DistUpdate::Planned(plan)) => UpdateStatus::Planned(plan),
+                _ => UpdateStatus::Unchanged,
+            });
+        }
+
         let previous_version = if toolchain.exists() {
             Some(toolchain.rustc_version())
         } else {
             None
         };
+        let previous_components = if previous_version.is_some() {
+            self.installed_components()
+        } else {
+            None
+        };
         if previous_version.is_some() {
             (toolchain.cfg().notify_handler)(RootNotification::UpdatingToolchain(
                 &toolchain.name(),
@@ -72,7 +89,13 @@ impl<'a> InstallMethod<'a> {
 
         let status = match (updated, previous_version) {
             (true, None) => UpdateStatus::Installed,
-            (true, Some(v)) => UpdateStatus::Updated(v),
+            (true, Some(v)) => {
+                let report = match (previous_components, self.installed_components()) {
+                    (Some(old), Some(new)) => UpdateReport::new(&old, &new),
+                    _ => UpdateReport::default(),
+                };
+                UpdateStatus::Updated(v, report)
+            }
             (false, _) => UpdateStatus::Unchanged,
         };
 
@@ -99,6 +122,33 @@ impl<'a> InstallMethod<'a> {
                 utils::symlink_dir(src, &path, notify_handler)?;
                 Ok(true)
             }
+            InstallMethod::Dist { update_hash, .. } => {
+                if let Some(DistUpdate::Installed(hash)) = self.update_from_dist(path)? {
+                    if let Some(hash_file) = update_hash {
+                        utils::write_file("update hash", hash_file, &hash)?;
+                    }
+
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+        }
+    }
+
+    // The components of a dist toolchain, if it can be read, so that what
+    // an update changed can be reported
+    fn installed_components(&self) -> Option<InstalledComponents> {
+        match self {
+            InstallMethod::Dist { distributable, .. } => {
+                distributable.installed_components().ok().and_then(|c| c)
+            }
+            _ => None,
+        }
+    }
+
+    fn update_from_dist(self, path: &Path) -> Result<Option<DistUpdate>> {
+        match self {
             InstallMethod::Dist {
                 desc,
                 profile,
@@ -106,36 +156,30 @@ impl<'a> InstallMethod<'a> {
                 dl_cfg,
                 force_update,
                 allow_downgrade,
-                exists,
                 old_date,
                 components,
                 targets,
+                dry_run,
+                retain_old,
                 ..
             } => {
                 let prefix = &InstallPrefix::from(path.to_owned());
-                let maybe_new_hash = dist::update_from_dist(
+                dist::update_from_dist(
                     dl_cfg,
                     update_hash,
                     desc,
-                    if exists { None } else { Some(profile) },
+                    profile,
                     prefix,
                     force_update,
                     allow_downgrade,
                     old_date,
                     components,
                     targets,
-                )?;
-
-                if let Some(hash) = maybe_new_hash {
-                    if let Some(hash_file) = update_hash {
-                        utils::write_file("update hash", hash_file, &hash)?;
-                    }
-
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
+                    dry_run,
+                    retain_old,
+                )
             }
+            _ => unreachable!("only dist installs are updated from dist"),
         }
     }
 }
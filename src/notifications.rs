@@ -1,8 +1,10 @@
 use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::errors::*;
 
+use crate::config::SelfUpdateMode;
 use crate::dist::temp;
 use crate::utils::notify::NotificationLevel;
 
@@ -33,6 +35,25 @@ pub enum Notification<'a> {
     UpgradeRemovesToolchains,
     MissingFileDuringSelfUninstall(PathBuf),
     PlainVerboseMessage(&'a str),
+    SetRetainVersions(usize),
+    SetMaxRetries(usize),
+    SetRetryDelay(Duration),
+    SetConcurrentDownloads(usize),
+    SetStreamPackages(bool),
+    SetMaxDownloadRate(Option<&'a str>),
+    SetConnectTimeout(Duration),
+    SetIdleTimeout(Duration),
+    SetMinDownloadRate(Option<&'a str>),
+    SetStallTimeout(Duration),
+    SetManifestCacheTtl(Duration),
+    SetRustupChannel(&'a str),
+    SetPinnedRustupVersion(Option<&'a str>),
+    SetRustupBackupRuns(usize),
+    SetSelfUpdateMode(SelfUpdateMode),
+    RetainingToolchainVersion(&'a str, &'a str),
+    RemovingRetainedVersion(&'a str, &'a str),
+    RollingBackToolchain(&'a str, &'a str),
+    InstalledProfileNotFound(&'a str, &'a str),
 }
 
 impl<'a> From<crate::dist::Notification<'a>> for Notification<'a> {
@@ -66,10 +87,28 @@ impl<'a> Notification<'a> {
             | ReadMetadataVersion(_)
             | InstalledToolchain(_)
             | PlainVerboseMessage(_)
+            | RetainingToolchainVersion(_, _)
+            | RemovingRetainedVersion(_, _)
             | UpdateHashMatches => NotificationLevel::Verbose,
             SetDefaultToolchain(_)
             | SetOverrideToolchain(_, _)
             | SetProfile(_)
+            | SetRetainVersions(_)
+            | SetMaxRetries(_)
+            | SetRetryDelay(_)
+            | SetConcurrentDownloads(_)
+            | SetStreamPackages(_)
+            | SetMaxDownloadRate(_)
+            | SetConnectTimeout(_)
+            | SetIdleTimeout(_)
+            | SetMinDownloadRate(_)
+            | SetStallTimeout(_)
+            | SetManifestCacheTtl(_)
+            | SetRustupChannel(_)
+            | SetPinnedRustupVersion(_)
+            | SetRustupBackupRuns(_)
+            | SetSelfUpdateMode(_)
+            | RollingBackToolchain(_, _)
             | UsingExistingToolchain(_)
             | UninstallingToolchain(_)
             | UninstalledToolchain(_)
@@ -77,7 +116,9 @@ impl<'a> Notification<'a> {
             | UpgradingMetadata(_, _)
             | MetadataUpgradeNotNeeded(_) => NotificationLevel::Info,
             NonFatalError(_) => NotificationLevel::Error,
-            UpgradeRemovesToolchains | MissingFileDuringSelfUninstall(_) => NotificationLevel::Warn,
+            UpgradeRemovesToolchains
+            | MissingFileDuringSelfUninstall(_)
+            | InstalledProfileNotFound(_, _) => NotificationLevel::Warn,
         }
     }
 }
@@ -130,6 +171,76 @@ impl<'a> Display for Notification<'a> {
                 p.display()
             ),
             PlainVerboseMessage(r) => write!(f, "{}", r),
+            InstalledProfileNotFound(name, profile) => write!(
+                f,
+                "'{}' was installed with profile '{}', which no longer exists; \
+                 its components won't follow changes to the profile",
+                name, profile
+            ),
+            SetRetainVersions(n) => write!(f, "retaining up to {} previous versions", n),
+            SetMaxDownloadRate(Some(rate)) => write!(f, "limiting downloads to {}", rate),
+            SetMaxDownloadRate(None) => write!(f, "not limiting how fast to download"),
+            SetConnectTimeout(timeout) => write!(
+                f,
+                "giving up on connecting to servers after {}s",
+                timeout.as_secs()
+            ),
+            SetIdleTimeout(timeout) => write!(
+                f,
+                "giving up on downloads that receive nothing for {}s",
+                timeout.as_secs()
+            ),
+            SetMinDownloadRate(Some(rate)) => {
+                write!(f, "giving up on downloads slower than {}", rate)
+            }
+            SetMinDownloadRate(None) => write!(f, "not giving up on slow downloads"),
+            SetStallTimeout(timeout) => write!(
+                f,
+                "giving up on downloads slower than the minimum rate for {}s",
+                timeout.as_secs()
+            ),
+            SetManifestCacheTtl(ttl) if ttl.as_secs() == 0 => write!(
+                f,
+                "asking whether cached channel manifests have changed every time"
+            ),
+            SetManifestCacheTtl(ttl) => write!(
+                f,
+                "using cached channel manifests for {}s before asking whether they've changed",
+                ttl.as_secs()
+            ),
+            SetRustupChannel(channel) => {
+                write!(f, "rustup will update itself from the {} channel", channel)
+            }
+            SetPinnedRustupVersion(Some(version)) => {
+                write!(f, "rustup will update itself only to version {}", version)
+            }
+            SetPinnedRustupVersion(None) => {
+                write!(f, "rustup will update itself to the latest version")
+            }
+            SetRustupBackupRuns(n) => write!(
+                f,
+                "keeping the previous rustup for {} runs after a self-update",
+                n
+            ),
+            SetSelfUpdateMode(mode) => write!(f, "auto-self-update set to '{}'", mode),
+            SetMaxRetries(n) => write!(f, "retrying failed downloads up to {} times", n),
+            SetRetryDelay(delay) => write!(
+                f,
+                "waiting about {}ms before first retrying a failed download",
+                delay.as_millis()
+            ),
+            SetConcurrentDownloads(n) => write!(f, "downloading up to {} packages at once", n),
+            SetStreamPackages(true) => write!(f, "unpacking packages as they're downloaded"),
+            SetStreamPackages(false) => write!(f, "saving packages before unpacking them"),
+            RetainingToolchainVersion(name, date) => {
+                write!(f, "retaining version {} of '{}'", date, name)
+            }
+            RemovingRetainedVersion(name, date) => {
+                write!(f, "removing retained version {} of '{}'", date, name)
+            }
+            RollingBackToolchain(name, date) => {
+                write!(f, "rolling back '{}' to version {}", name, date)
+            }
         }
     }
 }
@@ -12,19 +12,22 @@ use wait_timeout::ChildExt;
 
 use crate::component_for_bin;
 use crate::config::Cfg;
-use crate::dist::dist::Profile;
+use crate::dist::dist::InstallProfile;
 use crate::dist::dist::TargetTriple;
 use crate::dist::dist::ToolchainDesc;
 use crate::dist::download::DownloadCfg;
 use crate::dist::manifest::Component;
 use crate::dist::manifest::Manifest;
-use crate::dist::manifestation::{Changes, Manifestation};
+use crate::dist::manifestation::{recover_interrupted_update, Changes, Manifestation, UpdatePlan};
 use crate::dist::prefix::InstallPrefix;
+use crate::dist::report::{InstalledComponents, UpdateReport};
 use crate::env_var;
 use crate::errors::*;
 use crate::install::{self, InstallMethod};
 use crate::notifications::*;
 use crate::process;
+use crate::utils::lock::FileLock;
+use crate::utils::raw;
 use crate::utils::utils;
 
 /// An installed toolchain
@@ -58,8 +61,11 @@ pub struct ComponentStatus {
 #[derive(Clone, Debug)]
 pub enum UpdateStatus {
     Installed,
-    Updated(String), // Stores the version of rustc *before* the update
+    // Stores the version of rustc *before* the update, and how the
+    // update changed the toolchain's components
+    Updated(String, UpdateReport),
     Unchanged,
+    Planned(UpdatePlan), // The changes a dry run would have made
 }
 
 impl<'a> Toolchain<'a> {
@@ -126,8 +132,31 @@ impl<'a> Toolchain<'a> {
     pub fn verify(&self) -> Result<()> {
         utils::assert_is_directory(&self.path)
     }
+    /// Take this toolchain's lock, which is held by whichever rustup
+    /// process is installing, updating or removing it.
+    pub fn lock(&self) -> Result<FileLock> {
+        let path = self
+            .cfg
+            .rustup_dir
+            .join("locks")
+            .join(format!("{}.lock", self.name));
+        FileLock::acquire(&path, self.cfg.notify_handler.as_ref())
+    }
+    /// Take this toolchain's lock in order to change it. A transaction
+    /// against the toolchain is only in progress while its lock is held, so
+    /// any found on first taking the lock was interrupted, and is rolled
+    /// back before anything else can see it.
+    pub(crate) fn lock_for_changes(&self) -> Result<FileLock> {
+        let lock = self.lock()?;
+        if !lock.reentered() {
+            let prefix = InstallPrefix::from(self.path.to_owned());
+            recover_interrupted_update(&prefix, &|n| (self.cfg.notify_handler)(n.into()))?;
+        }
+        Ok(lock)
+    }
     // Custom and Distributable. Installed only.
     pub fn remove(&self) -> Result<()> {
+        let _lock = self.lock()?;
         if self.exists() || self.is_symlink() {
             (self.cfg.notify_handler)(Notification::UninstallingToolchain(&self.name));
         } else {
@@ -143,11 +172,24 @@ impl<'a> Toolchain<'a> {
                 }
             }
         }
+        let retained_dir = self.retained_dir();
+        if utils::path_exists(&retained_dir) {
+            utils::remove_dir(
+                "retained versions",
+                &retained_dir,
+                self.cfg.notify_handler.as_ref(),
+            )?;
+        }
         if !self.exists() {
             (self.cfg.notify_handler)(Notification::UninstalledToolchain(&self.name));
         }
         Ok(())
     }
+    /// Where previous versions of this toolchain are kept, one directory
+    /// per manifest date.
+    fn retained_dir(&self) -> PathBuf {
+        self.cfg.retained_dir.join(&self.name)
+    }
 
     // Custom only
     pub fn is_custom(&self) -> bool {
@@ -457,7 +499,12 @@ impl<'a> DistributableToolchain<'a> {
     }
 
     // Installed only.
-    pub fn add_component(&self, mut component: Component) -> Result<()> {
+    pub fn add_component(
+        &self,
+        mut component: Component,
+        dry_run: bool,
+    ) -> Result<Option<UpdatePlan>> {
+        let _lock = self.0.lock_for_changes()?;
         if !self.0.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.0.name.to_owned()).into());
         }
@@ -500,26 +547,51 @@ impl<'a> DistributableToolchain<'a> {
             }
 
             let changes = Changes {
-                explicit_add_components: vec![component],
-                remove_components: vec![],
+                explicit_add_components: vec![component.clone()],
+                requested_components: vec![component],
+                ..Changes::none()
             };
 
-            manifestation.update(
-                &manifest,
-                changes,
-                false,
-                &self.download_cfg(),
-                &self.download_cfg().notify_handler,
-                &toolchain.manifest_name(),
-                false,
-            )?;
-
-            Ok(())
+            self.update_components(&manifestation, &manifest, changes, dry_run)
         } else {
             Err(ErrorKind::ComponentsUnsupported(self.0.name.to_string()).into())
         }
     }
 
+    /// Apply changes to the installed components, or with `dry_run` just
+    /// work out what they would be.
+    fn update_components(
+        &self,
+        manifestation: &Manifestation,
+        manifest: &Manifest,
+        changes: Changes,
+        dry_run: bool,
+    ) -> Result<Option<UpdatePlan>> {
+        let toolchain = self.desc()?.manifest_name();
+        let download_cfg = self.download_cfg()?;
+        if dry_run {
+            return manifestation.plan_update(
+                manifest,
+                changes,
+                false,
+                &download_cfg,
+                &download_cfg.notify_handler,
+                &toolchain,
+            );
+        }
+        manifestation.update(
+            manifest,
+            changes,
+            false,
+            &download_cfg,
+            &download_cfg.notify_handler,
+            &toolchain,
+            false,
+            None,
+        )?;
+        Ok(None)
+    }
+
     // Create a command as a fallback for another toolchain. This is used
     // to give custom toolchains access to cargo
     // Installed only.
@@ -576,7 +648,7 @@ impl<'a> DistributableToolchain<'a> {
         Ok(ToolchainDesc::from_str(&self.0.name)?)
     }
 
-    fn download_cfg(&self) -> DownloadCfg<'_> {
+    fn download_cfg(&self) -> Result<DownloadCfg<'_>> {
         self.0.cfg.download_cfg(&*self.0.dist_handler)
     }
 
@@ -679,6 +751,57 @@ impl<'a> DistributableToolchain<'a> {
         manifestation.load_manifest()
     }
 
+    // Installed only.
+    pub fn installed_components(&self) -> Result<Option<InstalledComponents>> {
+        let toolchain = ToolchainDesc::from_str(&self.0.name())?;
+        let prefix = InstallPrefix::from(self.0.path().to_owned());
+        let manifestation = Manifestation::open(prefix, toolchain.target)?;
+
+        let manifest = match manifestation.load_manifest()? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        let components = match manifestation.read_config()? {
+            Some(config) => config.components,
+            None => return Ok(None),
+        };
+        Ok(Some(InstalledComponents {
+            manifest,
+            components,
+        }))
+    }
+
+    // Installed only.
+    pub fn installed_profile_name(&self) -> Result<Option<String>> {
+        let toolchain = ToolchainDesc::from_str(&self.0.name())?;
+        let prefix = InstallPrefix::from(self.0.path().to_owned());
+        let manifestation = Manifestation::open(prefix, toolchain.target)?;
+
+        Ok(manifestation
+            .read_config()?
+            .and_then(|config| config.profile))
+    }
+
+    /// The profile the toolchain was installed with, so that updates can
+    /// follow changes to it. `None` if that isn't recorded, or if it was a
+    /// custom profile that has since been removed.
+    fn installed_profile(&self) -> Result<Option<InstallProfile>> {
+        let name = match self.installed_profile_name()? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match self.0.cfg.resolve_profile(&name) {
+            Ok(profile) => Ok(Some(profile)),
+            Err(_) => {
+                (self.0.cfg.notify_handler)(Notification::InstalledProfileNotFound(
+                    &self.0.name,
+                    &name,
+                ));
+                Ok(None)
+            }
+        }
+    }
+
     // Not installed only?
     pub fn install_from_dist(
         &self,
@@ -686,45 +809,221 @@ impl<'a> DistributableToolchain<'a> {
         allow_downgrade: bool,
         components: &[&str],
         targets: &[&str],
-        profile: Option<Profile>,
+        profile: Option<InstallProfile>,
+        dry_run: bool,
     ) -> Result<UpdateStatus> {
+        let _lock = self.0.lock_for_changes()?;
         let update_hash = self.update_hash()?;
         let old_date = self.get_manifest().ok().and_then(|m| m.map(|m| m.date));
-        InstallMethod::Dist {
+        let retain_old = if dry_run {
+            None
+        } else {
+            self.retained_version_path(old_date.as_deref())?
+        };
+        // An existing toolchain keeps the profile it was installed with
+        let profile = if self.0.exists() {
+            self.installed_profile()?
+        } else {
+            match profile {
+                Some(profile) => Some(profile),
+                None => Some(self.0.cfg.get_profile()?),
+            }
+        };
+        let status = InstallMethod::Dist {
             desc: &self.desc()?,
-            profile: profile
-                .map(Ok)
-                .unwrap_or_else(|| self.0.cfg.get_profile())?,
+            profile: profile.as_ref(),
             update_hash: Some(&update_hash),
-            dl_cfg: self.download_cfg(),
+            dl_cfg: self.download_cfg()?,
             force_update,
             allow_downgrade,
-            exists: self.0.exists(),
             old_date: old_date.as_deref(),
             components,
             targets,
             distributable: &self,
+            dry_run,
+            retain_old: retain_old.as_deref(),
+        }
+        .install(&self.0);
+
+        if let Ok(UpdateStatus::Updated(..)) = status {
+            if let (Some(date), Some(dest)) = (&old_date, &retain_old) {
+                if utils::path_exists(dest) {
+                    (self.0.cfg.notify_handler)(Notification::RetainingToolchainVersion(
+                        self.0.name(),
+                        date,
+                    ));
+                }
+            }
+            self.prune_retained_versions()?;
+        }
+        status
+    }
+
+    /// Where to keep the installed version of a tracking toolchain once an
+    /// update has replaced it, if the user has asked for previous versions
+    /// to be retained and this one isn't already.
+    fn retained_version_path(&self, date: Option<&str>) -> Result<Option<PathBuf>> {
+        let date = match date {
+            Some(date) if self.0.is_tracking() && self.0.exists() => date,
+            _ => return Ok(None),
+        };
+        if self.0.cfg.get_retain_versions()? == 0 {
+            return Ok(None);
+        }
+        let dest = self.0.retained_dir().join(date);
+        if utils::path_exists(&dest) {
+            return Ok(None);
+        }
+        Ok(Some(dest))
+    }
+
+    /// Previous versions of this toolchain which can be rolled back to,
+    /// newest first.
+    pub fn retained_versions(&self) -> Result<Vec<String>> {
+        let retained_dir = self.0.retained_dir();
+        if !utils::is_directory(&retained_dir) {
+            return Ok(Vec::new());
+        }
+        let mut versions: Vec<_> = utils::read_dir("retained versions", &retained_dir)?
+            .filter_map(std::io::Result::ok)
+            .filter(|e| e.file_type().map(|f| f.is_dir()).unwrap_or(false))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        // Manifest dates are YYYY-MM-DD, so they sort as strings
+        versions.sort_by(|a, b| b.cmp(a));
+        Ok(versions)
+    }
+
+    fn prune_retained_versions(&self) -> Result<()> {
+        let keep = self.0.cfg.get_retain_versions()?;
+        let retained_dir = self.0.retained_dir();
+        // A copy of the installed version is no use for rolling back to
+        let current = self.get_manifest().ok().and_then(|m| m.map(|m| m.date));
+        let (same, older): (Vec<_>, Vec<_>) = self
+            .retained_versions()?
+            .into_iter()
+            .partition(|date| Some(date) == current.as_ref());
+        for date in same.iter().chain(older.iter().skip(keep)) {
+            (self.0.cfg.notify_handler)(Notification::RemovingRetainedVersion(self.0.name(), date));
+            utils::remove_dir(
+                "retained version",
+                &retained_dir.join(date),
+                self.0.cfg.notify_handler.as_ref(),
+            )?;
+        }
+        if keep == 0 && utils::is_directory(&retained_dir) {
+            utils::remove_dir(
+                "retained versions",
+                &retained_dir,
+                self.0.cfg.notify_handler.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Swap the installed toolchain for a retained previous version, the
+    /// newest unless `to` names the date of another. The version being
+    /// replaced is retained in its place. Returns the date rolled back to.
+    pub fn rollback(&self, to: Option<&str>) -> Result<String> {
+        let _lock = self.0.lock_for_changes()?;
+        let name = self.0.name();
+        let versions = self.retained_versions()?;
+        let date = match to {
+            Some(to) => versions.into_iter().find(|v| v == to).ok_or_else(|| {
+                ErrorKind::RetainedVersionNotFound(name.to_owned(), to.to_owned())
+            })?,
+            None => versions
+                .into_iter()
+                .next()
+                .ok_or_else(|| ErrorKind::NoRetainedVersions(name.to_owned()))?,
+        };
+
+        let notify_handler = self.0.cfg.notify_handler.as_ref();
+        notify_handler(Notification::RollingBackToolchain(name, &date));
+        let retained_dir = self.0.retained_dir();
+        let retained = retained_dir.join(&date);
+        if self.0.exists() {
+            let current = self.get_manifest().ok().and_then(|m| m.map(|m| m.date));
+            let replaced = self.swap_in_retained(&retained)?;
+            // The version replaced is retained in its place, unless it
+            // already is or its date can't be told. It's been replaced
+            // either way, so failing to keep or remove it is only reported.
+            let kept = match current {
+                Some(ref current) if !utils::path_exists(retained_dir.join(current)) => {
+                    notify_handler(Notification::RetainingToolchainVersion(name, current));
+                    utils::rename_dir(
+                        "toolchain",
+                        &replaced,
+                        &retained_dir.join(current),
+                        notify_handler,
+                    )
+                }
+                _ => utils::remove_dir("toolchain", &replaced, notify_handler),
+            };
+            if let Err(e) = kept {
+                notify_handler(Notification::NonFatalError(&e));
+                if utils::path_exists(&replaced) {
+                    // FIXME Ignoring cascading errors
+                    let _ = utils::remove_dir("toolchain", &replaced, notify_handler);
+                }
+            }
+        } else {
+            utils::rename_dir("retained version", &retained, self.0.path(), notify_handler)?;
+        }
+
+        // The next update has to fetch the channel even if it hasn't moved
+        utils::ensure_file_removed("update hash", &self.update_hash()?)?;
+        self.prune_retained_versions()?;
+        Ok(date)
+    }
+
+    // Puts the retained version at `retained` in place of the installed
+    // toolchain, atomically where the filesystem allows, and returns where
+    // the replaced toolchain ended up. Should that fail, the installed
+    // toolchain is left as it was.
+    fn swap_in_retained(&self, retained: &Path) -> Result<PathBuf> {
+        let path = self.0.path();
+        if let Ok(true) = raw::exchange_paths(retained, path) {
+            return Ok(retained.to_owned());
+        }
+
+        let notify_handler = self.0.cfg.notify_handler.as_ref();
+        let mut aside_name = OsString::from(".");
+        aside_name.push(&self.0.name);
+        aside_name.push(".rollback");
+        let aside = path.with_file_name(aside_name);
+        // Left behind by a rollback that was interrupted.
+        if utils::path_exists(&aside) {
+            utils::remove_dir("toolchain", &aside, notify_handler)?;
         }
-        .install(&self.0)
+        utils::rename_dir("toolchain", path, &aside, notify_handler)?;
+        if let Err(e) = utils::rename_dir("retained version", retained, path, notify_handler) {
+            // FIXME Ignoring cascading errors
+            let _ = utils::rename_dir("toolchain", &aside, path, notify_handler);
+            return Err(e);
+        }
+        Ok(aside)
     }
 
     // Installed or not installed.
     pub fn install_from_dist_if_not_installed(&self) -> Result<UpdateStatus> {
+        let _lock = self.0.lock_for_changes()?;
         let update_hash = self.update_hash()?;
         (self.0.cfg.notify_handler)(Notification::LookingForToolchain(&self.0.name));
         if !self.0.exists() {
             Ok(InstallMethod::Dist {
                 desc: &self.desc()?,
-                profile: self.0.cfg.get_profile()?,
+                profile: Some(&self.0.cfg.get_profile()?),
                 update_hash: Some(&update_hash),
-                dl_cfg: self.download_cfg(),
+                dl_cfg: self.download_cfg()?,
                 force_update: false,
                 allow_downgrade: false,
-                exists: false,
                 old_date: None,
                 components: &[],
                 targets: &[],
                 distributable: &self,
+                dry_run: false,
+                retain_old: None,
             }
             .install(&self.0)?)
         } else {
@@ -801,7 +1100,12 @@ impl<'a> DistributableToolchain<'a> {
     }
 
     // Installed only.
-    pub fn remove_component(&self, mut component: Component) -> Result<()> {
+    pub fn remove_component(
+        &self,
+        mut component: Component,
+        dry_run: bool,
+    ) -> Result<Option<UpdatePlan>> {
+        let _lock = self.0.lock_for_changes()?;
         // Overlapping code with get_manifest :/.
         if !self.0.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.0.name.to_owned()).into());
@@ -836,21 +1140,11 @@ impl<'a> DistributableToolchain<'a> {
             }
 
             let changes = Changes {
-                explicit_add_components: vec![],
                 remove_components: vec![component],
+                ..Changes::none()
             };
 
-            manifestation.update(
-                &manifest,
-                changes,
-                false,
-                &self.download_cfg(),
-                &self.download_cfg().notify_handler,
-                &toolchain.manifest_name(),
-                false,
-            )?;
-
-            Ok(())
+            self.update_components(&manifestation, &manifest, changes, dry_run)
         } else {
             Err(ErrorKind::ComponentsUnsupported(self.0.name.to_string()).into())
         }
@@ -861,7 +1155,7 @@ impl<'a> DistributableToolchain<'a> {
         let update_hash = self.update_hash()?;
 
         match crate::dist::dist::dl_v2_manifest(
-            self.download_cfg(),
+            self.download_cfg()?,
             Some(&update_hash),
             &self.desc()?,
         )? {
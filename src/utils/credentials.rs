@@ -0,0 +1,272 @@
+//! Credentials for dist servers that need them, from a netrc file or a
+//! credential helper.
+//!
+//! The credentials only ever go into the `Authorization` header of
+//! requests to the host they're for, never into URLs, notifications or
+//! errors.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use download::CredentialProvider;
+
+/// Credentials from a host's `machine` entry in a netrc file. The file is
+/// read again each time the credentials are fetched, so that it may be
+/// updated while rustup runs.
+pub struct Netrc {
+    path: PathBuf,
+    host: String,
+}
+
+impl Netrc {
+    pub fn new(path: &Path, host: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            host: host.to_owned(),
+        }
+    }
+}
+
+impl CredentialProvider for Netrc {
+    fn authorization(&self) -> download::Result<Option<String>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(download::Error::with_chain(
+                    e,
+                    format!("could not read '{}'", self.path.display()),
+                ))
+            }
+        };
+        Ok(netrc_login(&contents, &self.host).map(|(login, password)| basic(&login, &password)))
+    }
+}
+
+/// The hosts a netrc file has a `machine` entry for.
+pub fn netrc_machines(contents: &str) -> Vec<String> {
+    let tokens = netrc_tokens(contents);
+    tokens
+        .windows(2)
+        .filter(|pair| pair[0] == "machine")
+        .map(|pair| pair[1].to_owned())
+        .collect()
+}
+
+// The login and password for `host` in netrc `contents`, if it has them.
+// A `default` entry is ignored, so that credentials are only ever sent to
+// the hosts they're for.
+fn netrc_login(contents: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = netrc_tokens(contents).into_iter();
+    let mut in_host = false;
+    let (mut login, mut password) = (None, None);
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" if in_host => break,
+            "machine" => {
+                in_host = tokens
+                    .next()
+                    .map_or(false, |machine| machine.eq_ignore_ascii_case(host))
+            }
+            "default" => {}
+            "login" | "password" | "account" => {
+                let value = tokens.next();
+                if in_host {
+                    match token {
+                        "login" => login = value,
+                        "password" => password = value,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some((login?.to_owned(), password?.to_owned()))
+}
+
+// The words of a netrc file, leaving out comments and macro definitions.
+fn netrc_tokens(contents: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for word in line.split_whitespace() {
+            if word == "macdef" {
+                // A macro runs to the next empty line
+                lines
+                    .by_ref()
+                    .take_while(|line| !line.trim().is_empty())
+                    .for_each(drop);
+                break;
+            }
+            tokens.push(word);
+        }
+    }
+    tokens
+}
+
+/// Credentials from a helper program, such as one that hands out
+/// short-lived tokens, run again each time they're fetched.
+///
+/// Like a git credential helper, it's run with the argument `get`, and
+/// told the host on its standard input as `protocol=https` and
+/// `host=<host>` lines. It answers on its standard output with either
+/// `username=` and `password=` lines, or a `token=` line for a bearer
+/// token, or nothing if it has no credentials for the host.
+pub struct CredentialHelper {
+    command: Vec<String>,
+    host: String,
+}
+
+impl CredentialHelper {
+    /// `command` is the helper followed by any arguments to run it with.
+    pub fn new(command: Vec<String>, host: &str) -> Self {
+        Self {
+            command,
+            host: host.to_owned(),
+        }
+    }
+
+    fn run(&self) -> io::Result<std::process::Output> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            write!(stdin, "protocol=https\nhost={}\n\n", self.host)?;
+        }
+        child.wait_with_output()
+    }
+}
+
+impl CredentialProvider for CredentialHelper {
+    fn authorization(&self) -> download::Result<Option<String>> {
+        // Only the command is named in errors, as the output may be the
+        // credentials
+        let helper = self.command.join(" ");
+        let output = self.run().map_err(|e| {
+            download::Error::with_chain(e, format!("could not run credential helper '{}'", helper))
+        })?;
+        if !output.status.success() {
+            return Err(format!(
+                "credential helper '{}' failed for '{}': {}",
+                helper, self.host, output.status
+            )
+            .into());
+        }
+        let output = String::from_utf8(output.stdout).map_err(|_| {
+            download::Error::from(format!(
+                "credential helper '{}' answered with invalid UTF-8",
+                helper
+            ))
+        })?;
+        Ok(helper_authorization(&output))
+    }
+}
+
+// The `Authorization` header value for a credential helper's answer.
+fn helper_authorization(output: &str) -> Option<String> {
+    let (mut username, mut password, mut token) = (None, None, None);
+    for line in output.lines() {
+        let mut pair = line.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("username"), Some(value)) => username = Some(value),
+            (Some("password"), Some(value)) => password = Some(value),
+            (Some("token"), Some(value)) => token = Some(value),
+            _ => {}
+        }
+    }
+    match (token, username, password) {
+        (Some(token), _, _) => Some(format!("Bearer {}", token)),
+        (None, Some(username), Some(password)) => Some(basic(username, password)),
+        _ => None,
+    }
+}
+
+fn basic(login: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::encode(format!("{}:{}", login, password))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "\
+# Mirrors
+machine mirror.example.com
+  login ci
+  password hunter2
+
+macdef init
+machine evil.example.com login x password y
+
+default login anonymous password guest
+machine other.example.com login other password secret
+";
+
+    #[test]
+    fn netrc_entries_are_found_by_host() {
+        assert_eq!(
+            netrc_login(NETRC, "Mirror.Example.com"),
+            Some(("ci".to_owned(), "hunter2".to_owned()))
+        );
+        assert_eq!(
+            netrc_login(NETRC, "other.example.com"),
+            Some(("other".to_owned(), "secret".to_owned()))
+        );
+        // Neither the default entry nor anything in a macro is used
+        assert_eq!(netrc_login(NETRC, "static.rust-lang.org"), None);
+        assert_eq!(netrc_login(NETRC, "evil.example.com"), None);
+        assert_eq!(
+            netrc_machines(NETRC),
+            vec!["mirror.example.com", "other.example.com"]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn helper_may_have_spaces_in_its_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join("mirror credentials");
+        fs::write(&helper, "#!/bin/sh\necho \"token=$1-$2\"\n").unwrap();
+        fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let command = vec![helper.to_str().unwrap().to_owned(), "ci".to_owned()];
+        let helper = CredentialHelper::new(command, "mirror.example.com");
+        assert_eq!(
+            helper.authorization().unwrap(),
+            Some("Bearer ci-get".to_owned())
+        );
+    }
+
+    #[test]
+    fn helper_answers_become_authorization() {
+        assert_eq!(
+            helper_authorization("username=ci\npassword=hunter2\n"),
+            Some("Basic Y2k6aHVudGVyMg==".to_owned())
+        );
+        assert_eq!(
+            helper_authorization("token=abc=def\n"),
+            Some("Bearer abc=def".to_owned())
+        );
+        assert_eq!(helper_authorization("username=ci\n"), None);
+        assert_eq!(helper_authorization(""), None);
+    }
+}
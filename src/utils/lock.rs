@@ -0,0 +1,332 @@
+//! Advisory locks shared between rustup processes.
+//!
+//! These keep concurrent rustup invocations, such as two proxies starting
+//! at once in a fresh checkout, from modifying the same toolchain or the
+//! download cache at the same time. A process that finds a lock held
+//! reports the holder's pid and waits for it to be released.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use crate::errors::*;
+use crate::utils::notifications::Notification;
+use crate::utils::utils;
+
+lazy_static! {
+    // The locks held by this process, how many `FileLock`s refer to each,
+    // and whether to remove its file once released. Locks are re-entrant
+    // so that e.g. an install which takes a toolchain's lock can be called
+    // by something already holding it.
+    static ref HELD: Mutex<HashMap<PathBuf, (File, usize, bool)>> = Mutex::new(HashMap::new());
+}
+
+/// An exclusive lock on a file, released once the last `FileLock` for the
+/// path is dropped or the process exits.
+#[derive(Debug)]
+#[must_use]
+pub struct FileLock {
+    path: PathBuf,
+    reentered: bool,
+}
+
+impl FileLock {
+    /// Lock `path`, creating it if needed, and waiting for any other
+    /// process that holds the lock.
+    pub fn acquire<'a, N>(path: &'a Path, notify_handler: &'a dyn Fn(N)) -> Result<Self>
+    where
+        N: From<Notification<'a>>,
+    {
+        Self::acquire_(path, false, notify_handler)
+    }
+
+    /// Lock `path` as `acquire` does, but remove the file again once the
+    /// lock is released, for locks on things that come and go, such as the
+    /// packages in the download cache.
+    pub fn acquire_transient<'a, N>(path: &'a Path, notify_handler: &'a dyn Fn(N)) -> Result<Self>
+    where
+        N: From<Notification<'a>>,
+    {
+        Self::acquire_(path, true, notify_handler)
+    }
+
+    fn acquire_<'a, N>(
+        path: &'a Path,
+        transient: bool,
+        notify_handler: &'a dyn Fn(N),
+    ) -> Result<Self>
+    where
+        N: From<Notification<'a>>,
+    {
+        if let Some((_, count, _)) = HELD.lock().unwrap().get_mut(path) {
+            *count += 1;
+            return Ok(FileLock {
+                path: path.to_owned(),
+                reentered: true,
+            });
+        }
+
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir_exists("lock", parent, notify_handler)?;
+        }
+        let locking = || ErrorKind::LockingFile(path.to_owned());
+        let mut file = loop {
+            let mut file = sys::open(path).chain_err(locking)?;
+            if !sys::try_lock(&file).chain_err(locking)? {
+                let holder = read_holder(&mut file);
+                notify_handler(Notification::WaitingForFileLock(path, holder).into());
+                sys::lock(&file).chain_err(locking)?;
+            }
+            // The holder we waited for may have removed the file, leaving
+            // us with a lock that the next process along won't see.
+            if sys::is_current(&file, path).chain_err(locking)? {
+                break file;
+            }
+        };
+
+        // Record ourselves as the holder for the benefit of anyone who
+        // has to wait. Failing to do so only makes that message vaguer.
+        let _ = write_holder(&mut file);
+
+        HELD.lock()
+            .unwrap()
+            .insert(path.to_owned(), (file, 1, transient));
+        Ok(FileLock {
+            path: path.to_owned(),
+            reentered: false,
+        })
+    }
+
+    /// Whether this process already held the lock when this was taken.
+    pub fn reentered(&self) -> bool {
+        self.reentered
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap();
+        let released = match held.get_mut(&self.path) {
+            Some((_, count, _)) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if released {
+            // Closing the file releases the lock.
+            if let Some((file, _, transient)) = held.remove(&self.path) {
+                if transient {
+                    sys::remove(file, &self.path);
+                }
+            }
+        }
+    }
+}
+
+fn read_holder(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+fn write_holder(file: &mut File) -> io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", process::id())?;
+    file.sync_data()
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    pub fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+    }
+
+    // Whether `file` is still the one at `path`, rather than one since
+    // removed, perhaps to be replaced by another.
+    pub fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+        let opened = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) => Ok(current.dev() == opened.dev() && current.ino() == opened.ino()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Removing the file before closing it, and so releasing the lock,
+    // means whoever was waiting for it will find it gone and start over.
+    pub fn remove(file: File, path: &Path) {
+        let _ = fs::remove_file(path);
+        drop(file);
+    }
+
+    fn flock(file: &File, flags: libc::c_int) -> io::Result<()> {
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), flags) } == 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        match flock(file, libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(()) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn lock(file: &File) -> io::Result<()> {
+        flock(file, libc::LOCK_EX)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::mem;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+
+    use winapi::shared::minwindef::DWORD;
+    use winapi::shared::winerror::ERROR_LOCK_VIOLATION;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+    use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+    // Not sharing the right to delete the file means it can't be removed
+    // while anyone has it open.
+    pub fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+            .open(path)
+    }
+
+    pub fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // Only once the file is closed can it be removed, which then fails if
+    // anyone else has opened it in the meantime, to wait for the lock.
+    pub fn remove(file: File, path: &Path) {
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+
+    // Byte-range locks on Windows are mandatory, so lock a byte well past
+    // the end of the file to keep the holder's pid readable.
+    const LOCK_OFFSET: DWORD = DWORD::max_value();
+
+    fn lock_file_ex(file: &File, flags: DWORD) -> io::Result<()> {
+        unsafe {
+            let mut overlapped: OVERLAPPED = mem::zeroed();
+            overlapped.u.s_mut().Offset = LOCK_OFFSET;
+            if LockFileEx(file.as_raw_handle() as _, flags, 0, 1, 0, &mut overlapped) != 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+    }
+
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        match lock_file_ex(file, LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY) {
+            Ok(()) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn lock(file: &File) -> io::Result<()> {
+        lock_file_ex(file, LOCKFILE_EXCLUSIVE_LOCK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    fn notify(_: Notification<'_>) {}
+
+    #[test]
+    fn lock_is_exclusive_and_reentrant() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let path = dir.path().join("locks/toolchain.lock");
+
+        let outer = FileLock::acquire(&path, &notify).unwrap();
+        let inner = FileLock::acquire(&path, &notify).unwrap();
+        assert!(!outer.reentered());
+        assert!(inner.reentered());
+
+        let mut other = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        assert!(!sys::try_lock(&other).unwrap());
+        assert_eq!(read_holder(&mut other), Some(process::id()));
+
+        drop(inner);
+        assert!(!sys::try_lock(&other).unwrap());
+
+        drop(outer);
+        assert!(sys::try_lock(&other).unwrap());
+    }
+
+    #[test]
+    fn transient_lock_is_removed_once_released() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let path = dir.path().join("downloads/package.lock");
+
+        let outer = FileLock::acquire_transient(&path, &notify).unwrap();
+        let inner = FileLock::acquire_transient(&path, &notify).unwrap();
+        drop(inner);
+        assert!(path.exists());
+
+        drop(outer);
+        assert!(!path.exists());
+    }
+
+    // A process that waited for a transient lock finds its file gone, and
+    // has to lock a new one, along with anyone else arriving since.
+    #[test]
+    #[cfg(unix)]
+    fn removed_lock_file_is_not_current() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let path = dir.path().join("package.lock");
+
+        let file = sys::open(&path).unwrap();
+        assert!(sys::is_current(&file, &path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(!sys::is_current(&file, &path).unwrap());
+        let _replacement = sys::open(&path).unwrap();
+        assert!(!sys::is_current(&file, &path).unwrap());
+    }
+}
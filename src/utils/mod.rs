@@ -1,6 +1,9 @@
 ///!  Utility functions for rustup
+pub mod credentials;
+pub mod lock;
 pub mod notifications;
 pub mod raw;
+pub mod retry_policy;
 pub mod toml_utils;
 pub mod tty;
 pub mod units;
@@ -19,6 +19,9 @@ pub enum Notification<'a> {
     DownloadDataReceived(&'a [u8]),
     /// Download has finished.
     DownloadFinished,
+    /// Holding back from downloading faster than the given rate, in bytes
+    /// per second.
+    DownloadThrottled(u64),
     /// The things we're tracking that are not counted in bytes.
     /// Must be paired with a pop-units; our other calls are not
     /// setup to guarantee this any better.
@@ -27,6 +30,12 @@ pub enum Notification<'a> {
     DownloadPopUnit,
     NoCanonicalPath(&'a Path),
     ResumingPartialDownload,
+    /// The file has changed since the partial download of it started, or
+    /// the server can't resume it, so it's being downloaded from the start.
+    DownloadRestarted,
+    /// Received the `ETag` and `Last-Modified` of the file, either of which
+    /// may be missing.
+    DownloadVersionReceived(Option<&'a str>, Option<&'a str>),
     /// This would make more sense as a crate::notifications::Notification
     /// member, but the notification callback is already narrowed to
     /// utils::notifications by the time tar unpacking is called.
@@ -40,6 +49,9 @@ pub enum Notification<'a> {
     /// running programs like virus scanner are known to cause this
     /// the heuristic is quite good.
     RenameInUse(&'a Path, &'a Path),
+    /// Another process holds a lock we need; the pid is that of the
+    /// holder, if known.
+    WaitingForFileLock(&'a Path, Option<u32>),
 }
 
 impl<'a> Notification<'a> {
@@ -56,10 +68,15 @@ impl<'a> Notification<'a> {
             | DownloadPushUnit(_)
             | DownloadPopUnit
             | DownloadFinished
+            | DownloadThrottled(_)
             | ResumingPartialDownload
+            | DownloadRestarted
+            | DownloadVersionReceived(_, _)
             | UsingCurl
             | UsingReqwest => NotificationLevel::Verbose,
-            RenameInUse(_, _) | SetDefaultBufferSize(_) => NotificationLevel::Info,
+            RenameInUse(_, _) | SetDefaultBufferSize(_) | WaitingForFileLock(_, _) => {
+                NotificationLevel::Info
+            }
             NoCanonicalPath(_) => NotificationLevel::Warn,
             Error(_) => NotificationLevel::Error,
         }
@@ -85,6 +102,15 @@ impl<'a> Display for Notification<'a> {
                 src.display(),
                 dest.display()
             ),
+            WaitingForFileLock(path, Some(pid)) => write!(
+                f,
+                "waiting for lock held by pid {} on '{}'",
+                pid,
+                path.display()
+            ),
+            WaitingForFileLock(path, None) => {
+                write!(f, "waiting for lock on '{}'", path.display())
+            }
             SetDefaultBufferSize(size) => write!(
                 f,
                 "using up to {} of RAM to unpack components",
@@ -96,8 +122,22 @@ impl<'a> Display for Notification<'a> {
             DownloadPushUnit(_) => Ok(()),
             DownloadPopUnit => Ok(()),
             DownloadFinished => write!(f, "download finished"),
+            DownloadThrottled(rate) => write!(
+                f,
+                "download throttled to {}",
+                units::Size::new(*rate as usize, units::Unit::B, units::UnitMode::Rate)
+                    .to_string()
+                    .trim_start()
+            ),
             NoCanonicalPath(path) => write!(f, "could not canonicalize path: '{}'", path.display()),
             ResumingPartialDownload => write!(f, "resuming partial download"),
+            DownloadRestarted => write!(f, "partial download can't be resumed; starting again"),
+            DownloadVersionReceived(etag, last_modified) => write!(
+                f,
+                "file version is: ETag '{}', last modified '{}'",
+                etag.unwrap_or("none"),
+                last_modified.unwrap_or("unknown")
+            ),
             UsingCurl => write!(f, "downloading with curl"),
             UsingReqwest => write!(f, "downloading with reqwest"),
         }
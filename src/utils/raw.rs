@@ -327,6 +327,75 @@ pub fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Recreate the tree at `src` as `dest`, hardlinking files rather than
+/// copying them, except those for which `copy` returns true. Symlinks are
+/// recreated as they are.
+pub fn link_dir(src: &Path, dest: &Path, copy: &dyn Fn(&Path) -> bool) -> io::Result<()> {
+    fs::create_dir(dest)?;
+    for entry in src.read_dir()? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let src = entry.path();
+        let dest = dest.join(entry.file_name());
+        if kind.is_dir() {
+            link_dir(&src, &dest, copy)?;
+        } else if kind.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(&src)?, &dest)?;
+            #[cfg(windows)]
+            fs::copy(&src, &dest).map(|_| ())?;
+        } else if copy(&src) {
+            fs::copy(&src, &dest)?;
+        } else {
+            fs::hard_link(&src, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Atomically swap two paths, so that each names what the other did.
+///
+/// Returns `false`, having changed nothing, where that isn't supported:
+/// on platforms other than Linux, and there by kernels that predate it
+/// and by some filesystems.
+pub fn exchange_paths(a: &Path, b: &Path) -> io::Result<bool> {
+    #[cfg(target_os = "linux")]
+    fn inner(a: &Path, b: &Path) -> io::Result<bool> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        const RENAME_EXCHANGE: libc::c_uint = 1 << 1;
+
+        let a = CString::new(a.as_os_str().as_bytes())?;
+        let b = CString::new(b.as_os_str().as_bytes())?;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                libc::AT_FDCWD,
+                a.as_ptr(),
+                libc::AT_FDCWD,
+                b.as_ptr(),
+                RENAME_EXCHANGE,
+            )
+        };
+        if ret == 0 {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINVAL) | Some(libc::ENOSYS) => Ok(false),
+            _ => Err(e),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn inner(_: &Path, _: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    inner(a, b)
+}
+
 pub fn prefix_arg<S: AsRef<OsStr>>(name: &str, s: S) -> OsString {
     let mut arg = OsString::from(name);
     arg.push(s);
@@ -0,0 +1,339 @@
+//! How downloads that fail for reasons which may pass are retried.
+
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use retry::delay::jitter;
+
+use crate::errors::*;
+
+/// However long the backoff grows or a server asks us to wait, no retry
+/// waits longer than this.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How many times, and how soon, a failed download is tried again.
+///
+/// Only failures that might not happen again are retried: dropped
+/// connections, server errors, and servers asking to be asked again
+/// later (429). Anything else, such as the file not being there (404),
+/// fails straight away.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times to retry before giving up
+    pub max_retries: usize,
+    /// About how long to wait before the first retry. Each retry waits
+    /// about twice as long as the one before, unless the server says how
+    /// long to wait with a `Retry-After` header.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `f` until it succeeds, fails in a way not worth retrying, or
+    /// has been retried `max_retries` times, returning the last result.
+    /// `retrying` is told how long the wait is before each retry. Setting
+    /// `cancel` stops any more retries, including one being waited for.
+    pub fn retry<T>(
+        &self,
+        cancel: Option<&AtomicBool>,
+        mut f: impl FnMut() -> Result<T>,
+        retrying: &dyn Fn(Duration),
+    ) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            let e = match f() {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if retries == self.max_retries || !is_transient(&e) || is_cancelled(cancel) {
+                return Err(e);
+            }
+            let delay = retry_after(&e)
+                .unwrap_or_else(|| self.backoff(retries))
+                .min(MAX_DELAY);
+            retrying(delay);
+            if !sleep(delay, cancel) {
+                return Err(e);
+            }
+            retries += 1;
+        }
+    }
+
+    // The wait before retry number `retries`, counting from zero. Between
+    // half of it and all of it is chosen at random so that clients which
+    // failed together, say when a server restarted, don't all retry
+    // together.
+    fn backoff(&self, retries: usize) -> Duration {
+        let delay = self
+            .base_delay
+            .checked_mul(1 << retries.min(16) as u32)
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+        delay / 2 + jitter(delay / 2)
+    }
+}
+
+/// Whether a download that failed with `e` might succeed if tried again:
+/// whether the server timed out, stalled, dropped the connection, or
+/// answered with a server error or asked to be asked again later.
+pub fn is_transient(e: &Error) -> bool {
+    match e.kind() {
+        // Downloads of files that aren't there fail with `DownloadNotExists`
+        // or `StreamingFileNotExists` instead
+        ErrorKind::DownloadingFile { .. } | ErrorKind::StreamingFile { .. } => {}
+        ErrorKind::BrokenPartialFile => return true,
+        _ => return false,
+    }
+    let mut source = e.source();
+    while let Some(cause) = source {
+        match download_error_kind(cause) {
+            Some(download::ErrorKind::HttpStatus(code)) => {
+                return *code == 408 || *code == 429 || (500..600).contains(code)
+            }
+            Some(download::ErrorKind::TimedOut)
+            | Some(download::ErrorKind::Stalled(..))
+            | Some(download::ErrorKind::ConnectionFailed)
+            | Some(download::ErrorKind::UnexpectedContentRange(..)) => return true,
+            _ => {}
+        }
+        source = cause.source();
+    }
+    false
+}
+
+// The kind of `e` if it's a download error, whether or not it's been turned
+// into one of ours.
+fn download_error_kind<'a>(e: &'a (dyn StdError + 'static)) -> Option<&'a download::ErrorKind> {
+    if let Some(e) = e.downcast_ref::<Error>() {
+        if let ErrorKind::Download(kind) = e.kind() {
+            return Some(kind);
+        }
+    }
+    e.downcast_ref::<download::Error>()
+        .map(download::Error::kind)
+}
+
+// How long the server asked us to wait before trying again, if it did.
+fn retry_after(e: &Error) -> Option<Duration> {
+    let mut source = e.source();
+    while let Some(cause) = source {
+        if let Some(cause) = cause.downcast_ref::<download::Error>() {
+            if let download::ErrorKind::RetryAfter(delay) = cause.kind() {
+                return Some(*delay);
+            }
+        }
+        source = cause.source();
+    }
+    None
+}
+
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.map_or(false, |cancel| cancel.load(Ordering::Relaxed))
+}
+
+// Waits for `delay`, or until cancelled, returning whether it wasn't
+// cancelled.
+fn sleep(delay: Duration, cancel: Option<&AtomicBool>) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    let until = Instant::now() + delay;
+    loop {
+        if is_cancelled(cancel) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= until {
+            return true;
+        }
+        thread::sleep((until - now).min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::io;
+    use std::path::PathBuf;
+
+    fn failed_download(status: u32, retry_after: Option<Duration>) -> Error {
+        let e = download::Error::from(download::ErrorKind::HttpStatus(status));
+        let e = match retry_after {
+            Some(delay) => download::Error::with_chain(
+                download::Error::from(download::ErrorKind::RetryAfter(delay)),
+                download::ErrorKind::HttpStatus(status),
+            ),
+            None => e,
+        };
+        let url = "http://example.com/file".parse().unwrap();
+        let path = PathBuf::from("file");
+        Error::with_chain(
+            Error::from(e),
+            if status == 404 {
+                ErrorKind::DownloadNotExists { url, path }
+            } else {
+                ErrorKind::DownloadingFile { url, path }
+            },
+        )
+    }
+
+    fn failed_with(e: download::Error) -> Error {
+        Error::with_chain(
+            Error::from(e),
+            ErrorKind::DownloadingFile {
+                url: "http://example.com/file".parse().unwrap(),
+                path: PathBuf::from("file"),
+            },
+        )
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let attempts = Cell::new(0);
+        let result = policy().retry(
+            None,
+            || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() < 3 {
+                    Err(failed_download(503, None))
+                } else {
+                    Ok(())
+                }
+            },
+            &|_| (),
+        );
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn retries_give_up_after_max_retries() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy().retry(
+            None,
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(failed_download(429, None))
+            },
+            &|_| (),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 4);
+    }
+
+    #[test]
+    fn missing_files_are_not_retried() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy().retry(
+            None,
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(failed_download(404, None))
+            },
+            &|_| (),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn network_failures_are_transient() {
+        assert!(is_transient(&failed_download(500, None)));
+        assert!(is_transient(&failed_with(
+            download::ErrorKind::TimedOut.into()
+        )));
+        assert!(is_transient(&failed_with(download::Error::with_chain(
+            io::Error::from(io::ErrorKind::ConnectionReset),
+            download::ErrorKind::ConnectionFailed,
+        ))));
+        assert!(!is_transient(&failed_download(403, None)));
+    }
+
+    #[test]
+    fn tls_failures_are_not_retried() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy().retry(
+            None,
+            || {
+                attempts.set(attempts.get() + 1);
+                let tls = io::Error::new(io::ErrorKind::InvalidData, "invalid certificate");
+                Err(failed_with(download::Error::with_chain(
+                    tls,
+                    "failed to make network request",
+                )))
+            },
+            &|_| (),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn full_disk_is_not_retried() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy().retry(
+            None,
+            || {
+                attempts.set(attempts.get() + 1);
+                let full = io::Error::from_raw_os_error(libc::ENOSPC);
+                Err(failed_with(full.into()))
+            },
+            &|_| (),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn retry_after_is_honored() {
+        let e = failed_download(503, Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&e), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&failed_download(503, None)), None);
+
+        // Waiting for it would hold up the test, so cancel the retry while
+        // checking it would have waited as long as asked.
+        let cancel = AtomicBool::new(false);
+        let delays = RefCell::new(Vec::new());
+        let result: Result<()> = policy().retry(
+            Some(&cancel),
+            || Err(failed_download(503, Some(Duration::from_secs(7)))),
+            &|delay| {
+                delays.borrow_mut().push(delay);
+                cancel.store(true, Ordering::Relaxed);
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(*delays.borrow(), vec![Duration::from_secs(7)]);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_within_bounds() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(2),
+        };
+        for retries in 0..4 {
+            let full = Duration::from_secs(2 << retries);
+            let delay = policy.backoff(retries);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+        assert!(policy.backoff(40) <= MAX_DELAY);
+    }
+}
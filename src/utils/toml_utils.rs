@@ -32,6 +32,22 @@ pub fn get_opt_string(
     }
 }
 
+pub fn get_opt_integer(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+) -> Result<Option<i64>> {
+    if let Ok(v) = get_value(table, key, path) {
+        if let toml::Value::Integer(i) = v {
+            Ok(Some(i))
+        } else {
+            Err(ErrorKind::ExpectedType("integer", path.to_owned() + key).into())
+        }
+    } else {
+        Ok(None)
+    }
+}
+
 pub fn get_bool(table: &mut toml::value::Table, key: &str, path: &str) -> Result<bool> {
     get_value(table, key, path).and_then(|v| {
         if let toml::Value::Boolean(b) = v {
@@ -42,6 +58,18 @@ pub fn get_bool(table: &mut toml::value::Table, key: &str, path: &str) -> Result
     })
 }
 
+pub fn get_opt_bool(table: &mut toml::value::Table, key: &str, path: &str) -> Result<Option<bool>> {
+    if let Ok(v) = get_value(table, key, path) {
+        if let toml::Value::Boolean(b) = v {
+            Ok(Some(b))
+        } else {
+            Err(ErrorKind::ExpectedType("bool", path.to_owned() + key).into())
+        }
+    } else {
+        Ok(None)
+    }
+}
+
 pub fn get_table(
     table: &mut toml::value::Table,
     key: &str,
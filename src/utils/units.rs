@@ -75,6 +75,34 @@ impl Display for Size {
     }
 }
 
+/// Parses a rate in bytes per second, such as `2MiB/s`, `500 KB/s` or
+/// `1000000`. Decimal (`KB`, `MB`, `GB`) and binary (`KiB`, `MiB`, `GiB`)
+/// multiples are understood, and the `/s` may be left off.
+pub fn parse_rate(rate: &str) -> Option<u64> {
+    let rate = rate.trim().trim_end_matches("/s");
+    let unit_start = rate
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or_else(|| rate.len());
+    let (number, unit) = rate.split_at(unit_start);
+    let number: f64 = number.parse().ok()?;
+    let multiple: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" | "kB" => 1000,
+        "KiB" => 1 << 10,
+        "MB" => 1000 * 1000,
+        "MiB" => 1 << 20,
+        "GB" => 1000 * 1000 * 1000,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+    let rate = number * multiple as f64;
+    if rate >= 1.0 && rate < std::u64::MAX as f64 {
+        Some(rate as u64)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -153,4 +181,18 @@ mod tests {
             "  1.0 giga-IOPS"
         );
     }
+    #[test]
+    fn rate_parser_test() {
+        use crate::utils::units::parse_rate;
+
+        assert_eq!(parse_rate("2MiB/s"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_rate("500 KB/s"), Some(500_000));
+        assert_eq!(parse_rate("1.5KiB"), Some(1536));
+        assert_eq!(parse_rate("1000000"), Some(1_000_000));
+        assert_eq!(parse_rate(" 1 GB/s "), Some(1_000_000_000));
+        assert_eq!(parse_rate("0"), None);
+        assert_eq!(parse_rate("fast"), None);
+        assert_eq!(parse_rate("2 MiBs"), None);
+        assert_eq!(parse_rate("MiB/s"), None);
+    }
 }
@@ -5,6 +5,7 @@ use std::fs::{self, File};
 use std::io::{self, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use retry::delay::{jitter, Fibonacci};
 use retry::{retry, OperationResult};
@@ -170,18 +171,48 @@ pub fn download_file_with_resume(
     resume_from_partial: bool,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
-    use download::ErrorKind as DEK;
-    match download_file_(url, path, hasher, resume_from_partial, notify_handler) {
+    download_file_with_backend(
+        download_backend(),
+        &download::Options::default(),
+        url,
+        path,
+        hasher,
+        resume_from_partial,
+        None,
+        notify_handler,
+    )
+}
+
+/// Like `download_file_with_resume`, but with `backend` already chosen,
+/// downloading as `options` say, and giving up as soon as `cancel` is set.
+/// As this doesn't consult the process environment, it may be used from
+/// threads other than the main one.
+pub fn download_file_with_backend(
+    backend: download::Backend,
+    options: &download::Options<'_>,
+    url: &Url,
+    path: &Path,
+    hasher: Option<&mut Sha256>,
+    resume_from_partial: bool,
+    cancel: Option<&AtomicBool>,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    match download_file_(
+        backend,
+        options,
+        url,
+        path,
+        hasher,
+        resume_from_partial,
+        cancel,
+        notify_handler,
+    ) {
         Ok(_) => Ok(()),
+        // The file wasn't sent as it hasn't changed since the version asked
+        // about, which is no failure
+        Err(e) if is_not_modified(&e) => Err(e),
         Err(e) => {
-            let is_client_error = match e.kind() {
-                // Specifically treat the bad partial range error as not our
-                // fault in case it was something odd which happened.
-                ErrorKind::Download(DEK::HttpStatus(416)) => false,
-                ErrorKind::Download(DEK::HttpStatus(400..=499)) => true,
-                ErrorKind::Download(DEK::FileNotFound) => true,
-                _ => false,
-            };
+            let is_client_error = is_client_error(&e);
             Err(e).chain_err(|| {
                 if is_client_error {
                     ErrorKind::DownloadNotExists {
@@ -199,15 +230,94 @@ pub fn download_file_with_resume(
     }
 }
 
+/// Downloads `url` with `backend`, handing the data to `data_received` as
+/// it arrives rather than saving it, downloading as `options` say, and
+/// giving up as soon as `cancel` is set. Like `download_file_with_backend`,
+/// this may be used from threads other than the main one.
+pub fn stream_file_with_backend(
+    backend: download::Backend,
+    options: &download::Options<'_>,
+    url: &Url,
+    cancel: &AtomicBool,
+    data_received: &dyn Fn(&[u8]),
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    use download::{Backend, Event};
+
+    notify_handler(match backend {
+        Backend::Curl => Notification::UsingCurl,
+        Backend::Reqwest(_) => Notification::UsingReqwest,
+    });
+    let res = download::download_with_backend(backend, url, 0, options, &|msg| {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(download::ErrorKind::Cancelled.into());
+        }
+        match msg {
+            Event::DownloadContentLengthReceived(len) => {
+                notify_handler(Notification::DownloadContentLengthReceived(len));
+            }
+            Event::DownloadDataReceived(data) => data_received(data),
+            Event::DownloadThrottled(rate) => {
+                notify_handler(Notification::DownloadThrottled(rate));
+            }
+            // Nothing is resumed, as nothing is saved
+            _ => {}
+        }
+        Ok(())
+    });
+
+    res.map_err(Error::from).map_err(|e| {
+        let is_client_error = is_client_error(&e);
+        e.chain_err(|| {
+            if is_client_error {
+                ErrorKind::StreamingFileNotExists { url: url.clone() }
+            } else {
+                ErrorKind::StreamingFile { url: url.clone() }
+            }
+        })
+    })
+}
+
+/// Whether a download failed only because the file hasn't changed since
+/// the version in its `download::Options::cached`.
+pub fn is_not_modified(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Download(download::ErrorKind::NotModified)
+    )
+}
+
+// Whether a failed download is the fault of the request rather than of
+// the network or server, so there's no point retrying it.
+fn is_client_error(e: &Error) -> bool {
+    use download::ErrorKind as DEK;
+    match e.kind() {
+        // Specifically treat the bad partial range error as not our
+        // fault in case it was something odd which happened.
+        ErrorKind::Download(DEK::HttpStatus(416)) => false,
+        // The server timed out waiting for the request, or is rate
+        // limiting us, either of which may pass.
+        ErrorKind::Download(DEK::HttpStatus(408)) | ErrorKind::Download(DEK::HttpStatus(429)) => {
+            false
+        }
+        ErrorKind::Download(DEK::HttpStatus(400..=499)) => true,
+        ErrorKind::Download(DEK::FileNotFound) => true,
+        _ => false,
+    }
+}
+
 fn download_file_(
+    backend: download::Backend,
+    options: &download::Options<'_>,
     url: &Url,
     path: &Path,
     hasher: Option<&mut Sha256>,
     resume_from_partial: bool,
+    cancel: Option<&AtomicBool>,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
-    use download::download_to_path_with_backend;
-    use download::{Backend, Event, TlsBackend};
+    use download::download_to_path_with_options;
+    use download::{Backend, Event};
     use sha2::Digest;
     use std::cell::RefCell;
 
@@ -218,9 +328,16 @@ fn download_file_(
     // This callback will write the download to disk and optionally
     // hash the contents, then forward the notification up the stack
     let callback: &dyn Fn(Event<'_>) -> download::Result<()> = &|msg| {
-        if let Event::DownloadDataReceived(data) = msg {
-            if let Some(h) = hasher.borrow_mut().as_mut() {
-                h.update(data);
+        if cancel.map_or(false, |c| c.load(Ordering::Relaxed)) {
+            return Err(download::ErrorKind::Cancelled.into());
+        }
+
+        if let Some(h) = hasher.borrow_mut().as_mut() {
+            match msg {
+                Event::DownloadDataReceived(data) => h.update(data),
+                // The data received so far is thrown away
+                Event::DownloadRestarted => h.reset(),
+                _ => {}
             }
         }
 
@@ -231,21 +348,52 @@ fn download_file_(
             Event::DownloadDataReceived(data) => {
                 notify_handler(Notification::DownloadDataReceived(data));
             }
+            Event::DownloadThrottled(rate) => {
+                notify_handler(Notification::DownloadThrottled(rate));
+            }
             Event::ResumingPartialDownload => {
                 notify_handler(Notification::ResumingPartialDownload);
             }
+            Event::DownloadRestarted => {
+                notify_handler(Notification::DownloadRestarted);
+            }
+            Event::DownloadVersionReceived(etag, last_modified) => {
+                notify_handler(Notification::DownloadVersionReceived(etag, last_modified));
+            }
+            _ => {}
         }
 
         Ok(())
     };
 
     // Download the file
+    notify_handler(match backend {
+        Backend::Curl => Notification::UsingCurl,
+        Backend::Reqwest(_) => Notification::UsingReqwest,
+    });
+    let res = download_to_path_with_options(
+        backend,
+        url,
+        path,
+        resume_from_partial,
+        options,
+        Some(callback),
+    );
+
+    notify_handler(Notification::DownloadFinished);
+
+    res.map_err(|e| e.into())
+}
+
+/// The download backend selected by the environment.
+pub fn download_backend() -> download::Backend {
+    use download::{Backend, TlsBackend};
 
     // Keep the curl env var around for a bit
     let use_curl_backend = process().var_os("RUSTUP_USE_CURL").is_some();
     let use_rustls = process().var_os("RUSTUP_USE_RUSTLS").is_some();
-    let (backend, notification) = if use_curl_backend {
-        (Backend::Curl, Notification::UsingCurl)
+    if use_curl_backend {
+        Backend::Curl
     } else {
         let tls_backend = if use_rustls {
             TlsBackend::Rustls
@@ -259,15 +407,15 @@ fn download_file_(
                 TlsBackend::Rustls
             }
         };
-        (Backend::Reqwest(tls_backend), Notification::UsingReqwest)
-    };
-    notify_handler(notification);
-    let res =
-        download_to_path_with_backend(backend, url, path, resume_from_partial, Some(callback));
-
-    notify_handler(Notification::DownloadFinished);
+        Backend::Reqwest(tls_backend)
+    }
+}
 
-    res.map_err(|e| e.into())
+/// The size of the file at `url` as reported by the server, if it says,
+/// asking as `options` say.
+pub fn content_length(url: &Url, options: &download::Options<'_>) -> Result<Option<u64>> {
+    download::content_length_with_backend(download_backend(), url, options)
+        .chain_err(|| format!("could not get the size of '{}'", url))
 }
 
 pub fn parse_url(url: &str) -> Result<Url> {
@@ -356,6 +504,13 @@ where
     })
 }
 
+pub fn link_dir(src: &Path, dest: &Path, copy: &dyn Fn(&Path) -> bool) -> Result<()> {
+    raw::link_dir(src, dest, copy).chain_err(|| ErrorKind::CopyingDirectory {
+        src: PathBuf::from(src),
+        dest: PathBuf::from(dest),
+    })
+}
+
 pub fn copy_file(src: &Path, dest: &Path) -> Result<()> {
     let metadata = fs::symlink_metadata(src).chain_err(|| ErrorKind::ReadingFile {
         name: "metadata for",
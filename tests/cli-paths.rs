@@ -32,6 +32,13 @@ export PATH="$HOME/apple/bin"
         format!(". \"{dir}/{sh}\"\n", dir = dir, sh = sh)
     }
 
+    // Runs `args` as though `shell` were the user's shell.
+    fn run_in_shell(config: &clitools::Config, shell: &str, args: &[&str]) {
+        let mut cmd = clitools::cmd(config, args[0], &args[1..]);
+        cmd.env("SHELL", shell);
+        assert!(cmd.output().unwrap().status.success());
+    }
+
     #[test]
     fn install_creates_necessary_scripts() {
         clitools::setup(Scenario::Empty, &|config| {
@@ -282,6 +289,91 @@ export PATH="$HOME/apple/bin"
         });
     }
 
+    #[test]
+    fn install_and_uninstall_fish_conf() {
+        clitools::setup(Scenario::Empty, &|config| {
+            let conf = config.homedir.join(".config/fish/conf.d/rustup.fish");
+
+            run_in_shell(config, "/usr/bin/fish", &INIT_NONE);
+            let env = fs::read_to_string(config.cargodir.join("env.fish")).unwrap();
+            let cargo_bin = config.cargodir.join("bin");
+            assert!(env.contains(&format!("set -x PATH \"{}\" $PATH", cargo_bin.display())));
+            let expected = format!("source \"{}/env.fish\"\n", config.cargodir.display());
+            assert_eq!(fs::read_to_string(&conf).unwrap(), expected);
+
+            run_in_shell(
+                config,
+                "/usr/bin/fish",
+                &["rustup", "self", "uninstall", "-y"],
+            );
+            assert!(!conf.exists());
+        });
+    }
+
+    #[test]
+    fn install_and_uninstall_existing_nushell_env() {
+        clitools::setup(Scenario::Empty, &|config| {
+            let rc = config.homedir.join(".config/nushell/env.nu");
+            fs::create_dir_all(rc.parent().unwrap()).unwrap();
+            raw::write_file(&rc, FAKE_RC).unwrap();
+
+            expect_ok(config, &INIT_NONE);
+            assert!(config.cargodir.join("env.nu").exists());
+            let expected = format!(
+                "{}source \"{}/env.nu\"\n",
+                FAKE_RC,
+                config.cargodir.display()
+            );
+            assert_eq!(fs::read_to_string(&rc).unwrap(), expected);
+
+            expect_ok(config, &["rustup", "self", "uninstall", "-y"]);
+            assert_eq!(fs::read_to_string(&rc).unwrap(), FAKE_RC);
+        });
+    }
+
+    #[test]
+    fn install_and_uninstall_elvish_rc() {
+        clitools::setup(Scenario::Empty, &|config| {
+            let rc = config.homedir.join(".config/elvish/rc.elv");
+            let legacy_rc = config.homedir.join(".elvish/rc.elv");
+
+            run_in_shell(config, "/usr/bin/elvish", &INIT_NONE);
+            assert!(config.cargodir.join("env.elv").exists());
+            let expected = format!("eval (slurp < '{}/env.elv')\n", config.cargodir.display());
+            assert_eq!(fs::read_to_string(&rc).unwrap(), expected);
+            assert!(!legacy_rc.exists());
+
+            run_in_shell(
+                config,
+                "/usr/bin/elvish",
+                &["rustup", "self", "uninstall", "-y"],
+            );
+            assert_eq!(fs::read_to_string(&rc).unwrap(), "");
+        });
+    }
+
+    #[test]
+    fn install_and_uninstall_existing_cshrc() {
+        clitools::setup(Scenario::Empty, &|config| {
+            let rc = config.homedir.join(".cshrc");
+            raw::write_file(&rc, FAKE_RC).unwrap();
+
+            run_in_shell(config, "/bin/tcsh", &INIT_NONE);
+            assert!(config.cargodir.join("env.csh").exists());
+            let expected = format!(
+                "{}source \"{}/env.csh\"\n",
+                FAKE_RC,
+                config.cargodir.display()
+            );
+            assert_eq!(fs::read_to_string(&rc).unwrap(), expected);
+            // tcsh would read .tcshrc in place of .cshrc
+            assert!(!config.homedir.join(".tcshrc").exists());
+
+            run_in_shell(config, "/bin/tcsh", &["rustup", "self", "uninstall", "-y"]);
+            assert_eq!(fs::read_to_string(&rc).unwrap(), FAKE_RC);
+        });
+    }
+
     #[test]
     fn install_doesnt_modify_path_if_passed_no_modify_path() {
         clitools::setup(Scenario::Empty, &|config| {
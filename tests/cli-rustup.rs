@@ -4,16 +4,24 @@ pub mod mock;
 
 use std::env::consts::EXE_SUFFIX;
 use std::fs;
+use std::io::{BufRead, BufReader};
 use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::process::{self, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 use rustup::for_host;
 use rustup::test::this_host_triple;
+use rustup::utils::lock::FileLock;
 use rustup::utils::raw;
+use rustup::utils::Notification;
 
 use crate::mock::clitools::{
     self, expect_err, expect_not_stdout_ok, expect_ok, expect_ok_ex, expect_stderr_ok,
     expect_stdout_ok, run, set_current_dist_date, Config, Scenario,
 };
+use crate::mock::fault_server::Fault;
 
 macro_rules! for_host_and_home {
     ($config:ident, $s: expr) => {
@@ -1030,6 +1038,143 @@ fn show_toolchain_env_not_installed() {
     });
 }
 
+// A proxy that finds another process installing its toolchain waits for
+// it, then uses what it installed.
+#[test]
+fn proxy_waits_for_toolchain_being_installed_elsewhere() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let toolchain = config
+            .rustupdir
+            .join(format!("toolchains/nightly-{}", this_host_triple()));
+        let stash = config.rustupdir.join("stashed-nightly");
+        fs::rename(&toolchain, &stash).unwrap();
+
+        // The test process stands in for the rustup installing nightly.
+        let lock_path = config
+            .rustupdir
+            .join(format!("locks/nightly-{}.lock", this_host_triple()));
+        let lock = FileLock::acquire(&lock_path, &|_: Notification<'_>| ()).unwrap();
+
+        let mut cmd = clitools::cmd(config, "rustc", &["--version"]);
+        cmd.env("RUSTUP_TOOLCHAIN", "nightly");
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = cmd.spawn().unwrap();
+        let stderr = BufReader::new(child.stderr.take().unwrap());
+        let (tx, rx) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            for line in stderr.lines() {
+                tx.send(line.unwrap()).unwrap();
+            }
+        });
+
+        let waiting = format!("waiting for lock held by pid {}", process::id());
+        loop {
+            let line = rx
+                .recv_timeout(Duration::from_secs(60))
+                .expect("the proxy didn't wait for the lock");
+            if line.contains(&waiting) {
+                break;
+            }
+        }
+
+        // Finish "installing" it, and let the proxy go.
+        fs::rename(&stash, &toolchain).unwrap();
+        drop(lock);
+
+        let output = child.wait_with_output().unwrap();
+        reader.join().unwrap();
+        let rest: Vec<String> = rx.iter().collect();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "1.3.0 (hash-nightly-2)\n"
+        );
+        for line in &rest {
+            assert!(!line.contains("installing component"), "{}", line);
+            assert!(!line.contains("syncing channel updates"), "{}", line);
+        }
+    });
+}
+
+// An install killed part way through is rolled back by the next rustup to
+// take the toolchain's lock, which then installs it afresh.
+#[test]
+fn interrupted_install_is_rolled_back_by_the_next_run() {
+    setup(&|config| {
+        let server = clitools::serve_dist_server(config, Scenario::ArchivesV2);
+        // rustc is installed last, so holding up its download leaves the
+        // install waiting with the other components installed.
+        server.fail(
+            &format!("rustc-nightly-{}.tar.xz", this_host_triple()),
+            &[Fault::Delay(Duration::from_secs(60))],
+        );
+
+        let mut cmd = clitools::cmd(config, "rustup", &["toolchain", "install", "nightly"]);
+        cmd.env("RUSTUP_DIST_SERVER", server.url().as_str());
+        cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+        let mut child = cmd.spawn().unwrap();
+        let stderr = BufReader::new(child.stderr.take().unwrap());
+        let installing = stderr
+            .lines()
+            .map(|line| line.unwrap())
+            .any(|line| line.contains("installing component 'rust-std'"));
+        assert!(installing, "the install finished without being interrupted");
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let toolchain = config
+            .rustupdir
+            .join(format!("toolchains/nightly-{}", this_host_triple()));
+        assert!(toolchain.join(format!("bin/cargo{}", EXE_SUFFIX)).exists());
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly"],
+            "rolling back interrupted changes",
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+    });
+}
+
+// Commands that only look at a toolchain leave a transaction against it be,
+// since the process holding its lock may still be in the middle of it.
+#[test]
+fn transaction_in_progress_is_left_alone_by_readers() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let toolchain = config
+            .rustupdir
+            .join(format!("toolchains/nightly-{}", this_host_triple()));
+        raw::write_file(&toolchain.join("foo"), "").unwrap();
+        raw::write_file(
+            &toolchain.join("lib/rustlib/rustup-transaction-journal"),
+            "add-file\tfoo\n",
+        )
+        .unwrap();
+
+        // The test process stands in for the rustup making the changes.
+        let lock_path = config
+            .rustupdir
+            .join(format!("locks/nightly-{}.lock", this_host_triple()));
+        let lock = FileLock::acquire(&lock_path, &|_: Notification<'_>| ()).unwrap();
+        expect_ok(config, &["rustup", "show"]);
+        expect_ok(config, &["rustup", "component", "list"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        assert!(toolchain.join("foo").exists());
+
+        // Once it has gone, whatever it left is rolled back.
+        drop(lock);
+        expect_stderr_ok(
+            config,
+            &["rustup", "component", "add", "rust-src"],
+            "rolling back interrupted changes",
+        );
+        assert!(!toolchain.join("foo").exists());
+    });
+}
+
 #[test]
 fn show_active_toolchain() {
     setup(&|config| {
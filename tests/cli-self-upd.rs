@@ -17,8 +17,9 @@ use rustup::Notification;
 
 use crate::mock::clitools::{
     self, expect_component_executable, expect_component_not_executable, expect_err, expect_err_ex,
-    expect_ok, expect_ok_contains, expect_ok_ex, expect_stderr_ok, expect_stdout_ok,
-    output_release_file, run, self_update_setup, Config, Scenario,
+    expect_not_stderr_ok, expect_ok, expect_ok_contains, expect_ok_ex, expect_stderr_ok,
+    expect_stdout_ok, output_archived_rustup, output_channel_release_file, output_release_file,
+    run, self_update_setup, Config, Scenario,
 };
 use crate::mock::dist::calc_hash;
 
@@ -444,6 +445,308 @@ fn update_no_change() {
     });
 }
 
+#[test]
+fn update_to_exact_version() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, self_dist| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        output_release_file(self_dist, "1", version);
+        output_archived_rustup(config, self_dist, "1.0.5");
+        expect_ok_ex(
+            config,
+            &["rustup", "self", "update", "--version", "1.0.5"],
+            &format!("  rustup updated - {} (from {})\n\n", version, version),
+            r"info: downloading self-update
+",
+        );
+        expect_err(
+            config,
+            &["rustup", "self", "update", "--version", "9.9.9"],
+            "could not download file",
+        );
+        expect_err(
+            config,
+            &["rustup", "self", "update", "--version", "latest"],
+            "The version must be such as 1.22.1",
+        );
+    });
+}
+
+#[test]
+fn update_to_pinned_version() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, self_dist| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        output_release_file(self_dist, "1", version);
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "pinned-rustup-version", TEST_VERSION],
+            "info: rustup will update itself only to version 1.1.1",
+        );
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "update"],
+            "info: rustup is pinned to version 1.1.1",
+        );
+        assert_ne!(before_hash, calc_hash(&bin));
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "pinned-rustup-version", "none"],
+            "info: rustup will update itself to the latest version",
+        );
+        expect_err(
+            config,
+            &["rustup", "set", "pinned-rustup-version", "1.1"],
+            "The version must be such as 1.22.1, or 'none'",
+        );
+    });
+}
+
+#[test]
+fn pinned_version_already_installed_is_not_updated() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_ok(config, &["rustup", "set", "pinned-rustup-version", version]);
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_not_stderr_ok(
+            config,
+            &["rustup", "update"],
+            "info: checking for self-updates",
+        );
+        assert_eq!(before_hash, calc_hash(&bin));
+        expect_stdout_ok(
+            config,
+            &["rustup", "check"],
+            &format!("rustup - Up to date : {}", version),
+        );
+    });
+}
+
+#[test]
+fn update_from_beta_channel() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, self_dist| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        output_release_file(self_dist, "1", version);
+        output_channel_release_file(self_dist, "beta", "1", TEST_VERSION);
+        expect_stdout_ok(
+            config,
+            &["rustup", "check"],
+            &format!("rustup - Up to date : {}", version),
+        );
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "rustup-channel", "beta"],
+            "info: rustup will update itself from the beta channel",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "check"],
+            &format!("rustup - Update available : {} -> 1.1.1", version),
+        );
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_ok(config, &["rustup", "self", "update"]);
+        assert_ne!(before_hash, calc_hash(&bin));
+
+        expect_err(
+            config,
+            &["rustup", "set", "rustup-channel", "nightly"],
+            "'nightly' isn't a valid value",
+        );
+    });
+}
+
+#[test]
+fn rollback_restores_replaced_rustup() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_ok(config, &["rustup", "self", "update"]);
+        assert_ne!(before_hash, calc_hash(&bin));
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "rollback"],
+            &format!("info: rolling back rustup from {} to {}", version, version),
+        );
+        assert_eq!(before_hash, calc_hash(&bin));
+        let cargo = config.cargodir.join(&format!("bin/cargo{}", EXE_SUFFIX));
+        assert_eq!(before_hash, calc_hash(&cargo));
+
+        expect_err(
+            config,
+            &["rustup", "self", "rollback"],
+            "no previous version of rustup to roll back to",
+        );
+    });
+}
+
+#[test]
+fn rollback_is_not_kept_forever() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "rustup-backup-runs", "2"],
+            "info: keeping the previous rustup for 2 runs after a self-update",
+        );
+        expect_ok(config, &["rustup", "self", "update"]);
+
+        let backup_dir = config.rustupdir.join("self-backup");
+        expect_ok(config, &["rustup", "show"]);
+        expect_ok(config, &["rustup", "show"]);
+        assert!(backup_dir.exists());
+        expect_ok(config, &["rustup", "show"]);
+        assert!(!backup_dir.exists());
+
+        expect_err(
+            config,
+            &["rustup", "self", "rollback"],
+            "no previous version of rustup to roll back to",
+        );
+    });
+}
+
+#[test]
+fn rollback_is_kept_through_proxy_and_failed_runs() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_ok(config, &["rustup", "set", "rustup-backup-runs", "1"]);
+        expect_ok(config, &["rustup", "self", "update"]);
+
+        let backup_dir = config.rustupdir.join("self-backup");
+        expect_ok(config, &["rustc", "--version"]);
+        expect_ok(config, &["cargo", "--version"]);
+        expect_err(
+            config,
+            &["rustup", "set", "rustup-backup-runs", "many"],
+            "The number of runs must be a number",
+        );
+        expect_ok(config, &["rustup", "show"]);
+        assert!(backup_dir.exists());
+        expect_ok(config, &["rustup", "show"]);
+        assert!(!backup_dir.exists());
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn rollback_is_refused_where_self_update_is() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_ok(config, &["rustup", "self", "update"]);
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let updated_hash = calc_hash(&bin);
+        let out = run(config, "rustup", &["self", "rollback"], &[("SNAP", "1")]);
+        assert!(!out.ok);
+        assert!(out
+            .stderr
+            .contains("self-update is disabled for this build of rustup"));
+        assert_eq!(updated_hash, calc_hash(&bin));
+        assert!(config.rustupdir.join("self-backup").exists());
+    });
+}
+
+#[test]
+fn auto_self_update_disabled() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "auto-self-update", "disable"],
+            "info: auto-self-update set to 'disable'",
+        );
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_not_stderr_ok(
+            config,
+            &["rustup", "update"],
+            "info: checking for self-updates",
+        );
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        assert_eq!(before_hash, calc_hash(&bin));
+
+        // Asking for it outright still updates
+        expect_ok(config, &["rustup", "self", "update"]);
+        assert_ne!(before_hash, calc_hash(&bin));
+    });
+}
+
+#[test]
+fn auto_self_update_check_only() {
+    let version = env!("CARGO_PKG_VERSION");
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_ok(config, &["rustup", "set", "auto-self-update", "check-only"]);
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_stderr_ok(
+            config,
+            &["rustup", "update"],
+            &format!(
+                "info: rustup 1.1.1 is available (this is {}); \
+                 run `rustup self update` to install it",
+                version
+            ),
+        );
+        assert_eq!(before_hash, calc_hash(&bin));
+
+        expect_err(
+            config,
+            &["rustup", "set", "auto-self-update", "sometimes"],
+            "'sometimes' isn't a valid value",
+        );
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn auto_self_update_locked_by_fallback_settings() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        let fallback_settings = config.current_dir().join("fallback_settings.toml");
+        raw::write_file(&fallback_settings, "auto_self_update = 'disable'").unwrap();
+        let env = [(
+            "RUSTUP_OVERRIDE_UNIX_FALLBACK_SETTINGS",
+            fallback_settings.to_str().unwrap(),
+        )];
+
+        let out = run(
+            config,
+            "rustup",
+            &["set", "auto-self-update", "enable"],
+            &env,
+        );
+        assert!(!out.ok);
+        assert!(out.stderr.contains(
+            "auto-self-update is set to 'disable' by the system administrator, and can't be changed"
+        ));
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        let out = run(config, "rustup", &["update"], &env);
+        assert!(out.ok);
+        assert!(!out.stderr.contains("info: checking for self-updates"));
+        assert_eq!(before_hash, calc_hash(&bin));
+    });
+}
+
 #[test]
 fn rustup_self_updates_trivial() {
     update_setup(&|config, _| {
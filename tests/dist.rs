@@ -10,6 +10,7 @@ use std::fs;
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use url::Url;
 
@@ -17,17 +18,22 @@ use rustup::currentprocess;
 use rustup::dist::dist::{Profile, TargetTriple, ToolchainDesc, DEFAULT_DIST_SERVER};
 use rustup::dist::download::DownloadCfg;
 use rustup::dist::manifest::{Component, Manifest};
-use rustup::dist::manifestation::{Changes, Manifestation, UpdateStatus};
+use rustup::dist::manifest_cache::ManifestCache;
+use rustup::dist::manifestation::{
+    recover_interrupted_update, Changes, Manifestation, UpdateStatus,
+};
 use rustup::dist::prefix::InstallPrefix;
 use rustup::dist::temp;
 use rustup::dist::Notification;
 use rustup::errors::Result;
 use rustup::utils::raw as utils_raw;
+use rustup::utils::retry_policy::RetryPolicy;
 use rustup::utils::utils;
 use rustup::ErrorKind;
 use rustup::PgpPublicKey;
 
 use crate::mock::dist::*;
+use crate::mock::fault_server::{Fault, FaultServer};
 use crate::mock::{MockComponentBuilder, MockFile, MockInstallerBuilder};
 
 const SHA256_HASH_LEN: usize = 64;
@@ -459,6 +465,7 @@ fn update_from_dist(
     let changes = Changes {
         explicit_add_components: add_components,
         remove_components: remove.to_owned(),
+        ..Changes::none()
     };
 
     manifestation.update(
@@ -469,6 +476,7 @@ fn update_from_dist(
         download_cfg.notify_handler,
         &toolchain.manifest_name(),
         true,
+        None,
     )
 }
 
@@ -507,13 +515,35 @@ fn setup(
     setup_from_dist_server(mock_dist_server, &url, enable_xz, f);
 }
 
+// Sets up as `setup` does, but with the dist server served over HTTP by a
+// `FaultServer`, which `f` is given to script failures with.
+fn setup_over_http(
+    f: &dyn Fn(&FaultServer, &Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
+) {
+    let dist_tempdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let mock_dist_server = create_mock_dist_server(dist_tempdir.path(), None);
+    let server = FaultServer::serve(dist_tempdir.path());
+    setup_from_dist_server(
+        mock_dist_server,
+        &server.url(),
+        false,
+        &|url, toolchain, prefix, download_cfg, temp_cfg| {
+            f(&server, url, toolchain, prefix, download_cfg, temp_cfg)
+        },
+    );
+}
+
 fn setup_from_dist_server(
     server: MockDistServer,
     url: &Url,
     enable_xz: bool,
     f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
 ) {
-    server.write(&[ManifestVersion::V2], enable_xz);
+    if url.scheme() == "file" {
+        server.write(&[ManifestVersion::V2], enable_xz);
+    } else {
+        server.write_served_at(&[ManifestVersion::V2], enable_xz, url);
+    }
 
     let prefix_tempdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
 
@@ -537,6 +567,17 @@ fn setup_from_dist_server(
             "test-key".into(),
             get_public_key(),
         )],
+        retry_policy: RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(0),
+        },
+        concurrent_downloads: 4,
+        stream_packages: false,
+        hosts: &download::HostConfigs::new(),
+        throttle: None,
+        timeouts: download::Timeouts::default(),
+        proxy: None,
+        manifest_cache: None,
     };
 
     currentprocess::with(
@@ -693,6 +734,92 @@ fn upgrade() {
     });
 }
 
+// Updates are built beside the toolchain and swapped in as a whole.
+#[test]
+#[cfg(unix)]
+fn upgrade_swaps_in_staged_toolchain() {
+    use std::os::unix::fs::MetadataExt;
+
+    setup(None, false, &|url,
+                         toolchain,
+                         prefix,
+                         download_cfg,
+                         temp_cfg| {
+        change_channel_date(url, "nightly", "2016-02-01");
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+        let old_ino = fs::metadata(prefix.path()).unwrap().ino();
+
+        change_channel_date(url, "nightly", "2016-02-02");
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert_ne!(fs::metadata(prefix.path()).unwrap().ino(), old_ino);
+        assert_eq!(
+            "2016-02-02",
+            fs::read_to_string(&prefix.path().join("bin/rustc")).unwrap()
+        );
+        let name = prefix.path().file_name().unwrap().to_str().unwrap();
+        let staging = prefix.path().with_file_name(format!(".{}.staging", name));
+        assert!(!utils::path_exists(&staging));
+    });
+}
+
+// An update swapped in by renames that was interrupted once the old version
+// was moved aside is undone, and one interrupted after the new version was
+// moved in is finished off.
+#[test]
+fn interrupted_swap_is_recovered() {
+    setup(None, false, &|url,
+                         toolchain,
+                         prefix,
+                         download_cfg,
+                         temp_cfg| {
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+        let name = prefix.path().file_name().unwrap().to_str().unwrap();
+        let replaced = prefix.path().with_file_name(format!(".{}.replaced", name));
+        let notify = &|_: Notification<'_>| ();
+
+        fs::rename(prefix.path(), &replaced).unwrap();
+        recover_interrupted_update(prefix, notify).unwrap();
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert!(!utils::path_exists(&replaced));
+
+        fs::create_dir(&replaced).unwrap();
+        recover_interrupted_update(prefix, notify).unwrap();
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert!(!utils::path_exists(&replaced));
+    });
+}
+
 #[test]
 fn unavailable_component() {
     // On day 2 the bonus component is no longer available
@@ -2043,6 +2170,381 @@ fn unable_to_download_component() {
     });
 }
 
+const RUSTC_TARBALL: &str = "dist/2016-02-02/rustc-nightly-x86_64-apple-darwin.tar.gz";
+
+#[test]
+fn server_errors_are_retried() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Status(500), Fault::Status(503)]);
+
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert_eq!(server.requests(RUSTC_TARBALL).len(), 3);
+    });
+}
+
+#[test]
+fn dropped_connections_are_retried() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Drop]);
+
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert_eq!(server.requests(RUSTC_TARBALL).len(), 2);
+    });
+}
+
+#[test]
+fn missing_components_are_not_retried() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Status(404)]);
+
+        let err = update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap_err();
+
+        match *err.kind() {
+            ErrorKind::ComponentDownloadFailed(..) => (),
+            _ => panic!(),
+        }
+        assert_eq!(server.requests(RUSTC_TARBALL).len(), 1);
+    });
+}
+
+#[test]
+fn slow_starts_are_waited_for() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Delay(Duration::from_millis(500))]);
+
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert_eq!(server.requests(RUSTC_TARBALL).len(), 1);
+    });
+}
+
+#[test]
+fn packages_are_installed_while_later_ones_download() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        // The rust-std packages are installed after rustc and cargo
+        let delay = Duration::from_secs(1);
+        for target in &[
+            "x86_64-apple-darwin",
+            "i686-apple-darwin",
+            "i686-unknown-linux-gnu",
+        ] {
+            let tarball = format!("dist/2016-02-02/rust-std-nightly-{}.tar.gz", target);
+            server.fail(&tarball, &[Fault::Delay(delay)]);
+        }
+
+        let rustc_installed = Cell::new(None);
+        let timings = Cell::new(None);
+        let download_cfg = DownloadCfg {
+            notify_handler: &|n| match n {
+                Notification::InstallingComponent("rustc", _, _) => {
+                    rustc_installed.set(Some(Instant::now()))
+                }
+                Notification::UpdateTimings(downloading, installing, total) => {
+                    timings.set(Some((downloading, installing, total)))
+                }
+                _ => (),
+            },
+            ..*download_cfg
+        };
+        let start = Instant::now();
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            &download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+        // rustc was installed while rust-std was still being waited for
+        let rustc_installed = rustc_installed.get().unwrap();
+        assert!(rustc_installed.duration_since(start) < delay);
+        let (downloading, installing, total) = timings.get().unwrap();
+        assert!(downloading >= delay);
+        assert!(total < downloading + installing);
+    });
+}
+
+#[test]
+fn truncated_downloads_are_resumed() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Truncate(10)]);
+
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        let ranges: Vec<_> = server
+            .requests(RUSTC_TARBALL)
+            .into_iter()
+            .map(|request| request.range_start)
+            .collect();
+        assert_eq!(ranges, [None, Some(10)]);
+    });
+}
+
+#[test]
+fn short_content_length_fails_checksum_and_is_resumed() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::ContentLength(10)]);
+
+        let err = update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap_err();
+        match *err.kind() {
+            ErrorKind::ComponentDownloadFailed(..) => (),
+            _ => panic!(),
+        }
+        assert!(!utils::path_exists(&prefix.path().join("bin/rustc")));
+
+        // What was received is of the right file, so is resumed from
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        let ranges: Vec<_> = server
+            .requests(RUSTC_TARBALL)
+            .into_iter()
+            .map(|request| request.range_start)
+            .collect();
+        assert_eq!(ranges, [None, Some(10)]);
+    });
+}
+
+#[test]
+fn corrupt_partial_downloads_are_restarted() {
+    setup_over_http(&|server, url, toolchain, prefix, download_cfg, temp_cfg| {
+        server.fail(RUSTC_TARBALL, &[Fault::Replace(b"garbage".to_vec())]);
+
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap_err();
+
+        // Resuming from the garbage fails the checksum, so the download
+        // starts again
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        let ranges: Vec<_> = server
+            .requests(RUSTC_TARBALL)
+            .into_iter()
+            .map(|request| request.range_start)
+            .collect();
+        assert_eq!(ranges, [None, Some(7), None]);
+    });
+}
+
+#[test]
+fn manifest_downloads_are_retried() {
+    setup_over_http(&|server, url, _, _, download_cfg, _| {
+        let manifest = "dist/channel-rust-nightly.toml";
+        let hash = "dist/channel-rust-nightly.toml.sha256";
+        server.fail(manifest, &[Fault::Drop]);
+        server.fail(hash, &[Fault::Status(500)]);
+
+        let manifest_url = url.join(manifest).unwrap();
+        let (file, _) = download_cfg
+            .download_and_check(manifest_url.as_str(), None, ".toml")
+            .unwrap()
+            .unwrap();
+
+        Manifest::parse(&utils::read_file("manifest", &file).unwrap()).unwrap();
+        assert_eq!(server.requests(manifest).len(), 2);
+        assert_eq!(server.requests(hash).len(), 2);
+    });
+}
+
+// Sets up a `DownloadCfg` like `download_cfg` that caches manifests in a
+// temporary directory, and runs `f` with it.
+fn with_manifest_cache(
+    download_cfg: &DownloadCfg<'_>,
+    ttl: Duration,
+    f: &dyn Fn(&DownloadCfg<'_>, &Cell<usize>),
+) {
+    let cache_tempdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let cache = ManifestCache::new(cache_tempdir.path().join("manifests"), ttl);
+    let unchanged = Cell::new(0);
+    let download_cfg = DownloadCfg {
+        dist_root: download_cfg.dist_root,
+        temp_cfg: download_cfg.temp_cfg,
+        download_dir: download_cfg.download_dir,
+        notify_handler: &|n| {
+            if let Notification::ManifestUnchanged(_) = n {
+                unchanged.set(unchanged.get() + 1);
+            }
+        },
+        pgp_keys: &[PgpPublicKey::FromEnvironment(
+            "test-key".into(),
+            get_public_key(),
+        )],
+        retry_policy: download_cfg.retry_policy,
+        concurrent_downloads: download_cfg.concurrent_downloads,
+        stream_packages: download_cfg.stream_packages,
+        hosts: download_cfg.hosts,
+        throttle: download_cfg.throttle,
+        timeouts: download_cfg.timeouts,
+        proxy: download_cfg.proxy,
+        manifest_cache: Some(&cache),
+    };
+    f(&download_cfg, &unchanged);
+}
+
+#[test]
+fn unchanged_manifests_are_not_downloaded_again() {
+    setup_over_http(&|server, url, _, _, download_cfg, _| {
+        with_manifest_cache(
+            download_cfg,
+            Duration::from_secs(0),
+            &|download_cfg, unchanged| {
+                let manifest = "dist/channel-rust-nightly.toml";
+                let hash = "dist/channel-rust-nightly.toml.sha256";
+                let manifest_url = url.join(manifest).unwrap();
+
+                let (file, partial_hash) = download_cfg
+                    .download_and_check(manifest_url.as_str(), None, ".toml")
+                    .unwrap()
+                    .unwrap();
+                let contents = utils::read_file("manifest", &file).unwrap();
+                assert_eq!(unchanged.get(), 0);
+
+                let (file, cached_partial_hash) = download_cfg
+                    .download_and_check(manifest_url.as_str(), None, ".toml")
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(utils::read_file("manifest", &file).unwrap(), contents);
+                assert_eq!(cached_partial_hash, partial_hash);
+                assert_eq!(unchanged.get(), 1);
+
+                let requests = server.requests(manifest);
+                assert_eq!(requests.len(), 2);
+                assert_eq!(requests[0].if_none_match, None);
+                assert!(requests[1].if_none_match.is_some());
+                // The cache already has the manifest's hash
+                assert_eq!(server.requests(hash).len(), 1);
+            },
+        );
+    });
+}
+
+#[test]
+fn fresh_manifests_are_used_without_asking() {
+    setup_over_http(&|server, url, _, prefix, download_cfg, _| {
+        let ttl = Duration::from_secs(3600);
+        with_manifest_cache(download_cfg, ttl, &|download_cfg, unchanged| {
+            let manifest = "dist/channel-rust-nightly.toml";
+            let manifest_url = url.join(manifest).unwrap();
+            let update_hash = prefix.path().join("update-hash");
+
+            let (_, partial_hash) = download_cfg
+                .download_and_check(manifest_url.as_str(), Some(&update_hash), ".toml")
+                .unwrap()
+                .unwrap();
+            utils::write_file("update hash", &update_hash, &partial_hash).unwrap();
+
+            // Already installed, so there's nothing to do
+            assert!(download_cfg
+                .download_and_check(manifest_url.as_str(), Some(&update_hash), ".toml")
+                .unwrap()
+                .is_none());
+            assert_eq!(unchanged.get(), 1);
+            assert_eq!(server.requests(manifest).len(), 1);
+        });
+    });
+}
+
 fn prevent_installation(prefix: &InstallPrefix) {
     utils::ensure_dir_exists(
         "installation path",
@@ -2083,6 +2585,14 @@ fn reuse_downloaded_file() {
                 "test-key".into(),
                 get_public_key(),
             )],
+            retry_policy: download_cfg.retry_policy,
+            concurrent_downloads: download_cfg.concurrent_downloads,
+            stream_packages: download_cfg.stream_packages,
+            hosts: download_cfg.hosts,
+            throttle: download_cfg.throttle,
+            timeouts: download_cfg.timeouts,
+            proxy: download_cfg.proxy,
+            manifest_cache: download_cfg.manifest_cache,
         };
 
         update_from_dist(
@@ -2154,6 +2664,14 @@ fn checks_files_hashes_before_reuse() {
                 "test-key".into(),
                 get_public_key(),
             )],
+            retry_policy: download_cfg.retry_policy,
+            concurrent_downloads: download_cfg.concurrent_downloads,
+            stream_packages: download_cfg.stream_packages,
+            hosts: download_cfg.hosts,
+            throttle: download_cfg.throttle,
+            timeouts: download_cfg.timeouts,
+            proxy: download_cfg.proxy,
+            manifest_cache: download_cfg.manifest_cache,
         };
 
         update_from_dist(
@@ -1,4 +1,4 @@
-use rustup::dist::component::Transaction;
+use rustup::dist::component::{recover_interrupted_transaction, Transaction};
 use rustup::dist::dist::DEFAULT_DIST_SERVER;
 use rustup::dist::prefix::InstallPrefix;
 use rustup::dist::temp;
@@ -8,7 +8,7 @@ use rustup::utils::utils;
 use rustup::ErrorKind;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn add_file() {
@@ -771,6 +771,237 @@ fn rollback_failure_keeps_going() {
     assert!(!utils::path_exists(prefix.path().join("baz")));
 }
 
+// The files under `dir` with their contents, ignoring the transaction
+// journal.
+fn snapshot(dir: &Path) -> Vec<(PathBuf, String)> {
+    let mut files = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .map(|e| e.unwrap())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.file_name() != "rustup-transaction-journal")
+        .map(|e| {
+            let rel = e.path().strip_prefix(dir).unwrap().to_owned();
+            (rel, fs::read_to_string(e.path()).unwrap())
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// Runs the first `steps` operations of a transaction against a prefix
+// with some existing contents, then abandons it without committing or
+// rolling back, as if the process had been killed.
+fn interrupted_transaction(
+    prefix: &InstallPrefix,
+    srcdir: &Path,
+    tmpcfg: &temp::Cfg,
+    steps: usize,
+) {
+    let notify = |_: Notification<'_>| ();
+    let mut tx = Transaction::new(prefix.clone(), tmpcfg, &notify);
+
+    for step in 0..steps {
+        match step {
+            0 => tx
+                .copy_file("", PathBuf::from("bin/rustc"), &srcdir.join("rustc"))
+                .unwrap(),
+            1 => tx
+                .copy_dir("", PathBuf::from("share/doc"), &srcdir.join("doc"))
+                .unwrap(),
+            2 => {
+                tx.modify_file(PathBuf::from("lib/rustlib/components"))
+                    .unwrap();
+                utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "new")
+                    .unwrap();
+            }
+            3 => {
+                tx.modify_file(PathBuf::from("lib/rustlib/fresh")).unwrap();
+                utils_raw::write_file(&prefix.path().join("lib/rustlib/fresh"), "new").unwrap();
+            }
+            4 => tx
+                .write_file(
+                    "",
+                    PathBuf::from("lib/rustlib/manifest-c"),
+                    "new".to_string(),
+                )
+                .unwrap(),
+            5 => tx.remove_file("", PathBuf::from("bin/oldrustc")).unwrap(),
+            6 => tx.remove_dir("", PathBuf::from("olddoc")).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    std::mem::forget(tx);
+}
+
+fn setup_interrupted_prefix(prefix: &InstallPrefix, srcdir: &Path) {
+    utils_raw::write_file(&srcdir.join("rustc"), "rustc").unwrap();
+    fs::create_dir_all(srcdir.join("doc/html")).unwrap();
+    utils_raw::write_file(&srcdir.join("doc/html/index.html"), "doc").unwrap();
+
+    fs::create_dir_all(prefix.path().join("bin")).unwrap();
+    fs::create_dir_all(prefix.path().join("lib/rustlib")).unwrap();
+    fs::create_dir_all(prefix.path().join("olddoc/html")).unwrap();
+    utils_raw::write_file(&prefix.path().join("bin/oldrustc"), "old").unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "old").unwrap();
+    utils_raw::write_file(&prefix.path().join("olddoc/html/index.html"), "old").unwrap();
+}
+
+#[test]
+fn recover_interrupted_transaction_at_every_step() {
+    for steps in 0..=7 {
+        let srcdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let prefixdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let txdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+
+        let tmpcfg = temp::Cfg::new(
+            txdir.path().to_owned(),
+            DEFAULT_DIST_SERVER,
+            Box::new(|_| ()),
+        );
+        let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+        setup_interrupted_prefix(&prefix, srcdir.path());
+        let before = snapshot(prefix.path());
+
+        interrupted_transaction(&prefix, srcdir.path(), &tmpcfg, steps);
+
+        let notify = |_: Notification<'_>| ();
+        recover_interrupted_transaction(&prefix, &notify).unwrap();
+
+        assert_eq!(
+            snapshot(prefix.path()),
+            before,
+            "interrupted after {} steps",
+            steps
+        );
+        assert!(!utils::path_exists(
+            prefix.manifest_file("rustup-transaction-journal")
+        ));
+    }
+}
+
+#[test]
+fn recover_interrupted_transaction_ignores_torn_entry() {
+    let srcdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let prefixdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let txdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    setup_interrupted_prefix(&prefix, srcdir.path());
+    let before = snapshot(prefix.path());
+
+    interrupted_transaction(&prefix, srcdir.path(), &tmpcfg, 3);
+    let journal = prefix.manifest_file("rustup-transaction-journal");
+    let mut file = fs::OpenOptions::new().append(true).open(&journal).unwrap();
+    write!(file, "remove-fi").unwrap();
+    drop(file);
+
+    let notify = |_: Notification<'_>| ();
+    recover_interrupted_transaction(&prefix, &notify).unwrap();
+
+    assert_eq!(snapshot(prefix.path()), before);
+}
+
+#[test]
+fn recover_interrupted_transaction_without_backup_needs_repair() {
+    let srcdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let prefixdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let txdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    setup_interrupted_prefix(&prefix, srcdir.path());
+    interrupted_transaction(&prefix, srcdir.path(), &tmpcfg, 6);
+
+    // Lose the backups kept beside the journal.
+    utils::delete_dir_contents(&prefix.manifest_file("rustup-transaction-backups"));
+
+    let notify = |_: Notification<'_>| ();
+    for _ in 0..2 {
+        let err = recover_interrupted_transaction(&prefix, &notify).unwrap_err();
+        match err.kind() {
+            ErrorKind::ToolchainNeedsRepair(path) => assert_eq!(path, prefix.path()),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+// The backups belong to the transaction, so cleaning out rustup's temp
+// directory, as another rustup finishing an update does, leaves them be.
+#[test]
+fn recover_interrupted_transaction_after_temp_dir_is_cleaned() {
+    let srcdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let prefixdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let txdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    setup_interrupted_prefix(&prefix, srcdir.path());
+    let before = snapshot(prefix.path());
+    interrupted_transaction(&prefix, srcdir.path(), &tmpcfg, 7);
+
+    tmpcfg.clean();
+
+    let notify = |_: Notification<'_>| ();
+    recover_interrupted_transaction(&prefix, &notify).unwrap();
+
+    assert_eq!(snapshot(prefix.path()), before);
+    assert!(!utils::path_exists(
+        prefix.manifest_file("rustup-transaction-backups")
+    ));
+}
+
+// Tabs and newlines separate the journal's fields and entries, so paths
+// containing them must survive being written to it.
+#[test]
+#[cfg(unix)]
+fn recover_interrupted_transaction_with_awkward_paths() {
+    let prefixdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+    let txdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let names = ["tab\there", "new\nline", "back\\slash\\n"];
+    for name in &names {
+        utils_raw::write_file(&prefix.path().join(name), "old").unwrap();
+    }
+    let before = snapshot(prefix.path());
+
+    let notify = |_: Notification<'_>| ();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+    for name in &names {
+        tx.remove_file("c", PathBuf::from(name)).unwrap();
+        tx.add_file("c", PathBuf::from(name)).unwrap();
+    }
+    std::mem::forget(tx);
+
+    recover_interrupted_transaction(&prefix, &notify).unwrap();
+
+    assert_eq!(snapshot(prefix.path()), before);
+}
+
 // Test that when a transaction creates intermediate directories that
 // they are deleted during rollback.
 #[test]
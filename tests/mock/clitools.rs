@@ -24,6 +24,7 @@ use crate::mock::dist::{
     change_channel_date, ManifestVersion, MockChannel, MockComponent, MockDistServer, MockPackage,
     MockTargetedPackage,
 };
+use crate::mock::fault_server::FaultServer;
 use crate::mock::topical_doc_data;
 use crate::mock::{MockComponentBuilder, MockFile, MockInstallerBuilder};
 
@@ -149,7 +150,7 @@ pub fn setup(s: Scenario, f: &dyn Fn(&mut Config)) {
         workdir: RefCell::new(workdir),
     };
 
-    create_mock_dist_server(&config.distdir, s);
+    create_mock_dist_server(&config.distdir, s, None);
 
     let build_path = exe_dir.join(format!("rustup-init{}", EXE_SUFFIX));
 
@@ -189,6 +190,16 @@ pub fn setup(s: Scenario, f: &dyn Fn(&mut Config)) {
     assert!(!PathBuf::from("./bogus-cargo-home").exists());
 }
 
+/// Serves the test's dist server, as set up for `s`, over HTTP with a
+/// `FaultServer`, for failures to be scripted with. Its manifests give
+/// the packages' URLs as they are served from there, but rustup has to be
+/// pointed at it with `RUSTUP_DIST_SERVER`.
+pub fn serve_dist_server(config: &Config, s: Scenario) -> FaultServer {
+    let server = FaultServer::serve(&config.distdir);
+    create_mock_dist_server(&config.distdir, s, Some(&server.url()));
+    server
+}
+
 pub fn self_update_setup(f: &dyn Fn(&Config, &Path), version: &str) {
     setup(Scenario::SimpleV2, &|config| {
         // Create a mock self-update server
@@ -198,16 +209,8 @@ pub fn self_update_setup(f: &dyn Fn(&Config, &Path), version: &str) {
             .unwrap();
         let self_dist = self_dist_tmp.path();
 
-        let trip = this_host_triple();
-        let dist_dir = self_dist.join(&format!("archive/{}/{}", version, trip));
-        let dist_exe = dist_dir.join(&format!("rustup-init{}", EXE_SUFFIX));
-        let rustup_bin = config.exedir.join(&format!("rustup-init{}", EXE_SUFFIX));
-
-        fs::create_dir_all(dist_dir).unwrap();
+        output_archived_rustup(config, self_dist, version);
         output_release_file(self_dist, "1", version);
-        fs::copy(&rustup_bin, &dist_exe).unwrap();
-        // Modify the exe so it hashes different
-        raw::append_file(&dist_exe, "").unwrap();
 
         let root_url = format!("file://{}", self_dist.display());
         config.rustup_update_root = Some(root_url);
@@ -216,7 +219,24 @@ pub fn self_update_setup(f: &dyn Fn(&Config, &Path), version: &str) {
     });
 }
 
+/// Adds rustup-init to the mock self-update server's archive as `version`
+pub fn output_archived_rustup(config: &Config, self_dist: &Path, version: &str) {
+    let trip = this_host_triple();
+    let dist_dir = self_dist.join(&format!("archive/{}/{}", version, trip));
+    let dist_exe = dist_dir.join(&format!("rustup-init{}", EXE_SUFFIX));
+    let rustup_bin = config.exedir.join(&format!("rustup-init{}", EXE_SUFFIX));
+
+    fs::create_dir_all(dist_dir).unwrap();
+    fs::copy(&rustup_bin, &dist_exe).unwrap();
+    // Modify the exe so it hashes different
+    raw::append_file(&dist_exe, "").unwrap();
+}
+
 pub fn output_release_file(dist_dir: &Path, schema: &str, version: &str) {
+    output_channel_release_file(dist_dir, "stable", schema, version)
+}
+
+pub fn output_channel_release_file(dist_dir: &Path, channel: &str, schema: &str, version: &str) {
     let contents = format!(
         r#"
 schema-version = "{}"
@@ -224,7 +244,7 @@ version = "{}"
 "#,
         schema, version
     );
-    let file = dist_dir.join("release-stable.toml");
+    let file = dist_dir.join(format!("release-{}.toml", channel));
     utils::write_file("release", &file, &contents).unwrap();
 }
 
@@ -466,6 +486,11 @@ pub fn env<E: rustup_test::Env>(config: &Config, cmd: &mut E) {
     // These are used in some installation tests that unset RUSTUP_HOME/CARGO_HOME
     cmd.env("HOME", config.homedir.to_string_lossy().to_string());
     cmd.env("USERPROFILE", config.homedir.to_string_lossy().to_string());
+    // Shells keep their config here, so keep it in the test's HOME as well
+    cmd.env(
+        "XDG_CONFIG_HOME",
+        config.homedir.join(".config").to_string_lossy().to_string(),
+    );
 
     // Setting HOME will confuse the sudo check for rustup-init. Override it
     cmd.env("RUSTUP_INIT_SKIP_SUDO_CHECK", "yes");
@@ -805,7 +830,7 @@ impl Release {
 }
 
 // Creates a mock dist server populated with some test data
-fn create_mock_dist_server(path: &Path, s: Scenario) {
+fn create_mock_dist_server(path: &Path, s: Scenario, served_at: Option<&Url>) {
     let chans = match s {
         Scenario::Empty => vec![],
         Scenario::MissingComponent => vec![
@@ -882,11 +907,14 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
         | Scenario::MissingComponentMulti => vec![ManifestVersion::V2],
     };
 
-    MockDistServer {
+    let server = MockDistServer {
         path: path.to_owned(),
         channels: chans.iter().map(|c| c.mock()).collect(),
+    };
+    match served_at {
+        Some(url) => server.write_served_at(&vs, true, url),
+        None => server.write(&vs, true),
     }
-    .write(&vs, true);
 
     for chan in &chans {
         chan.link(path)
@@ -121,6 +121,17 @@ pub enum ManifestVersion {
 
 impl MockDistServer {
     pub fn write(&self, vs: &[ManifestVersion], enable_xz: bool) {
+        self.write_(vs, enable_xz, None)
+    }
+
+    /// Writes the server out as `write` does, but with its v2 manifests
+    /// giving the packages' URLs as they are served from `url`, rather
+    /// than as `file:` URLs.
+    pub fn write_served_at(&self, vs: &[ManifestVersion], enable_xz: bool, url: &Url) {
+        self.write_(vs, enable_xz, Some(url))
+    }
+
+    fn write_(&self, vs: &[ManifestVersion], enable_xz: bool, served_at: Option<&Url>) {
         fs::create_dir_all(&self.path).unwrap();
 
         for channel in self.channels.iter() {
@@ -132,7 +143,7 @@ impl MockDistServer {
             for v in vs {
                 match *v {
                     ManifestVersion::V1 => self.write_manifest_v1(&channel),
-                    ManifestVersion::V2 => self.write_manifest_v2(&channel, &hashes),
+                    ManifestVersion::V2 => self.write_manifest_v2(&channel, &hashes, served_at),
                 }
             }
         }
@@ -298,6 +309,7 @@ impl MockDistServer {
         &self,
         channel: &MockChannel,
         hashes: &HashMap<MockComponent, MockHashes>,
+        served_at: Option<&Url>,
     ) {
         let mut toml_manifest = toml::value::Table::new();
 
@@ -333,12 +345,20 @@ impl MockDistServer {
                 } else {
                     format!("{}-{}.tar.gz", package.name, channel.name)
                 };
-                let path = self
-                    .path
-                    .join("dist")
-                    .join(&channel.date)
-                    .join(package_file_name);
-                let url = format!("file://{}", path.to_string_lossy());
+                let url = match served_at {
+                    Some(url) => url
+                        .join(&format!("dist/{}/{}", channel.date, package_file_name))
+                        .unwrap()
+                        .to_string(),
+                    None => {
+                        let path = self
+                            .path
+                            .join("dist")
+                            .join(&channel.date)
+                            .join(package_file_name);
+                        format!("file://{}", path.to_string_lossy())
+                    }
+                };
                 toml_target.insert(String::from("url"), toml::Value::String(url.clone()));
 
                 let component = MockComponent {
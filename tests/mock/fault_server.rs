@@ -0,0 +1,254 @@
+//! An HTTP server for the files of a mock dist server, which fails
+//! requests in the ways it is told to, so that how downloads recover from
+//! failures can be tested without a network.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use url::Url;
+
+/// A way for the server to fail a request.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Respond with this status, and no body
+    Status(u16),
+    /// Send only this many bytes of the body, then close the connection
+    Truncate(usize),
+    /// Send the body, but claim in `Content-Length` that it is this long
+    ContentLength(u64),
+    /// Wait this long before responding as usual
+    Delay(Duration),
+    /// Close the connection without responding
+    Drop,
+    /// Send this in place of the file
+    Replace(Vec<u8>),
+}
+
+/// A request the server was sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub path: String,
+    /// Where the `Range` header asked for the body to start, if it did
+    pub range_start: Option<u64>,
+    /// The `If-None-Match` header, if there was one
+    pub if_none_match: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    faults: Vec<(String, VecDeque<Fault>)>,
+    requests: Vec<Request>,
+}
+
+pub struct FaultServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultServer {
+    /// Serves the files under `root`, until the test process exits.
+    pub fn serve(root: &Path) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let root = root.to_owned();
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let root = root.clone();
+                let state = server_state.clone();
+                // Clients giving up partway through is what some tests are after
+                thread::spawn(move || respond(stream, &root, &state));
+            }
+        });
+
+        Self { addr, state }
+    }
+
+    /// The URL the root is served at
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}", self.addr)).unwrap()
+    }
+
+    /// Fails requests for paths ending in `suffix` with `faults`, one per
+    /// request in turn, and then serves them as usual.
+    pub fn fail(&self, suffix: &str, faults: &[Fault]) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .faults
+            .push((suffix.to_owned(), faults.iter().cloned().collect()));
+    }
+
+    /// The requests for paths ending in `suffix` so far, in the order they
+    /// were made.
+    pub fn requests(&self, suffix: &str) -> Vec<Request> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|request| request.path.ends_with(suffix))
+            .cloned()
+            .collect()
+    }
+}
+
+fn respond(mut stream: TcpStream, root: &Path, state: &Mutex<State>) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("").to_owned();
+    let path = request_line.next().unwrap_or("/").to_owned();
+    let request_headers: Vec<_> = lines
+        .filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((&line[..colon], line[colon + 1..].trim()))
+        })
+        .collect();
+    let header = |name: &str| {
+        request_headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    };
+    // Only ranges of the form `bytes={start}-` are asked for
+    let range_start = header("range").and_then(|value| {
+        let start = value.trim_start_matches("bytes=").trim_end_matches('-');
+        start.parse().ok()
+    });
+    let if_none_match = header("if-none-match").map(ToOwned::to_owned);
+
+    let fault = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            path: path.clone(),
+            range_start,
+            if_none_match: if_none_match.clone(),
+        });
+        state
+            .faults
+            .iter_mut()
+            .find(|(suffix, faults)| path.ends_with(suffix.as_str()) && !faults.is_empty())
+            .and_then(|(_, faults)| faults.pop_front())
+    };
+
+    let mut headers = HashMap::new();
+    let (status, body, content_length) = match fault {
+        Some(Fault::Status(status)) => (status, Vec::new(), None),
+        Some(Fault::Drop) => return Ok(()),
+        Some(Fault::Replace(contents)) => (200, contents, None),
+        fault => {
+            if let Some(Fault::Delay(delay)) = fault {
+                thread::sleep(delay);
+            }
+            let (status, mut body) = file_response(
+                root,
+                &path,
+                range_start,
+                if_none_match.as_deref(),
+                &mut headers,
+            );
+            let len = body.len() as u64;
+            match fault {
+                Some(Fault::Truncate(sent)) => {
+                    body.truncate(sent);
+                    (status, body, Some(len))
+                }
+                Some(Fault::ContentLength(claimed)) => (status, body, Some(claimed)),
+                _ => (status, body, None),
+            }
+        }
+    };
+    let content_length = content_length.unwrap_or(body.len() as u64);
+    headers.insert("Content-Length", content_length.to_string());
+    send(&mut stream, status, &headers, &body, &method)
+}
+
+// The status and body of the response to a request for `path`, starting
+// at `range_start` if given, adding the headers it needs to `headers`. The
+// file's ETag is a hash of its contents, and a request `if_none_match` it
+// is answered with a 304.
+fn file_response(
+    root: &Path,
+    path: &str,
+    range_start: Option<u64>,
+    if_none_match: Option<&str>,
+    headers: &mut HashMap<&'static str, String>,
+) -> (u16, Vec<u8>) {
+    let file = root.join(path.trim_start_matches('/'));
+    let contents = match fs::read(&file) {
+        Ok(contents) => contents,
+        Err(_) => return (404, Vec::new()),
+    };
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    let etag = format!("\"{:x}\"", hasher.finish());
+    headers.insert("ETag", etag.clone());
+    if if_none_match == Some(etag.as_str()) {
+        return (304, Vec::new());
+    }
+    match range_start {
+        Some(start) if start as usize >= contents.len() => {
+            headers.insert("Content-Range", format!("bytes */{}", contents.len()));
+            (416, Vec::new())
+        }
+        Some(start) => {
+            headers.insert(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, contents.len() - 1, contents.len()),
+            );
+            (206, contents[start as usize..].to_vec())
+        }
+        None => (200, contents),
+    }
+}
+
+fn send(
+    stream: &mut TcpStream,
+    status: u16,
+    headers: &HashMap<&'static str, String>,
+    body: &[u8],
+    method: &str,
+) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes())?;
+    if method != "HEAD" {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        404 => "Not Found",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}